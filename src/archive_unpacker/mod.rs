@@ -1,4 +1,7 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 use cfg_if::cfg_if;
 cfg_if! {
@@ -10,49 +13,833 @@ cfg_if! {
     }
 }
 
-pub fn unpack_archive(archive_path: &Path, destination_path: &Path) -> compress_tools::Result<()> {
-    // compress_tools doesn't work with some rar archives, so unrar is used
-    let extension = archive_path.extension();
-    if extension.is_some() && extension.unwrap() == "rar" {
+mod vetting;
+mod volumes;
+
+use walkdir::WalkDir;
+
+use crate::common::FsEntry;
+pub use vetting::{ArchiveEntry, ArchiveLimits};
+pub use volumes::{is_complete_set, join_volumes, parse_volume_name, Volume, VolumeKind};
+
+pub const ALLOWED_EXTENSIONS: [&str; 10] = [
+    "7z", "rar", "zip", "tar", "tar.gz", "tgz", "tar.xz", "txz", "tar.zst", "tzst",
+];
+
+/// Allowed extension of archive name, tar.gz and alike count as one.
+pub fn archive_extension(file_name: &str) -> Option<&'static str> {
+    ALLOWED_EXTENSIONS
+        .iter()
+        .find(|extension| file_name.ends_with(&format!(".{}", extension)))
+        .copied()
+}
+
+/// Archive found inside unpacked archive, paths are relative to destination of unpacking.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NestedArchive {
+    pub archive: String,
+    /// Directory it was unpacked into, archive file itself is removed.
+    pub directory: String,
+}
+
+#[derive(Debug)]
+pub enum UnpackError {
+    /// Archive was rejected by vetting, reason is meant for uploader.
+    Unsafe(String),
+    Archive(String),
+}
+
+impl fmt::Display for UnpackError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnpackError::Unsafe(message) => write!(formatter, "Unsafe archive: {}", message),
+            UnpackError::Archive(message) => write!(formatter, "{}", message),
+        }
+    }
+}
+
+fn archive_error(error: compress_tools::Error) -> UnpackError {
+    UnpackError::Archive(error.to_string())
+}
+
+/// Format recognized by magic bytes, so misnamed archives are unpacked too. Contents of compressed
+/// formats aren't checked here, compressed file without tar inside unpacks to single file "data".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveFormat {
+    SevenZip,
+    Rar,
+    Zip,
+    Tar,
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+// tar has no magic at the start, "ustar" is at offset 257 of its first header
+const MAGIC_BYTES: [(ArchiveFormat, usize, &[u8]); 9] = [
+    (ArchiveFormat::SevenZip, 0, b"7z\xbc\xaf\x27\x1c"),
+    (ArchiveFormat::Rar, 0, b"Rar!\x1a\x07"),
+    (ArchiveFormat::Zip, 0, b"PK\x03\x04"),
+    (ArchiveFormat::Zip, 0, b"PK\x05\x06"),
+    (ArchiveFormat::Zip, 0, b"PK\x07\x08"),
+    (ArchiveFormat::Gzip, 0, b"\x1f\x8b"),
+    (ArchiveFormat::Xz, 0, b"\xfd7zXZ\x00"),
+    (ArchiveFormat::Zstd, 0, b"\x28\xb5\x2f\xfd"),
+    (ArchiveFormat::Tar, 257, b"ustar"),
+];
+
+pub fn detect_format(archive_path: &Path) -> Result<ArchiveFormat, UnpackError> {
+    let mut header = vec![];
+    std::fs::File::open(archive_path)
+        .and_then(|file| file.take(512).read_to_end(&mut header))
+        .map_err(|error| UnpackError::Archive(format!("{}: {}", archive_path.display(), error)))?;
+
+    MAGIC_BYTES
+        .iter()
+        .find(|(_, offset, magic)| header.get(*offset..offset + magic.len()) == Some(*magic))
+        .map(|(format, _, _)| *format)
+        .ok_or_else(|| UnpackError::Archive("Unrecognized archive format".to_string()))
+}
+
+// compress_tools doesn't work with some rar archives, so unrar is used
+fn is_rar(archive_path: &Path) -> Result<bool, UnpackError> {
+    Ok(detect_format(archive_path)? == ArchiveFormat::Rar)
+}
+
+/// Entries read from archive headers, nothing is extracted. Paths are relative to archive root,
+/// directories which are only implied by paths of files inside them are listed too.
+pub fn list_archive(archive_path: &Path) -> Result<Vec<FsEntry>, UnpackError> {
+    let entries: Vec<(String, bool)> = if is_rar(archive_path)? {
+        unpacker::rar_list_entries(archive_path).map(|entries| {
+            entries
+                .into_iter()
+                .map(|entry| (entry.path, entry.is_directory))
+                .collect()
+        })
+    } else {
+        unpacker::compress_tools_list_archive(archive_path).map(|paths| {
+            paths
+                .into_iter()
+                .map(|path| {
+                    let is_directory = path.ends_with('/') || path.ends_with('\\');
+                    (path, is_directory)
+                })
+                .collect()
+        })
+    }
+    .map_err(archive_error)?;
+
+    // path -> is_file, sorted so parents come before their contents. Directory entries aren't
+    // always marked as such, anything listed as directory or having contents stays one
+    let mut listed: BTreeMap<String, bool> = BTreeMap::new();
+    for (path, is_directory) in entries {
+        vetting::check_entry_path(&path).map_err(UnpackError::Unsafe)?;
+        let components = vetting::entry_components(&path);
+        for depth in 1..components.len() {
+            listed.insert(components[..depth].join("/"), false);
+        }
+        if !components.is_empty() {
+            let is_file = listed.entry(components.join("/")).or_insert(!is_directory);
+            *is_file = *is_file && !is_directory;
+        }
+    }
+    Ok(listed
+        .into_iter()
+        .map(|(path, is_file)| FsEntry {
+            path: path,
+            is_file: is_file,
+        })
+        .collect())
+}
+
+fn file_size(path: &Path) -> Result<u64, UnpackError> {
+    std::fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .map_err(|error| UnpackError::Archive(format!("{}: {}", path.display(), error)))
+}
+
+// lists and vets archive before extracting it, extracted files are checked again afterwards.
+// unpacked_before is size already unpacked from outer archives, only the rest of the limit is left
+// for this one. Returns unpacked size of this archive
+fn extract(
+    archive_path: &Path,
+    destination_path: &Path,
+    limits: &ArchiveLimits,
+    unpacked_before: u64,
+) -> Result<u64, UnpackError> {
+    let archive_size = file_size(archive_path)?;
+    let is_rar = is_rar(archive_path)?;
+    let size_limit = limits.max_unpacked_size().saturating_sub(unpacked_before);
+    let entries = if is_rar {
+        unpacker::rar_list_entries(archive_path)
+    } else {
+        unpacker::compress_tools_list_entries(archive_path, size_limit)
+    }
+    .map_err(archive_error)?;
+    vetting::vet_entries(&entries, archive_size, unpacked_before, limits)
+        .map_err(UnpackError::Unsafe)?;
+
+    if is_rar {
         unpacker::rar_unpack(archive_path, destination_path)
     } else {
         unpacker::compress_tools_unpack(archive_path, destination_path)
     }
+    .map_err(archive_error)?;
+    vetting::vet_extracted(destination_path, archive_size, unpacked_before, limits)
+        .map_err(UnpackError::Unsafe)
+}
+
+fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+// inner archive is unpacked next to itself into directory named after it, so archive with bare
+// car files still ends up in folder with the car ID
+fn nested_directory(archive_path: &Path, name: &str) -> PathBuf {
+    let directory = archive_path.with_file_name(name);
+    if !directory.exists() {
+        return directory;
+    }
+    let file_name = archive_path.file_name().unwrap().to_string_lossy();
+    let mut directory = archive_path.with_file_name(format!("{}_unpacked", file_name));
+    let mut counter = 2;
+    while directory.exists() {
+        directory = archive_path.with_file_name(format!("{}_unpacked_{}", file_name, counter));
+        counter += 1;
+    }
+    directory
+}
+
+// volumes of the same rar set, next to already unpacked first one. Returns their size
+fn remove_other_volumes(first_volume: &Path, volume: &Volume) -> std::io::Result<u64> {
+    let mut removed_size = 0;
+    for entry in std::fs::read_dir(first_volume.parent().unwrap())? {
+        let path = entry?.path();
+        let is_same_set = path
+            .file_name()
+            .and_then(|file_name| parse_volume_name(&file_name.to_string_lossy()))
+            .map_or(false, |other| {
+                other.kind == volume.kind && other.set_name == volume.set_name
+            });
+        if is_same_set && path.is_file() {
+            removed_size += std::fs::metadata(&path)?.len();
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(removed_size)
+}
+
+/// Archive unpacked together with archives inside it.
+#[derive(Debug)]
+pub struct UnpackedArchive {
+    pub nested: Vec<NestedArchive>,
+    /// Inner archives which couldn't be unpacked, they are left as they are.
+    pub warnings: Vec<String>,
+}
+
+struct Expansion<'a> {
+    root: &'a Path,
+    limits: &'a ArchiveLimits,
+    /// Everything unpacked under root so far.
+    unpacked_size: u64,
+    nested: Vec<NestedArchive>,
+    warnings: Vec<String>,
+}
+
+impl<'a> Expansion<'a> {
+    // only the directory unpacked last is walked, the rest was searched already
+    fn expand(&mut self, directory: &Path, depth: usize) -> Result<(), UnpackError> {
+        if depth > self.limits.max_nesting_depth {
+            return Ok(());
+        }
+        // path, name of directory it's unpacked into and rar volumes read together with it
+        let mut inner_archives: Vec<(PathBuf, String, Option<Volume>)> = vec![];
+        for entry in WalkDir::new(directory) {
+            let entry = entry.map_err(|error| UnpackError::Archive(error.to_string()))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            match parse_volume_name(&file_name) {
+                // unrar reads the rest of volumes when unpacking the first one
+                Some(volume) if volume.kind == VolumeKind::Rar && volume.number == 1 => {
+                    inner_archives.push((entry.into_path(), volume.set_name.clone(), Some(volume)))
+                }
+                Some(_) => {}
+                None => {
+                    if let Some(extension) = archive_extension(&file_name) {
+                        let name = file_name[..file_name.len() - extension.len() - 1].to_string();
+                        inner_archives.push((entry.into_path(), name, None));
+                    }
+                }
+            }
+        }
+
+        for (archive_path, name, volume) in inner_archives {
+            let archive = relative_path(self.root, &archive_path);
+            let destination_path = nested_directory(&archive_path, &name);
+            // limits apply to everything unpacked from the uploaded archive together
+            let extracted = std::fs::create_dir_all(&destination_path)
+                .map_err(|error| UnpackError::Archive(error.to_string()))
+                .and_then(|_| {
+                    extract(
+                        &archive_path,
+                        &destination_path,
+                        self.limits,
+                        self.unpacked_size,
+                    )
+                });
+            let unpacked_size = match extracted {
+                Ok(unpacked_size) => unpacked_size,
+                // inner archive may be just a download packed along, it stays like any other file
+                Err(error) => {
+                    let _ = std::fs::remove_dir_all(&destination_path);
+                    self.warnings.push(format!("{}: {}", archive, error));
+                    continue;
+                }
+            };
+
+            let in_archive =
+                |error: std::io::Error| UnpackError::Archive(format!("{}: {}", archive, error));
+            let mut removed_size = file_size(&archive_path)?;
+            std::fs::remove_file(&archive_path).map_err(in_archive)?;
+            if let Some(volume) = volume {
+                removed_size += remove_other_volumes(&archive_path, &volume).map_err(in_archive)?;
+            }
+            self.unpacked_size = self
+                .unpacked_size
+                .saturating_add(unpacked_size)
+                .saturating_sub(removed_size);
+
+            self.nested.push(NestedArchive {
+                archive: archive,
+                directory: relative_path(self.root, &destination_path),
+            });
+            self.expand(&destination_path, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists and vets archive before extracting it, extracted files are checked again afterwards.
+/// Archives inside it are unpacked the same way up to max_nesting_depth levels, inner archive
+/// failing that is only reported in warnings.
+pub fn unpack_archive(
+    archive_path: &Path,
+    destination_path: &Path,
+    limits: &ArchiveLimits,
+) -> Result<UnpackedArchive, UnpackError> {
+    let unpacked_size = extract(archive_path, destination_path, limits, 0)?;
+
+    let mut expansion = Expansion {
+        root: destination_path,
+        limits: limits,
+        unpacked_size: unpacked_size,
+        nested: vec![],
+        warnings: vec![],
+    };
+    expansion.expand(destination_path, 1)?;
+    Ok(UnpackedArchive {
+        nested: expansion.nested,
+        warnings: expansion.warnings,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use std::sync::Mutex;
-    lazy_static::lazy_static! {
-        static ref MOCK_STABILITY_MUTEX: Mutex<()> = Mutex::new(());
+    use tempdir::TempDir;
+
+    use crate::tests::mocks::unpack_archive_mock::MOCK_STABILITY_MUTEX;
+
+    const RAR_HEADER: &[u8] = b"Rar!\x1a\x07\x01\x00";
+    const ZIP_HEADER: &[u8] = b"PK\x03\x04\x14\x00";
+    const SEVEN_ZIP_HEADER: &[u8] = b"7z\xbc\xaf\x27\x1c\x00\x04";
+    const XZ_HEADER: &[u8] = b"\xfd7zXZ\x00\x00";
+
+    fn test_archive(temp_dir: &TempDir, file_name: &str, header: &[u8]) -> std::path::PathBuf {
+        let path = temp_dir.path().join(file_name);
+        std::fs::write(&path, header).unwrap();
+        path
+    }
+
+    // kept apart from test archives, which would be taken for nested ones otherwise
+    fn unpack_directory(temp_dir: &TempDir) -> std::path::PathBuf {
+        let path = temp_dir.path().join("unpack");
+        std::fs::create_dir(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_archive_extension() {
+        assert_eq!(Some("zip"), archive_extension("car.zip"));
+        assert_eq!(Some("tar.zst"), archive_extension("pack.v1.2.tar.zst"));
+        assert_eq!(Some("tgz"), archive_extension("pack.tgz"));
+        assert_eq!(None, archive_extension("pack.gz"));
+        assert_eq!(None, archive_extension("zip"));
+    }
+
+    #[test]
+    fn test_detect_format() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let mut tar_header = vec![0u8; 512];
+        tar_header[257..262].copy_from_slice(b"ustar");
+
+        for (header, format) in [
+            (SEVEN_ZIP_HEADER, ArchiveFormat::SevenZip),
+            (RAR_HEADER, ArchiveFormat::Rar),
+            (ZIP_HEADER, ArchiveFormat::Zip),
+            (&tar_header[..], ArchiveFormat::Tar),
+            (b"\x1f\x8b\x08\x00", ArchiveFormat::Gzip),
+            (XZ_HEADER, ArchiveFormat::Xz),
+            (b"\x28\xb5\x2f\xfd\x04", ArchiveFormat::Zstd),
+        ] {
+            let path = test_archive(&temp_dir, "archive", header);
+            assert_eq!(format, detect_format(&path).unwrap());
+        }
+
+        let path = test_archive(&temp_dir, "archive.zip", b"not an archive");
+        assert_eq!(
+            "Unrecognized archive format",
+            detect_format(&path).unwrap_err().to_string()
+        );
     }
 
     #[test]
     fn test_unpack_archive_rar() {
         let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+
+        let list_context = unpacker::rar_list_entries_context();
+        list_context.expect().returning(|_| Ok(vec![]));
+        let context = unpacker::rar_unpack_context();
+        context.expect().returning(|_, _| Ok(()));
+        assert!(unpack_archive(
+            &test_archive(&temp_dir, "archive.rar", RAR_HEADER),
+            &unpack_directory(&temp_dir),
+            &ArchiveLimits::default()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_unpack_archive_misnamed_rar() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
 
+        let list_context = unpacker::rar_list_entries_context();
+        list_context.expect().returning(|_| Ok(vec![]));
         let context = unpacker::rar_unpack_context();
         context.expect().returning(|_, _| Ok(()));
-        assert!(unpack_archive(Path::new("/archive.rar"), Path::new("/unpack")).is_ok());
+        assert!(unpack_archive(
+            &test_archive(&temp_dir, "archive.zip", RAR_HEADER),
+            &unpack_directory(&temp_dir),
+            &ArchiveLimits::default()
+        )
+        .is_ok());
     }
 
     #[test]
     fn test_unpack_archive_no_extension() {
         let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
 
+        let list_context = unpacker::compress_tools_list_entries_context();
+        list_context.expect().returning(|_, _| Ok(vec![]));
         let context = unpacker::compress_tools_unpack_context();
         context.expect().returning(|_, _| Ok(()));
-        assert!(unpack_archive(Path::new("/archive"), Path::new("/unpack")).is_ok());
+        assert!(unpack_archive(
+            &test_archive(&temp_dir, "archive", ZIP_HEADER),
+            &unpack_directory(&temp_dir),
+            &ArchiveLimits::default()
+        )
+        .is_ok());
     }
 
     #[test]
     fn test_unpack_archive_7z() {
         let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
 
+        let list_context = unpacker::compress_tools_list_entries_context();
+        list_context.expect().returning(|_, _| Ok(vec![]));
         let context = unpacker::compress_tools_unpack_context();
         context.expect().returning(|_, _| Ok(()));
-        assert!(unpack_archive(Path::new("/archive.7z"), Path::new("/unpack")).is_ok());
+        assert!(unpack_archive(
+            &test_archive(&temp_dir, "archive.7z", SEVEN_ZIP_HEADER),
+            &unpack_directory(&temp_dir),
+            &ArchiveLimits::default()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_unpack_archive_rejects_symlink_listed_as_file() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let destination_path = unpack_directory(&temp_dir);
+
+        // compress_tools lists symlink like any other file
+        let list_context = unpacker::compress_tools_list_entries_context();
+        list_context.expect().returning(|_, _| {
+            Ok(vec![ArchiveEntry {
+                path: "pack/link".to_string(),
+                size: 0,
+                is_directory: false,
+                is_symlink: false,
+            }])
+        });
+        let context = unpacker::compress_tools_unpack_context();
+        context
+            .expect()
+            .returning(|_: &Path, destination_path: &Path| {
+                std::fs::create_dir(destination_path.join("pack")).unwrap();
+                std::os::unix::fs::symlink("../../outside", destination_path.join("pack/link"))
+                    .unwrap();
+                Ok(())
+            });
+
+        let result = unpack_archive(
+            &test_archive(&temp_dir, "pack.zip", ZIP_HEADER),
+            &destination_path,
+            &ArchiveLimits::default(),
+        );
+        assert_eq!(
+            "Unsafe archive: Symlink leaving archive root: pack/link -> ../../outside",
+            result.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_list_archive_adds_implied_directories() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+
+        let context = unpacker::compress_tools_list_archive_context();
+        context.expect().returning(|_| {
+            Ok(vec![
+                "pack/content/cars/car/data.acd".to_string(),
+                "pack/content/tracks/".to_string(),
+                "pack\\readme.txt".to_string(),
+                "pack\\skins\\".to_string(),
+                // directory entries without trailing separator, before and after their contents
+                "pack/content/cars/car".to_string(),
+                "pack/content/cars/car/ui".to_string(),
+                "pack/content/cars/car/ui/ui_car.json".to_string(),
+            ])
+        });
+
+        let entry = |path: &str, is_file: bool| FsEntry {
+            path: path.to_string(),
+            is_file: is_file,
+        };
+        assert_eq!(
+            vec![
+                entry("pack", false),
+                entry("pack/content", false),
+                entry("pack/content/cars", false),
+                entry("pack/content/cars/car", false),
+                entry("pack/content/cars/car/data.acd", true),
+                entry("pack/content/cars/car/ui", false),
+                entry("pack/content/cars/car/ui/ui_car.json", true),
+                entry("pack/content/tracks", false),
+                entry("pack/readme.txt", true),
+                entry("pack/skins", false),
+            ],
+            list_archive(&test_archive(&temp_dir, "archive.tar.xz", XZ_HEADER)).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_list_archive_rar() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+
+        let context = unpacker::rar_list_entries_context();
+        context.expect().returning(|_| {
+            Ok(vec![ArchiveEntry {
+                path: "/etc/passwd".to_string(),
+                size: 10,
+                is_directory: false,
+                is_symlink: false,
+            }])
+        });
+        assert!(matches!(
+            list_archive(&test_archive(&temp_dir, "archive.rar", RAR_HEADER)),
+            Err(UnpackError::Unsafe(_))
+        ));
+    }
+
+    #[test]
+    fn test_unpack_archive_rejected_before_extraction() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+
+        let list_context = unpacker::compress_tools_list_entries_context();
+        list_context.expect().returning(|_, _| {
+            Ok(vec![ArchiveEntry {
+                path: "../../evil.dll".to_string(),
+                size: 10,
+                is_directory: false,
+                is_symlink: false,
+            }])
+        });
+        let context = unpacker::compress_tools_unpack_context();
+        context.expect().never();
+
+        let result = unpack_archive(
+            &test_archive(&temp_dir, "archive.zip", ZIP_HEADER),
+            &unpack_directory(&temp_dir),
+            &ArchiveLimits::default(),
+        );
+        assert_eq!(
+            "Unsafe archive: Path leaving archive root: ../../evil.dll",
+            result.unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_unpack_archive_expands_nested_archives() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let destination_path = unpack_directory(&temp_dir);
+
+        let list_context = unpacker::compress_tools_list_entries_context();
+        list_context.expect().returning(|_, _| Ok(vec![]));
+        let context = unpacker::compress_tools_unpack_context();
+        context
+            .expect()
+            .returning(|archive_path: &Path, destination_path: &Path| {
+                if archive_path.ends_with("pack.zip") {
+                    std::fs::create_dir_all(destination_path.join("pack")).unwrap();
+                    std::fs::write(destination_path.join("pack/readme.txt"), "").unwrap();
+                    std::fs::write(destination_path.join("pack/abc_gt3.7z"), SEVEN_ZIP_HEADER)
+                        .unwrap();
+                } else {
+                    std::fs::write(destination_path.join("abc_gt3.kn5"), "").unwrap();
+                }
+                Ok(())
+            });
+
+        let unpacked = unpack_archive(
+            &test_archive(&temp_dir, "pack.zip", ZIP_HEADER),
+            &destination_path,
+            &ArchiveLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![NestedArchive {
+                archive: "pack/abc_gt3.7z".to_string(),
+                directory: "pack/abc_gt3".to_string(),
+            }],
+            unpacked.nested
+        );
+        assert!(destination_path.join("pack/abc_gt3/abc_gt3.kn5").is_file());
+        assert!(!destination_path.join("pack/abc_gt3.7z").exists());
+    }
+
+    #[test]
+    fn test_unpack_archive_keeps_broken_inner_archive() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let destination_path = unpack_directory(&temp_dir);
+
+        let list_context = unpacker::compress_tools_list_entries_context();
+        list_context.expect().returning(|_, _| Ok(vec![]));
+        let context = unpacker::compress_tools_unpack_context();
+        context
+            .expect()
+            .returning(|archive_path: &Path, destination_path: &Path| {
+                if archive_path.ends_with("pack.zip") {
+                    std::fs::write(destination_path.join("broken.zip"), ZIP_HEADER).unwrap();
+                    std::fs::write(destination_path.join("car.zip"), ZIP_HEADER).unwrap();
+                    return Ok(());
+                }
+                std::fs::write(destination_path.join("car.kn5"), "").unwrap();
+                if archive_path.ends_with("broken.zip") {
+                    return Err(compress_tools::Error::from("Truncated archive".to_string()));
+                }
+                Ok(())
+            });
+
+        let unpacked = unpack_archive(
+            &test_archive(&temp_dir, "pack.zip", ZIP_HEADER),
+            &destination_path,
+            &ArchiveLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(1, unpacked.warnings.len());
+        assert!(unpacked.warnings[0].starts_with("broken.zip: "));
+        assert_eq!(
+            vec![NestedArchive {
+                archive: "car.zip".to_string(),
+                directory: "car".to_string(),
+            }],
+            unpacked.nested
+        );
+        assert!(destination_path.join("broken.zip").is_file());
+        assert!(!destination_path.join("broken").exists());
+        assert!(destination_path.join("car/car.kn5").is_file());
+    }
+
+    #[test]
+    fn test_unpack_archive_nesting_depth() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let destination_path = unpack_directory(&temp_dir);
+
+        let list_context = unpacker::compress_tools_list_entries_context();
+        list_context.expect().returning(|_, _| Ok(vec![]));
+        let context = unpacker::compress_tools_unpack_context();
+        context
+            .expect()
+            .returning(|_: &Path, destination_path: &Path| {
+                std::fs::write(destination_path.join("inner.zip"), ZIP_HEADER).unwrap();
+                Ok(())
+            });
+
+        let limits = ArchiveLimits {
+            max_nesting_depth: 2,
+            ..ArchiveLimits::default()
+        };
+        let unpacked = unpack_archive(
+            &test_archive(&temp_dir, "outer.zip", ZIP_HEADER),
+            &destination_path,
+            &limits,
+        )
+        .unwrap();
+        assert_eq!(2, unpacked.nested.len());
+        assert_eq!("inner/inner", unpacked.nested[1].directory);
+        assert!(destination_path.join("inner/inner/inner.zip").is_file());
+    }
+
+    #[test]
+    fn test_unpack_archive_reads_rar_volumes_once() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let destination_path = unpack_directory(&temp_dir);
+        let mut tar_header = vec![0u8; 257];
+        tar_header.extend_from_slice(b"ustar");
+
+        let list_context = unpacker::compress_tools_list_entries_context();
+        list_context.expect().returning(|_, _| Ok(vec![]));
+        let context = unpacker::compress_tools_unpack_context();
+        context
+            .expect()
+            .returning(|_: &Path, destination_path: &Path| {
+                for volume in ["pack.part1.rar", "pack.part2.rar", "pack.part3.rar"] {
+                    std::fs::write(destination_path.join(volume), RAR_HEADER).unwrap();
+                }
+                Ok(())
+            });
+        let rar_list_context = unpacker::rar_list_entries_context();
+        rar_list_context.expect().returning(|_| Ok(vec![]));
+        let rar_context = unpacker::rar_unpack_context();
+        rar_context
+            .expect()
+            .times(1)
+            .returning(|archive_path: &Path, destination_path: &Path| {
+                assert!(archive_path.ends_with("pack.part1.rar"));
+                std::fs::write(destination_path.join("track.kn5"), "").unwrap();
+                Ok(())
+            });
+
+        let unpacked = unpack_archive(
+            &test_archive(&temp_dir, "pack.tar", &tar_header),
+            &destination_path,
+            &ArchiveLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![NestedArchive {
+                archive: "pack.part1.rar".to_string(),
+                directory: "pack".to_string(),
+            }],
+            unpacked.nested
+        );
+        assert!(destination_path.join("pack/track.kn5").is_file());
+        assert_eq!(1, std::fs::read_dir(&destination_path).unwrap().count());
+    }
+
+    #[test]
+    fn test_unpack_archive_nested_size_budget() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let destination_path = unpack_directory(&temp_dir);
+        let size = 600 * 1024;
+
+        let list_context = unpacker::compress_tools_list_entries_context();
+        list_context
+            .expect()
+            .returning(move |archive_path: &Path, size_limit| {
+                if !archive_path.ends_with("inner.zip") {
+                    return Ok(vec![]);
+                }
+                // the rest of 1 MB after outer archive is all what inner one gets
+                assert!(size_limit < 1024 * 1024 - size);
+                Ok(vec![ArchiveEntry {
+                    path: "car.kn5".to_string(),
+                    size: size,
+                    is_directory: false,
+                    is_symlink: false,
+                }])
+            });
+        let context = unpacker::compress_tools_unpack_context();
+        context
+            .expect()
+            .returning(move |archive_path: &Path, destination_path: &Path| {
+                if archive_path.ends_with("pack.zip") {
+                    std::fs::write(destination_path.join("big.bin"), vec![0u8; size as usize])
+                        .unwrap();
+                    std::fs::write(destination_path.join("inner.zip"), ZIP_HEADER).unwrap();
+                } else {
+                    std::fs::write(destination_path.join("car.kn5"), "").unwrap();
+                }
+                Ok(())
+            });
+
+        let limits = ArchiveLimits {
+            max_unpacked_size_mb: 1,
+            ..ArchiveLimits::default()
+        };
+        let unpacked = unpack_archive(
+            &test_archive(&temp_dir, "pack.zip", ZIP_HEADER),
+            &destination_path,
+            &limits,
+        )
+        .unwrap();
+        // inner archive over the limit is left as it is
+        assert_eq!(
+            vec!["inner.zip: Unsafe archive: Unpacked size exceeds 1 MB".to_string()],
+            unpacked.warnings
+        );
+        assert!(unpacked.nested.is_empty());
+        assert!(destination_path.join("inner.zip").is_file());
+        assert!(!destination_path.join("inner").exists());
+    }
+
+    #[test]
+    fn test_nested_directory_is_free() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let archive_path = test_archive(&temp_dir, "car.zip", ZIP_HEADER);
+
+        assert_eq!(
+            temp_dir.path().join("car"),
+            nested_directory(&archive_path, "car")
+        );
+        std::fs::create_dir(temp_dir.path().join("car")).unwrap();
+        assert_eq!(
+            temp_dir.path().join("car.zip_unpacked"),
+            nested_directory(&archive_path, "car")
+        );
+        std::fs::create_dir(temp_dir.path().join("car.zip_unpacked")).unwrap();
+        assert_eq!(
+            temp_dir.path().join("car.zip_unpacked_2"),
+            nested_directory(&archive_path, "car")
+        );
     }
 }
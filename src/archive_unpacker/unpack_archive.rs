@@ -1,6 +1,12 @@
 pub mod unpacker {
     use std::path::Path;
 
+    use crate::archive_unpacker::ArchiveEntry;
+
+    // st_mode file type bits, stored in attributes of archives created on Unix
+    const UNIX_FILE_TYPE_MASK: u32 = 0o170000;
+    const UNIX_SYMLINK: u32 = 0o120000;
+
     pub fn rar_unpack(archive_path: &Path, destination_path: &Path) -> compress_tools::Result<()> {
         let path_as_string = archive_path.to_str().unwrap().to_string();
         let archive = unrar::Archive::new(path_as_string);
@@ -16,6 +22,27 @@ pub mod unpacker {
         Ok(())
     }
 
+    /// Sizes are the ones from headers, unrar extracts whole archive at once, so what is actually
+    /// unpacked is only checked after extraction.
+    pub fn rar_list_entries(archive_path: &Path) -> compress_tools::Result<Vec<ArchiveEntry>> {
+        let path_as_string = archive_path.to_str().unwrap().to_string();
+        let mut open_archive = unrar::Archive::new(path_as_string)
+            .list()
+            .map_err(|error| compress_tools::Error::from(error.to_string()))?;
+        let entries = open_archive
+            .process()
+            .map_err(|error| compress_tools::Error::from(error.to_string()))?;
+        Ok(entries
+            .iter()
+            .map(|entry| ArchiveEntry {
+                path: entry.filename.clone(),
+                size: entry.unpacked_size as u64,
+                is_directory: entry.is_directory(),
+                is_symlink: entry.file_attr & UNIX_FILE_TYPE_MASK == UNIX_SYMLINK,
+            })
+            .collect())
+    }
+
     pub fn compress_tools_unpack(
         archive_path: &Path,
         destination_path: &Path,
@@ -27,4 +54,45 @@ pub mod unpacker {
             compress_tools::Ownership::Ignore,
         )
     }
+
+    pub fn compress_tools_list_archive(archive_path: &Path) -> compress_tools::Result<Vec<String>> {
+        let mut source = std::fs::File::open(archive_path)?;
+        compress_tools::list_archive_files(&mut source)
+    }
+
+    /// Sizes are counted by decompressing into memory, since zip headers can lie. Counting stops
+    /// after size_limit is exceeded. Entry types aren't exposed by compress_tools, so symlinks are
+    /// listed as regular files and only checked after extraction.
+    pub fn compress_tools_list_entries(
+        archive_path: &Path,
+        size_limit: u64,
+    ) -> compress_tools::Result<Vec<ArchiveEntry>> {
+        use compress_tools::{ArchiveContents, ArchiveIterator};
+
+        let source = std::fs::File::open(archive_path)?;
+        let mut entries: Vec<ArchiveEntry> = vec![];
+        let mut total_size = 0u64;
+        for content in ArchiveIterator::from_read(source)? {
+            match content {
+                ArchiveContents::StartOfEntry(path) => entries.push(ArchiveEntry {
+                    is_directory: path.ends_with('/') || path.ends_with('\\'),
+                    path: path,
+                    size: 0,
+                    is_symlink: false,
+                }),
+                ArchiveContents::DataChunk(chunk) => {
+                    total_size += chunk.len() as u64;
+                    if let Some(entry) = entries.last_mut() {
+                        entry.size += chunk.len() as u64;
+                    }
+                    if total_size > size_limit {
+                        break;
+                    }
+                }
+                ArchiveContents::EndOfEntry => {}
+                ArchiveContents::Err(error) => return Err(error),
+            }
+        }
+        Ok(entries)
+    }
 }
@@ -0,0 +1,296 @@
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+// small archives of text files compress very well, ratio only matters for large contents
+const RATIO_CHECK_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Entry as listed in archive headers, before anything is written to disk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub size: u64,
+    pub is_directory: bool,
+    pub is_symlink: bool,
+}
+
+/// Caps checked before and after extracting uploaded archive.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ArchiveLimits {
+    pub max_unpacked_size_mb: u64,
+    /// Unpacked size divided by archive size.
+    pub max_compression_ratio: u64,
+    pub max_entries: usize,
+    /// How deep archives inside archive are unpacked, 0 leaves them as they are.
+    pub max_nesting_depth: usize,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        ArchiveLimits {
+            max_unpacked_size_mb: 20 * 1024,
+            max_compression_ratio: 100,
+            max_entries: 50000,
+            max_nesting_depth: 2,
+        }
+    }
+}
+
+impl ArchiveLimits {
+    pub fn max_unpacked_size(&self) -> u64 {
+        self.max_unpacked_size_mb.saturating_mul(1024 * 1024)
+    }
+
+    fn check_total_size(&self, unpacked_size: u64) -> Result<(), String> {
+        if unpacked_size > self.max_unpacked_size() {
+            return Err(format!(
+                "Unpacked size exceeds {} MB",
+                self.max_unpacked_size_mb
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_size(&self, unpacked_size: u64, archive_size: u64) -> Result<(), String> {
+        self.check_total_size(unpacked_size)?;
+        if unpacked_size >= RATIO_CHECK_MIN_SIZE
+            && unpacked_size / archive_size.max(1) > self.max_compression_ratio
+        {
+            return Err(format!(
+                "Compression ratio exceeds {}:1",
+                self.max_compression_ratio
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_entry_count(&self, entry_count: usize) -> Result<(), String> {
+        if entry_count > self.max_entries {
+            return Err(format!(
+                "Archive has more than {} entries",
+                self.max_entries
+            ));
+        }
+        Ok(())
+    }
+}
+
+// entry names may come from Windows, so backslash separates components as well
+pub(super) fn entry_components(path: &str) -> Vec<&str> {
+    path.split(|character| character == '/' || character == '\\')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect()
+}
+
+pub(super) fn check_entry_path(path: &str) -> Result<(), String> {
+    let has_drive = path.len() >= 2 && path.as_bytes()[1] == b':';
+    if path.starts_with('/') || path.starts_with('\\') || has_drive {
+        return Err(format!("Absolute path in archive: {}", path));
+    }
+    if entry_components(path).contains(&"..") {
+        return Err(format!("Path leaving archive root: {}", path));
+    }
+    Ok(())
+}
+
+/// Checks archive listing, nothing is extracted when this fails. Size limit applies to
+/// unpacked_before, what outer archives already unpacked, and this archive together.
+pub fn vet_entries(
+    entries: &[ArchiveEntry],
+    archive_size: u64,
+    unpacked_before: u64,
+    limits: &ArchiveLimits,
+) -> Result<(), String> {
+    limits.check_entry_count(entries.len())?;
+    let unpacked_size = entries
+        .iter()
+        .fold(0u64, |size, entry| size.saturating_add(entry.size));
+    limits.check_size(unpacked_size, archive_size)?;
+    limits.check_total_size(unpacked_before.saturating_add(unpacked_size))?;
+
+    // file or link followed by entries inside it is how extraction is tricked into writing
+    // through a symlink, regular archives never have these
+    let mut non_directories: Vec<Vec<&str>> = vec![];
+    for entry in entries {
+        check_entry_path(&entry.path)?;
+        let components = entry_components(&entry.path);
+        let inside_file = non_directories
+            .iter()
+            .any(|file| components.len() > file.len() && components[..file.len()] == file[..]);
+        if inside_file {
+            return Err(format!("Entry inside file or symlink: {}", entry.path));
+        }
+        if !entry.is_directory || entry.is_symlink {
+            non_directories.push(components);
+        }
+    }
+    Ok(())
+}
+
+// resolves . and .. without touching filesystem, None when path goes above its root
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(normalized)
+}
+
+/// Checks extracted files, archive headers can lie about sizes and not every format lists symlinks.
+/// Size limit applies to unpacked_before and this archive together, like in vet_entries. Returns
+/// unpacked size of this archive.
+pub fn vet_extracted(
+    destination_path: &Path,
+    archive_size: u64,
+    unpacked_before: u64,
+    limits: &ArchiveLimits,
+) -> Result<u64, String> {
+    let mut entry_count = 0;
+    let mut unpacked_size = 0u64;
+    for entry in WalkDir::new(destination_path).min_depth(1) {
+        let entry = entry.map_err(|error| error.to_string())?;
+        entry_count += 1;
+        limits.check_entry_count(entry_count)?;
+
+        let relative_path = entry
+            .path()
+            .strip_prefix(destination_path)
+            .unwrap_or(entry.path());
+        if entry.path_is_symlink() {
+            let target = std::fs::read_link(entry.path()).map_err(|error| error.to_string())?;
+            let parent = relative_path.parent().unwrap_or(Path::new(""));
+            if normalize(&parent.join(&target)).is_none() {
+                return Err(format!(
+                    "Symlink leaving archive root: {} -> {}",
+                    relative_path.display(),
+                    target.display()
+                ));
+            }
+            continue;
+        }
+
+        let metadata = entry.metadata().map_err(|error| error.to_string())?;
+        if metadata.is_file() {
+            unpacked_size = unpacked_size.saturating_add(metadata.len());
+        }
+    }
+    limits.check_size(unpacked_size, archive_size)?;
+    limits.check_total_size(unpacked_before.saturating_add(unpacked_size))?;
+    Ok(unpacked_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    fn file(path: &str, size: u64) -> ArchiveEntry {
+        ArchiveEntry {
+            path: path.to_string(),
+            size: size,
+            is_directory: false,
+            is_symlink: false,
+        }
+    }
+
+    fn directory(path: &str) -> ArchiveEntry {
+        ArchiveEntry {
+            path: path.to_string(),
+            size: 0,
+            is_directory: true,
+            is_symlink: false,
+        }
+    }
+
+    #[test]
+    fn test_vet_entries_rejects_paths_leaving_root() {
+        let limits = ArchiveLimits::default();
+        let entries = vec![directory("content/"), file("content/cars/car/data.acd", 10)];
+        assert!(vet_entries(&entries, 10, 0, &limits).is_ok());
+
+        for path in [
+            "../evil.dll",
+            "content/../../evil.dll",
+            "content\\..\\..\\evil.dll",
+            "/etc/passwd",
+            "C:\\Windows\\evil.dll",
+        ] {
+            let entries = vec![file(path, 10)];
+            assert!(vet_entries(&entries, 10, 0, &limits).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_vet_entries_rejects_entries_inside_symlink() {
+        let limits = ArchiveLimits::default();
+        let mut link = file("content/cars", 0);
+        link.is_symlink = true;
+        let entries = vec![link, file("content/cars/car/data.acd", 10)];
+
+        assert_eq!(
+            Err("Entry inside file or symlink: content/cars/car/data.acd".to_string()),
+            vet_entries(&entries, 10, 0, &limits)
+        );
+    }
+
+    #[test]
+    fn test_vet_entries_limits() {
+        let limits = ArchiveLimits {
+            max_unpacked_size_mb: 1024,
+            max_compression_ratio: 100,
+            max_entries: 2,
+            ..ArchiveLimits::default()
+        };
+        let size = 512 * 1024 * 1024;
+
+        let entries = vec![file("a.kn5", size), file("b.kn5", size)];
+        assert!(vet_entries(&entries, size, 0, &limits).is_ok());
+        assert_eq!(
+            Err("Unpacked size exceeds 1024 MB".to_string()),
+            vet_entries(&entries, size, 1, &limits)
+        );
+        assert_eq!(
+            Err("Compression ratio exceeds 100:1".to_string()),
+            vet_entries(&entries, 1024, 0, &limits)
+        );
+
+        let entries = vec![file("a.kn5", size), file("b.kn5", size + 1)];
+        assert_eq!(
+            Err("Unpacked size exceeds 1024 MB".to_string()),
+            vet_entries(&entries, size, 0, &limits)
+        );
+
+        let entries = vec![file("a", 1), file("b", 1), file("c", 1)];
+        assert_eq!(
+            Err("Archive has more than 2 entries".to_string()),
+            vet_entries(&entries, 3, 0, &limits)
+        );
+    }
+
+    #[test]
+    fn test_vet_extracted_rejects_escaping_symlink() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let path = temp_dir.path();
+        std::fs::create_dir_all(path.join("content/cars/car")).unwrap();
+        std::os::unix::fs::symlink("../car", path.join("content/cars/car/link")).unwrap();
+        assert!(vet_extracted(path, 1, 0, &ArchiveLimits::default()).is_ok());
+
+        std::os::unix::fs::symlink("../../../..", path.join("content/cars/car/escape")).unwrap();
+        assert_eq!(
+            Err("Symlink leaving archive root: content/cars/car/escape -> ../../../..".to_string()),
+            vet_extracted(path, 1, 0, &ArchiveLimits::default())
+        );
+    }
+}
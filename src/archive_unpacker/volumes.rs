@@ -0,0 +1,315 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use tempdir::TempDir;
+
+use super::{unpacker, ArchiveLimits, UnpackError};
+
+const SEVEN_ZIP_SIGNATURE: &[u8] = b"7z\xbc\xaf\x27\x1c";
+// signature, version, start header CRC, next header offset, size and CRC
+const SEVEN_ZIP_START_HEADER_SIZE: u64 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VolumeKind {
+    /// pack.part1.rar, pack.part2.rar, every volume is rar archive of its own.
+    Rar,
+    /// pack.7z.001, pack.7z.002, 7z archive cut into pieces.
+    SevenZip,
+}
+
+/// Part of multi-volume archive, recognized by file name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Volume {
+    pub set_name: String,
+    pub kind: VolumeKind,
+    /// Volumes are numbered from 1.
+    pub number: u32,
+}
+
+impl Volume {
+    /// Split 7z joined back is a regular 7z, rar set is unpacked and its contents packed into tar.
+    pub fn joined_file_name(&self) -> String {
+        match self.kind {
+            VolumeKind::Rar => format!("{}.tar", self.set_name),
+            VolumeKind::SevenZip => format!("{}.7z", self.set_name),
+        }
+    }
+}
+
+fn parse_number(digits: &str) -> Option<u32> {
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok().filter(|number| *number > 0)
+}
+
+pub fn parse_volume_name(file_name: &str) -> Option<Volume> {
+    // lowercase ASCII keeps byte offsets, so they can be used on the original name
+    let lowercase = file_name.to_ascii_lowercase();
+    if let Some(stem) = lowercase.strip_suffix(".rar") {
+        let part_start = stem.rfind(".part")?;
+        return Some(Volume {
+            set_name: file_name[..part_start].to_string(),
+            kind: VolumeKind::Rar,
+            number: parse_number(&stem[part_start + ".part".len()..])?,
+        });
+    }
+
+    let (stem, digits) = lowercase.rsplit_once('.')?;
+    let set_name_length = stem.strip_suffix(".7z")?.len();
+    Some(Volume {
+        set_name: file_name[..set_name_length].to_string(),
+        kind: VolumeKind::SevenZip,
+        number: parse_number(digits)?,
+    })
+}
+
+// size of whole archive according to start header of its first volume
+fn seven_zip_size(first_volume: &Path) -> Option<u64> {
+    let mut header = vec![];
+    File::open(first_volume)
+        .and_then(|file| {
+            file.take(SEVEN_ZIP_START_HEADER_SIZE)
+                .read_to_end(&mut header)
+        })
+        .ok()?;
+    if header.len() as u64 != SEVEN_ZIP_START_HEADER_SIZE
+        || !header.starts_with(SEVEN_ZIP_SIGNATURE)
+    {
+        return None;
+    }
+    let read_u64 = |offset: usize| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&header[offset..offset + 8]);
+        u64::from_le_bytes(bytes)
+    };
+    let next_header_offset = read_u64(12);
+    let next_header_size = read_u64(20);
+    SEVEN_ZIP_START_HEADER_SIZE
+        .checked_add(next_header_offset)?
+        .checked_add(next_header_size)
+}
+
+/// Volumes have to be numbered from 1 without gaps and listed in order. Rar is complete when unrar
+/// lists it without asking for next volume, so volumes have to be in the same directory under
+/// their original names. Split 7z is complete when its pieces add up to size from its header.
+pub fn is_complete_set(kind: VolumeKind, volumes: &[(u32, PathBuf)]) -> bool {
+    let numbered_in_order = volumes
+        .iter()
+        .enumerate()
+        .all(|(index, (number, _))| *number as usize == index + 1);
+    if volumes.is_empty() || !numbered_in_order {
+        return false;
+    }
+
+    match kind {
+        VolumeKind::Rar => unpacker::rar_list_entries(&volumes[0].1).is_ok(),
+        VolumeKind::SevenZip => {
+            let size = volumes.iter().fold(Some(0u64), |size, (_, path)| {
+                let volume_size = std::fs::metadata(path).ok()?.len();
+                size?.checked_add(volume_size)
+            });
+            size.is_some() && size == seven_zip_size(&volumes[0].1)
+        }
+    }
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> UnpackError {
+    UnpackError::Archive(format!("{}: {}", path.display(), error))
+}
+
+/// Writes complete set of volumes, listed in order, into single archive. Rar volumes can't be
+/// joined into one rar, so the set is unpacked under limits like any upload and packed into tar,
+/// which clients downloading the mod can unpack without the volumes.
+pub fn join_volumes(
+    kind: VolumeKind,
+    volumes: &[(u32, PathBuf)],
+    output_path: &Path,
+    limits: &ArchiveLimits,
+) -> Result<(), UnpackError> {
+    match kind {
+        VolumeKind::Rar => {
+            let unpack_directory = TempDir::new("acsync_server_volumes")
+                .map_err(|error| UnpackError::Archive(error.to_string()))?;
+            // unrar reads the rest of volumes when unpacking the first one
+            super::extract(&volumes[0].1, unpack_directory.path(), limits, 0)?;
+
+            let output = File::create(output_path).map_err(|error| io_error(output_path, error))?;
+            let mut builder = tar::Builder::new(output);
+            // links were vetted to stay inside, they are packed as links again
+            builder.follow_symlinks(false);
+            builder
+                .append_dir_all("", unpack_directory.path())
+                .and_then(|_| builder.finish())
+                .map_err(|error| io_error(output_path, error))
+        }
+        VolumeKind::SevenZip => {
+            let mut output =
+                File::create(output_path).map_err(|error| io_error(output_path, error))?;
+            for (_, path) in volumes {
+                File::open(path)
+                    .and_then(|mut volume| std::io::copy(&mut volume, &mut output))
+                    .map_err(|error| io_error(path, error))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    use crate::tests::mocks::unpack_archive_mock::MOCK_STABILITY_MUTEX;
+
+    fn volume(set_name: &str, kind: VolumeKind, number: u32) -> Option<Volume> {
+        Some(Volume {
+            set_name: set_name.to_string(),
+            kind: kind,
+            number: number,
+        })
+    }
+
+    #[test]
+    fn test_parse_volume_name() {
+        assert_eq!(
+            volume("pack", VolumeKind::Rar, 1),
+            parse_volume_name("pack.part1.rar")
+        );
+        assert_eq!(
+            volume("Track Pack", VolumeKind::Rar, 12),
+            parse_volume_name("Track Pack.PART012.RAR")
+        );
+        assert_eq!(
+            volume("pack", VolumeKind::SevenZip, 2),
+            parse_volume_name("pack.7z.002")
+        );
+        assert_eq!(
+            "pack.tar",
+            parse_volume_name("pack.part1.rar")
+                .unwrap()
+                .joined_file_name()
+        );
+        assert_eq!(
+            "pack.7z",
+            parse_volume_name("pack.7z.001").unwrap().joined_file_name()
+        );
+
+        for file_name in [
+            "pack.rar",
+            "pack.part.rar",
+            "pack.part0.rar",
+            "pack.7z",
+            "pack.7z.000",
+            "pack.zip.001",
+            "pack.7z.1a",
+        ] {
+            assert_eq!(None, parse_volume_name(file_name), "{}", file_name);
+        }
+    }
+
+    // 7z start header claiming archive of given size, rest of it doesn't matter for joining
+    fn seven_zip_volume(size: u64) -> Vec<u8> {
+        let mut data = SEVEN_ZIP_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0, 4, 0, 0, 0, 0]);
+        data.extend_from_slice(&(size - SEVEN_ZIP_START_HEADER_SIZE - 8).to_le_bytes());
+        data.extend_from_slice(&8u64.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data
+    }
+
+    #[test]
+    fn test_split_seven_zip_is_joined_when_complete() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let first = temp_dir.path().join("pack.7z.001");
+        let second = temp_dir.path().join("pack.7z.002");
+        let mut data = seven_zip_volume(40);
+        data.resize(40, 7);
+        std::fs::write(&first, &data[..30]).unwrap();
+
+        let volumes = vec![(1, first.clone())];
+        assert!(!is_complete_set(VolumeKind::SevenZip, &volumes));
+
+        std::fs::write(&second, &data[30..]).unwrap();
+        let volumes = vec![(2, second.clone()), (1, first.clone())];
+        assert!(!is_complete_set(VolumeKind::SevenZip, &volumes));
+        let volumes = vec![(1, first), (2, second)];
+        assert!(is_complete_set(VolumeKind::SevenZip, &volumes));
+
+        let joined_path = temp_dir.path().join("pack.7z");
+        join_volumes(
+            VolumeKind::SevenZip,
+            &volumes,
+            &joined_path,
+            &ArchiveLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(data, std::fs::read(&joined_path).unwrap());
+    }
+
+    #[test]
+    fn test_rar_set_is_complete_when_unrar_lists_it() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let volumes = vec![
+            (1, temp_dir.path().join("pack.part1.rar")),
+            (2, temp_dir.path().join("pack.part2.rar")),
+        ];
+
+        let list_context = unpacker::rar_list_entries_context();
+        list_context
+            .expect()
+            .times(1)
+            .returning(|_| Err(compress_tools::Error::from("Missing volume".to_string())));
+        assert!(!is_complete_set(VolumeKind::Rar, &volumes));
+        list_context.checkpoint();
+
+        list_context.expect().times(1).returning(|_| Ok(vec![]));
+        assert!(is_complete_set(VolumeKind::Rar, &volumes));
+        // unrar isn't asked when first volume is missing
+        assert!(!is_complete_set(VolumeKind::Rar, &volumes[1..]));
+    }
+
+    #[test]
+    fn test_rar_set_is_joined_into_tar_of_its_contents() {
+        let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let mut volumes = vec![];
+        for number in [1, 2] {
+            let path = temp_dir.path().join(format!("pack.part{}.rar", number));
+            std::fs::write(&path, b"Rar!\x1a\x07\x01\x00").unwrap();
+            volumes.push((number, path));
+        }
+
+        let list_context = unpacker::rar_list_entries_context();
+        list_context.expect().returning(|_| Ok(vec![]));
+        let unpack_context = unpacker::rar_unpack_context();
+        unpack_context.expect().times(1).returning(
+            |archive_path: &Path, destination_path: &Path| {
+                assert!(archive_path.ends_with("pack.part1.rar"));
+                std::fs::create_dir_all(destination_path.join("content/cars/car")).unwrap();
+                std::fs::write(destination_path.join("content/cars/car/data.acd"), "acd").unwrap();
+                Ok(())
+            },
+        );
+
+        let joined_path = temp_dir.path().join("pack.tar");
+        join_volumes(
+            VolumeKind::Rar,
+            &volumes,
+            &joined_path,
+            &ArchiveLimits::default(),
+        )
+        .unwrap();
+        let mut joined = tar::Archive::new(File::open(&joined_path).unwrap());
+        let paths: Vec<String> = joined
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert!(paths.contains(&"content/cars/car/data.acd".to_string()));
+    }
+}
@@ -0,0 +1,177 @@
+use std::io::Write;
+use std::path::Path;
+
+use clap::ArgMatches;
+
+use crate::config::{ConfigObject, ConfigTrait, Role};
+use crate::mod_installer::{self, ConflictResolution, ImportResult};
+
+fn read_password(matches: &ArgMatches) -> Result<String, String> {
+    if let Some(password) = matches.value_of("password") {
+        return Ok(password.to_string());
+    }
+
+    print!("Password: ");
+    let _ = std::io::stdout().flush();
+    let mut password = String::new();
+    if let Err(error) = std::io::stdin().read_line(&mut password) {
+        return Err(error.to_string());
+    }
+
+    let password = password.trim_end_matches(&['\r', '\n'][..]).to_string();
+    if password.is_empty() {
+        return Err("Password cannot be empty".to_string());
+    }
+    Ok(password)
+}
+
+fn run_user_command(matches: &ArgMatches, config: &mut ConfigObject) -> Result<(), String> {
+    match matches.subcommand() {
+        Some(("add", matches)) => {
+            let login = matches.value_of("login").unwrap().to_string();
+            let role: Role = matches.value_of("role").unwrap_or("viewer").parse()?;
+            let password = read_password(matches)?;
+            config.add_user(&login, &password, role)?;
+            println!("User {} added", login);
+        }
+        Some(("remove", matches)) => {
+            let login = matches.value_of("login").unwrap().to_string();
+            config.delete_user(&login)?;
+            println!("User {} removed", login);
+        }
+        Some(("passwd", matches)) => {
+            let login = matches.value_of("login").unwrap().to_string();
+            if !config.user_exists(&login) {
+                return Err("User not found".to_string());
+            }
+            let password = read_password(matches)?;
+            config.change_user_password(&login, &password)?;
+            println!("Password changed for user {}", login);
+        }
+        Some(("role", matches)) => {
+            let login = matches.value_of("login").unwrap().to_string();
+            let role: Role = matches.value_of("role").unwrap().parse()?;
+            config.set_user_role(&login, role)?;
+            println!("User {} is now {}", login, role.display_name());
+        }
+        Some(("list", _)) => {
+            for user in config.config.users.iter() {
+                println!("{} ({})", user.login, user.role.display_name());
+            }
+        }
+        Some(("disable-2fa", matches)) => {
+            let login = matches.value_of("login").unwrap().to_string();
+            config.disable_totp(&login)?;
+            println!("Two-factor authentication disabled for user {}", login);
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn run_mod_command(matches: &ArgMatches, config: &mut ConfigObject) -> Result<(), String> {
+    match matches.subcommand() {
+        Some(("import", matches)) => {
+            let archive_path = Path::new(matches.value_of("archive").unwrap());
+            let file_name = match archive_path.file_name() {
+                Some(file_name) => file_name.to_string_lossy().to_string(),
+                None => return Err("Invalid archive path".to_string()),
+            };
+            let resolution: ConflictResolution =
+                matches.value_of("on-conflict").unwrap_or("abort").parse()?;
+            match mod_installer::import_archive(config, archive_path, &file_name, resolution)
+                .map_err(|error| error.to_string())?
+            {
+                ImportResult::Imported {
+                    installed,
+                    replaced,
+                    server_paths,
+                    warnings,
+                } => {
+                    for report in server_paths.iter() {
+                        println!(
+                            "{} files copied to {}",
+                            report.files_copied, report.server_path
+                        );
+                    }
+                    for acmod in replaced.iter() {
+                        println!("Mod {} replaced", acmod.filename);
+                    }
+                    for warning in warnings.iter() {
+                        println!("Inner archive left unpacked: {}", warning);
+                    }
+                    println!("Mod {} imported, md5 {}", file_name, installed.checksum_md5);
+                }
+                ImportResult::Conflicts(conflicts) => {
+                    for conflict in conflicts.iter() {
+                        println!("{} is provided by {}", conflict.path, conflict.filename);
+                    }
+                    return Err(format!(
+                        "{} conflicts with installed mods, use --on-conflict keep_both or replace",
+                        file_name
+                    ));
+                }
+            }
+        }
+        Some(("preview", matches)) => {
+            let archive_path = Path::new(matches.value_of("archive").unwrap());
+            let file_name = match archive_path.file_name() {
+                Some(file_name) => file_name.to_string_lossy().to_string(),
+                None => return Err("Invalid archive path".to_string()),
+            };
+            let preview = mod_installer::preview_archive(
+                archive_path,
+                &file_name,
+                &config.get_server_paths(),
+                &config.config.archive_limits,
+            )
+            .map_err(|error| error.to_string())?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&preview).map_err(|error| error.to_string())?
+            );
+        }
+        Some(("remove", matches)) => {
+            let hash = matches.value_of("hash").unwrap().to_string();
+            config.delete_mod(&hash)?;
+            println!("Mod {} removed", hash);
+        }
+        Some(("restore", matches)) => {
+            let hash = matches.value_of("hash").unwrap().to_string();
+            let version_hash = matches.value_of("version_hash").unwrap().to_string();
+            let restored = mod_installer::restore_version(config, &hash, &version_hash)
+                .map_err(|error| error.to_string())?;
+            println!(
+                "Mod {} restored as version {}",
+                restored.filename, restored.version
+            );
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+fn run_verify_command(config: &ConfigObject) -> Result<(), String> {
+    let problems = config.verify();
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+
+    if problems.len() > 0 {
+        return Err(format!("{} problems found", problems.len()));
+    }
+    println!("No problems found");
+    Ok(())
+}
+
+/// Runs administrative subcommand, returns None if server should be started instead.
+pub fn run_command(matches: &ArgMatches, config: &mut ConfigObject) -> Option<Result<(), String>> {
+    let result = match matches.subcommand() {
+        Some(("rebuild-db", matches)) => config.rebuild_mod_storage(matches.is_present("clear")),
+        Some(("user", matches)) => run_user_command(matches, config),
+        Some(("mod", matches)) => run_mod_command(matches, config),
+        Some(("verify", _)) => run_verify_command(config),
+        _ => return None,
+    };
+    Some(result)
+}
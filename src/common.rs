@@ -1,6 +1,7 @@
 use std::path::Path;
 use walkdir::WalkDir;
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct FsEntry {
     pub path: String,
     pub is_file: bool,
@@ -17,3 +18,13 @@ pub fn recursive_ls(dir: &Path) -> Vec<FsEntry> {
     }
     ret
 }
+
+/// Compares every byte, so response time doesn't tell how much of a secret matched.
+pub fn constant_time_eq(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
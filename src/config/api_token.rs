@@ -0,0 +1,168 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use super::role::{Permission, Role};
+
+const TOKEN_PREFIX: &str = "acs_";
+
+// last_used is only updated when older than this, so every request doesn't save config
+const LAST_USED_SAVE_INTERVAL_MINUTES: i64 = 10;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    Read,
+    Upload,
+}
+
+impl TokenScope {
+    pub const ALL: [TokenScope; 2] = [TokenScope::Read, TokenScope::Upload];
+
+    /// Token never grants more than role of its owner, this only narrows it down.
+    pub fn allows(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::DownloadMods => true,
+            Permission::UploadMods => *self == TokenScope::Upload,
+            Permission::DeleteMods | Permission::ManageUsers => false,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "read",
+            TokenScope::Upload => "upload",
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            TokenScope::Read => "Read only",
+            TokenScope::Upload => "Read and upload",
+        }
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        TokenScope::ALL
+            .iter()
+            .find(|scope| scope.as_str() == value)
+            .copied()
+            .ok_or(format!("Unknown token scope: {}", value))
+    }
+}
+
+/// Owner of API token which request was authenticated with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenOwner {
+    pub login: String,
+    pub role: Role,
+    pub scope: TokenScope,
+    /// last_used is old enough to be updated with mark_api_token_used.
+    pub needs_marking: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiToken {
+    pub id: String,
+    pub name: String,
+    pub token_hash: String,
+    pub scope: TokenScope,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    /// Returns new token and its plain text value, which is only shown to user once.
+    pub fn generate(name: &str, scope: TokenScope, now: DateTime<Utc>) -> (ApiToken, String) {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let secret: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let token = format!("{}{}", TOKEN_PREFIX, secret);
+
+        let token_hash = hash_token(&token);
+        let api_token = ApiToken {
+            id: token_hash[..12].to_string(),
+            name: name.to_string(),
+            token_hash: token_hash,
+            scope: scope,
+            created_at: now,
+            last_used: None,
+        };
+        (api_token, token)
+    }
+
+    pub fn needs_marking(&self, now: DateTime<Utc>) -> bool {
+        match self.last_used {
+            Some(last_used) => {
+                now - last_used >= Duration::minutes(LAST_USED_SAVE_INTERVAL_MINUTES)
+            }
+            None => true,
+        }
+    }
+
+    /// Updates last_used, returns true if it was changed and config should be saved.
+    pub fn mark_used(&mut self, now: DateTime<Utc>) -> bool {
+        if !self.needs_marking(now) {
+            return false;
+        }
+        self.last_used = Some(now);
+        true
+    }
+}
+
+// Tokens are long random strings, so plain SHA-256 is enough and keeps lookup fast
+pub fn hash_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(token);
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_token() {
+        let (api_token, token) = ApiToken::generate("laptop", TokenScope::Read, Utc::now());
+
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(hash_token(&token), api_token.token_hash);
+        assert!(!api_token.token_hash.contains(&token[TOKEN_PREFIX.len()..]));
+        assert!(api_token.token_hash.starts_with(&api_token.id));
+
+        let (_, other_token) = ApiToken::generate("laptop", TokenScope::Read, Utc::now());
+        assert_ne!(token, other_token);
+    }
+
+    #[test]
+    fn test_token_scope() {
+        assert!(TokenScope::Read.allows(Permission::DownloadMods));
+        assert!(!TokenScope::Read.allows(Permission::UploadMods));
+        assert!(TokenScope::Upload.allows(Permission::UploadMods));
+        assert!(!TokenScope::Upload.allows(Permission::DeleteMods));
+        assert!(!TokenScope::Upload.allows(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn test_mark_used() {
+        let now = Utc::now();
+        let (mut api_token, _) = ApiToken::generate("laptop", TokenScope::Read, now);
+
+        assert!(api_token.needs_marking(now));
+        assert!(api_token.mark_used(now));
+        assert!(!api_token.needs_marking(now + Duration::minutes(1)));
+        assert!(!api_token.mark_used(now + Duration::minutes(1)));
+        assert_eq!(Some(now), api_token.last_used);
+        assert!(api_token.mark_used(now + Duration::minutes(12)));
+        assert_eq!(Some(now + Duration::minutes(12)), api_token.last_used);
+    }
+}
@@ -0,0 +1,312 @@
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    ApiTokenCreate,
+    ApiTokenRevoke,
+    Login,
+    LoginFailed,
+    LoginPasswordAccepted,
+    Logout,
+    ModDelete,
+    ModDiscardVolumes,
+    ModDownload,
+    ModRestore,
+    ModUpload,
+    PasswordChange,
+    TotpDisable,
+    UserAdd,
+    UserDelete,
+    UserSetRole,
+    UserUnlock,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 17] = [
+        AuditAction::ApiTokenCreate,
+        AuditAction::ApiTokenRevoke,
+        AuditAction::Login,
+        AuditAction::LoginFailed,
+        AuditAction::LoginPasswordAccepted,
+        AuditAction::Logout,
+        AuditAction::ModDelete,
+        AuditAction::ModDiscardVolumes,
+        AuditAction::ModDownload,
+        AuditAction::ModRestore,
+        AuditAction::ModUpload,
+        AuditAction::PasswordChange,
+        AuditAction::TotpDisable,
+        AuditAction::UserAdd,
+        AuditAction::UserDelete,
+        AuditAction::UserSetRole,
+        AuditAction::UserUnlock,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::ApiTokenCreate => "api_token_create",
+            AuditAction::ApiTokenRevoke => "api_token_revoke",
+            AuditAction::Login => "login",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::LoginPasswordAccepted => "login_password_accepted",
+            AuditAction::Logout => "logout",
+            AuditAction::ModDelete => "mod_delete",
+            AuditAction::ModDiscardVolumes => "mod_discard_volumes",
+            AuditAction::ModDownload => "mod_download",
+            AuditAction::ModRestore => "mod_restore",
+            AuditAction::ModUpload => "mod_upload",
+            AuditAction::PasswordChange => "password_change",
+            AuditAction::TotpDisable => "totp_disable",
+            AuditAction::UserAdd => "user_add",
+            AuditAction::UserDelete => "user_delete",
+            AuditAction::UserSetRole => "user_set_role",
+            AuditAction::UserUnlock => "user_unlock",
+        }
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        AuditAction::ALL
+            .iter()
+            .find(|action| action.as_str() == value)
+            .copied()
+            .ok_or(format!("Unknown action: {}", value))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub time: DateTime<Utc>,
+    pub actor: String,
+    pub action: AuditAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        actor: &str,
+        action: AuditAction,
+        target: Option<&str>,
+        remote_address: Option<String>,
+    ) -> AuditEntry {
+        AuditEntry {
+            time: Utc::now(),
+            actor: actor.to_string(),
+            action: action,
+            target: target.map(|target| target.to_string()),
+            remote_address: remote_address,
+            details: None,
+        }
+    }
+}
+
+/// Every field is optional, dates are inclusive and compared in UTC.
+#[derive(Default, Debug)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<AuditAction>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+fn parse_date(value: Option<&str>) -> Result<Option<NaiveDate>, String> {
+    match value.filter(|value| !value.is_empty()) {
+        Some(value) => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| format!("Invalid date {}, expected YYYY-MM-DD", value)),
+        None => Ok(None),
+    }
+}
+
+impl AuditFilter {
+    /// Parses filter from query parameters, empty values are treated as missing.
+    pub fn parse(
+        actor: Option<&str>,
+        action: Option<&str>,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> Result<AuditFilter, String> {
+        Ok(AuditFilter {
+            actor: actor
+                .filter(|actor| !actor.is_empty())
+                .map(|actor| actor.to_string()),
+            action: match action.filter(|action| !action.is_empty()) {
+                Some(action) => Some(action.parse()?),
+                None => None,
+            },
+            from: parse_date(from)?,
+            to: parse_date(to)?,
+        })
+    }
+
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        let date = entry.time.naive_utc().date();
+        self.actor
+            .as_ref()
+            .map_or(true, |actor| entry.actor == *actor)
+            && self.action.map_or(true, |action| entry.action == action)
+            && self.from.map_or(true, |from| date >= from)
+            && self.to.map_or(true, |to| date <= to)
+    }
+}
+
+/// Append-only log with one JSON entry per line, entries are never rewritten or removed.
+pub struct AuditLog {
+    path: PathBuf,
+    write_lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(path: &Path) -> AuditLog {
+        AuditLog {
+            path: path.to_path_buf(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn append(&self, entry: &AuditEntry) -> Result<(), String> {
+        let mut line = serde_json::to_string(entry).map_err(|error| error.to_string())?;
+        line.push('\n');
+
+        let _guard = self.write_lock.lock().unwrap();
+        let write_result = (|| -> std::io::Result<()> {
+            let mut file = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&self.path)?;
+            file.write_all(line.as_bytes())?;
+            file.sync_data()
+        })();
+        write_result.map_err(|error| format!("Failed to write {}: {}", self.path.display(), error))
+    }
+
+    /// Failing to write audit entry doesn't fail the action itself, error is only printed.
+    pub fn record(&self, entry: AuditEntry) {
+        if let Err(error) = self.append(&entry) {
+            println!("Error writing audit log entry {:?}: {}", entry, error);
+        }
+    }
+
+    /// Matching entries, newest first, at most limit of them.
+    pub fn read(&self, filter: &AuditFilter, limit: usize) -> Result<Vec<AuditEntry>, String> {
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(format!("{}: {}", self.path.display(), error)),
+        };
+
+        let mut entries = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|error| format!("{}: {}", self.path.display(), error))?;
+            // last line can be partial if server was killed while writing it
+            if let Ok(entry) = serde_json::from_str::<AuditEntry>(&line) {
+                if filter.matches(&entry) {
+                    entries.push(entry);
+                }
+            }
+        }
+
+        entries.reverse();
+        entries.truncate(limit);
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::TimeZone;
+    use tempdir::TempDir;
+
+    fn entry(actor: &str, action: AuditAction, day: u32) -> AuditEntry {
+        let mut entry = AuditEntry::new(actor, action, Some("car.zip"), None);
+        entry.time = Utc.ymd(2021, 3, day).and_hms(12, 0, 0);
+        entry
+    }
+
+    #[test]
+    fn test_append_and_read_newest_first() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let audit_log = AuditLog::new(&temp_dir.path().join("audit_log.jsonl"));
+
+        assert!(audit_log
+            .read(&AuditFilter::default(), 10)
+            .unwrap()
+            .is_empty());
+        audit_log
+            .append(&entry("admin", AuditAction::ModUpload, 1))
+            .unwrap();
+        audit_log
+            .append(&entry("user", AuditAction::ModDownload, 2))
+            .unwrap();
+        audit_log
+            .append(&entry("admin", AuditAction::ModDelete, 3))
+            .unwrap();
+
+        let entries = audit_log.read(&AuditFilter::default(), 2).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(AuditAction::ModDelete, entries[0].action);
+        assert_eq!(AuditAction::ModDownload, entries[1].action);
+    }
+
+    #[test]
+    fn test_read_skips_partial_line() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let path = temp_dir.path().join("audit_log.jsonl");
+        let audit_log = AuditLog::new(&path);
+
+        audit_log
+            .append(&entry("admin", AuditAction::Login, 1))
+            .unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"time\":\"2021-03-").unwrap();
+
+        assert_eq!(
+            1,
+            audit_log.read(&AuditFilter::default(), 10).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = AuditFilter::parse(
+            Some("admin"),
+            Some("mod_delete"),
+            Some("2021-03-02"),
+            Some(""),
+        )
+        .unwrap();
+
+        assert!(filter.matches(&entry("admin", AuditAction::ModDelete, 2)));
+        assert!(!filter.matches(&entry("admin", AuditAction::ModDelete, 1)));
+        assert!(!filter.matches(&entry("admin", AuditAction::ModUpload, 2)));
+        assert!(!filter.matches(&entry("user", AuditAction::ModDelete, 2)));
+
+        let filter = AuditFilter::parse(None, None, None, Some("2021-03-02")).unwrap();
+        assert!(filter.matches(&entry("user", AuditAction::Login, 2)));
+        assert!(!filter.matches(&entry("user", AuditAction::Login, 3)));
+    }
+
+    #[test]
+    fn test_filter_rejects_invalid_values() {
+        assert!(AuditFilter::parse(None, Some("format_disk"), None, None).is_err());
+        assert!(AuditFilter::parse(None, None, Some("03/02/2021"), None).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+const MAX_LOGIN_EVENTS: usize = 200;
+// keys come from request, so oldest records are dropped once there are this many
+const MAX_RECORDS: usize = 10000;
+// addresses remembered for each account, cleared together with it by unlock
+const MAX_ADDRESSES_PER_ACCOUNT: usize = 16;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoginThrottleSettings {
+    pub max_failures: u32,
+    pub base_delay_seconds: u64,
+    pub max_delay_seconds: u64,
+    pub lockout_minutes: u64,
+}
+
+impl Default for LoginThrottleSettings {
+    fn default() -> Self {
+        LoginThrottleSettings {
+            max_failures: 5,
+            base_delay_seconds: 1,
+            max_delay_seconds: 30,
+            lockout_minutes: 15,
+        }
+    }
+}
+
+impl LoginThrottleSettings {
+    fn lockout(&self) -> Duration {
+        Duration::from_secs(self.lockout_minutes * 60)
+    }
+
+    // 1s, 2s, 4s... after consecutive failures, capped at max_delay_seconds
+    fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::from_secs(0);
+        }
+        let multiplier = 1u64.checked_shl(failures - 1).unwrap_or(u64::MAX);
+        let delay = self.base_delay_seconds.saturating_mul(multiplier);
+        Duration::from_secs(delay.min(self.max_delay_seconds))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoginEvent {
+    pub time: SystemTime,
+    pub login: String,
+    pub remote_address: Option<String>,
+    pub success: bool,
+    pub message: String,
+}
+
+#[derive(Debug)]
+struct FailureRecord {
+    failures: u32,
+    last_failure: SystemTime,
+    locked_until: Option<SystemTime>,
+    /// Addresses failed logins to account came from, empty in records of addresses.
+    addresses: Vec<String>,
+}
+
+impl FailureRecord {
+    /// Time until next attempt is allowed, None if attempt is allowed now.
+    fn wait_time(&self, settings: &LoginThrottleSettings, now: SystemTime) -> Option<Duration> {
+        let allowed_at = match self.locked_until {
+            Some(locked_until) => locked_until,
+            None => self.last_failure + settings.delay(self.failures),
+        };
+        allowed_at
+            .duration_since(now)
+            .ok()
+            .filter(|wait| !wait.is_zero())
+    }
+
+    fn is_stale(&self, settings: &LoginThrottleSettings, now: SystemTime) -> bool {
+        let since_failure = now.duration_since(self.last_failure).unwrap_or_default();
+        self.wait_time(settings, now).is_none() && since_failure > settings.lockout()
+    }
+}
+
+/// Counts failed logins per account and per IP address, both are checked before password.
+/// State is kept in memory only.
+#[derive(Default)]
+pub struct LoginThrottle {
+    records: Mutex<HashMap<String, FailureRecord>>,
+    events: Mutex<VecDeque<LoginEvent>>,
+}
+
+fn account_key(login: &str) -> String {
+    format!("account:{}", login)
+}
+
+fn address_key(remote_address: &str) -> String {
+    format!("address:{}", remote_address)
+}
+
+fn keys(login: &str, remote_address: Option<&str>) -> Vec<String> {
+    let mut keys = vec![account_key(login)];
+    if let Some(remote_address) = remote_address {
+        keys.push(address_key(remote_address));
+    }
+    keys
+}
+
+// makes room for new record, stale ones go first and then the one which failed longest ago
+fn evict_records(
+    records: &mut HashMap<String, FailureRecord>,
+    settings: &LoginThrottleSettings,
+    now: SystemTime,
+) {
+    records.retain(|_, record| !record.is_stale(settings, now));
+    while records.len() >= MAX_RECORDS {
+        let oldest = records
+            .iter()
+            .min_by_key(|(_, record)| record.last_failure)
+            .map(|(key, _)| key.clone());
+        match oldest {
+            Some(key) => records.remove(&key),
+            None => break,
+        };
+    }
+}
+
+fn check_records(
+    records: &mut HashMap<String, FailureRecord>,
+    login: &str,
+    remote_address: Option<&str>,
+    settings: &LoginThrottleSettings,
+    now: SystemTime,
+) -> Result<(), u64> {
+    records.retain(|_, record| !record.is_stale(settings, now));
+
+    let wait = keys(login, remote_address)
+        .iter()
+        .filter_map(|key| records.get(key))
+        .filter_map(|record| record.wait_time(settings, now))
+        .max();
+    match wait {
+        // round up, so waiting given number of seconds is always enough
+        Some(wait) => Err(wait.as_secs() + (wait.subsec_nanos() > 0) as u64),
+        None => Ok(()),
+    }
+}
+
+fn add_failure(
+    records: &mut HashMap<String, FailureRecord>,
+    login: &str,
+    remote_address: Option<&str>,
+    settings: &LoginThrottleSettings,
+    now: SystemTime,
+) {
+    for key in keys(login, remote_address) {
+        if !records.contains_key(&key) && records.len() >= MAX_RECORDS {
+            evict_records(records, settings, now);
+        }
+        let record = records.entry(key).or_insert(FailureRecord {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+            addresses: vec![],
+        });
+        record.failures += 1;
+        record.last_failure = now;
+        if record.failures >= settings.max_failures {
+            record.locked_until = Some(now + settings.lockout());
+        }
+    }
+
+    if let (Some(remote_address), Some(record)) =
+        (remote_address, records.get_mut(&account_key(login)))
+    {
+        let address = remote_address.to_string();
+        if !record.addresses.contains(&address) {
+            if record.addresses.len() >= MAX_ADDRESSES_PER_ACCOUNT {
+                record.addresses.remove(0);
+            }
+            record.addresses.push(address);
+        }
+    }
+}
+
+/// Login attempt which passed throttle check and counts as failure until it's released, so
+/// parallel attempts can't all pass the check before the first failure is recorded.
+#[must_use]
+pub struct LoginAttempt {
+    login: String,
+    remote_address: Option<String>,
+}
+
+impl LoginThrottle {
+    /// Returns number of seconds to wait if login attempt is not allowed yet.
+    pub fn check(
+        &self,
+        login: &str,
+        remote_address: Option<&str>,
+        settings: &LoginThrottleSettings,
+        now: SystemTime,
+    ) -> Result<(), u64> {
+        let mut records = self.records.lock().unwrap();
+        check_records(&mut records, login, remote_address, settings, now)
+    }
+
+    /// Checks throttle and counts attempt as failure right away, password is verified afterwards.
+    /// Failed attempt is simply dropped, it's counted already.
+    pub fn begin_attempt(
+        &self,
+        login: &str,
+        remote_address: Option<&str>,
+        settings: &LoginThrottleSettings,
+        now: SystemTime,
+    ) -> Result<LoginAttempt, u64> {
+        let mut records = self.records.lock().unwrap();
+        check_records(&mut records, login, remote_address, settings, now)?;
+        add_failure(&mut records, login, remote_address, settings, now);
+        Ok(LoginAttempt {
+            login: login.to_string(),
+            remote_address: remote_address.map(|address| address.to_string()),
+        })
+    }
+
+    /// Takes back failure counted for attempt whose password was right.
+    pub fn release_attempt(&self, attempt: LoginAttempt, settings: &LoginThrottleSettings) {
+        let mut records = self.records.lock().unwrap();
+        for key in keys(&attempt.login, attempt.remote_address.as_deref()) {
+            let record = match records.get_mut(&key) {
+                Some(record) => record,
+                None => continue,
+            };
+            record.failures = record.failures.saturating_sub(1);
+            if record.failures < settings.max_failures {
+                record.locked_until = None;
+            }
+            if record.failures == 0 {
+                records.remove(&key);
+            }
+        }
+    }
+
+    pub fn record_failure(
+        &self,
+        login: &str,
+        remote_address: Option<&str>,
+        settings: &LoginThrottleSettings,
+        now: SystemTime,
+    ) {
+        let mut records = self.records.lock().unwrap();
+        add_failure(&mut records, login, remote_address, settings, now);
+    }
+
+    /// Clears failures of account only, so address can't reset its own counter with valid login.
+    pub fn record_success(&self, login: &str) {
+        let mut records = self.records.lock().unwrap();
+        records.remove(&account_key(login));
+    }
+
+    /// Clears failures of account and of addresses its failed logins came from, so its owner isn't
+    /// kept out by throttled address. Returns false if account was not throttled.
+    pub fn unlock(&self, login: &str) -> bool {
+        let mut records = self.records.lock().unwrap();
+        let record = match records.remove(&account_key(login)) {
+            Some(record) => record,
+            None => return false,
+        };
+        for address in record.addresses.iter() {
+            records.remove(&address_key(address));
+        }
+        true
+    }
+
+    pub fn is_locked(
+        &self,
+        login: &str,
+        settings: &LoginThrottleSettings,
+        now: SystemTime,
+    ) -> bool {
+        let records = self.records.lock().unwrap();
+        match records.get(&account_key(login)) {
+            Some(record) => {
+                record.locked_until.is_some() && record.wait_time(settings, now).is_some()
+            }
+            None => false,
+        }
+    }
+
+    pub fn add_event(&self, event: LoginEvent) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= MAX_LOGIN_EVENTS {
+            events.pop_back();
+        }
+        events.push_front(event);
+    }
+
+    /// Recent login events, newest first.
+    pub fn events(&self) -> Vec<LoginEvent> {
+        let events = self.events.lock().unwrap();
+        events.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> LoginThrottleSettings {
+        LoginThrottleSettings {
+            max_failures: 3,
+            base_delay_seconds: 2,
+            max_delay_seconds: 3,
+            lockout_minutes: 1,
+        }
+    }
+
+    fn seconds(count: u64) -> Duration {
+        Duration::from_secs(count)
+    }
+
+    #[test]
+    fn test_delay_is_exponential_and_capped() {
+        let settings = settings();
+
+        assert_eq!(seconds(0), settings.delay(0));
+        assert_eq!(seconds(2), settings.delay(1));
+        assert_eq!(seconds(3), settings.delay(2));
+        assert_eq!(seconds(3), settings.delay(100));
+    }
+
+    #[test]
+    fn test_backoff_after_failure() {
+        let throttle = LoginThrottle::default();
+        let now = SystemTime::now();
+
+        assert_eq!(Ok(()), throttle.check("admin", None, &settings(), now));
+        throttle.record_failure("admin", None, &settings(), now);
+        assert_eq!(Err(2), throttle.check("admin", None, &settings(), now));
+        assert_eq!(
+            Ok(()),
+            throttle.check("admin", None, &settings(), now + seconds(2))
+        );
+        assert_eq!(Ok(()), throttle.check("user", None, &settings(), now));
+    }
+
+    #[test]
+    fn test_lockout_and_unlock() {
+        let throttle = LoginThrottle::default();
+        let now = SystemTime::now();
+
+        for _ in 0..3 {
+            throttle.record_failure("admin", None, &settings(), now);
+        }
+        assert!(throttle.is_locked("admin", &settings(), now));
+        assert_eq!(
+            Err(50),
+            throttle.check("admin", None, &settings(), now + seconds(10))
+        );
+        assert_eq!(
+            Ok(()),
+            throttle.check("admin", None, &settings(), now + seconds(60))
+        );
+
+        throttle.record_failure("admin", None, &settings(), now);
+        assert!(throttle.unlock("admin"));
+        assert!(!throttle.is_locked("admin", &settings(), now));
+        assert_eq!(Ok(()), throttle.check("admin", None, &settings(), now));
+        assert!(!throttle.unlock("admin"));
+    }
+
+    #[test]
+    fn test_throttle_per_address() {
+        let throttle = LoginThrottle::default();
+        let now = SystemTime::now();
+        let address = Some("192.168.0.2");
+
+        // guessing different logins from one address is throttled as well
+        throttle.record_failure("admin", address, &settings(), now);
+        throttle.record_failure("user", address, &settings(), now);
+        throttle.record_failure("root", address, &settings(), now);
+        assert_eq!(
+            Err(59),
+            throttle.check("guest", address, &settings(), now + seconds(1))
+        );
+        assert_eq!(
+            Ok(()),
+            throttle.check("guest", Some("192.168.0.3"), &settings(), now)
+        );
+
+        throttle.record_success("admin");
+        assert_eq!(Ok(()), throttle.check("admin", None, &settings(), now));
+        assert!(throttle.check("admin", address, &settings(), now).is_err());
+    }
+
+    #[test]
+    fn test_unlock_clears_addresses_of_account() {
+        let throttle = LoginThrottle::default();
+        let now = SystemTime::now();
+        let address = Some("192.168.0.2");
+
+        for _ in 0..3 {
+            throttle.record_failure("admin", address, &settings(), now);
+        }
+        throttle.record_failure("user", Some("192.168.0.3"), &settings(), now);
+        assert!(throttle.unlock("admin"));
+        assert_eq!(Ok(()), throttle.check("admin", address, &settings(), now));
+        assert!(throttle
+            .check("user", Some("192.168.0.3"), &settings(), now)
+            .is_err());
+    }
+
+    #[test]
+    fn test_records_are_limited() {
+        let throttle = LoginThrottle::default();
+        let now = SystemTime::now();
+
+        throttle.record_failure("admin", None, &settings(), now);
+        let later = now + seconds(1);
+        for index in 0..MAX_RECORDS {
+            throttle.record_failure(&format!("user{}", index), None, &settings(), later);
+        }
+        assert_eq!(MAX_RECORDS, throttle.records.lock().unwrap().len());
+        // the oldest one was dropped
+        assert_eq!(Ok(()), throttle.check("admin", None, &settings(), now));
+    }
+
+    #[test]
+    fn test_events_are_limited() {
+        let throttle = LoginThrottle::default();
+        for index in 0..MAX_LOGIN_EVENTS + 10 {
+            throttle.add_event(LoginEvent {
+                time: SystemTime::now(),
+                login: format!("user{}", index),
+                remote_address: None,
+                success: false,
+                message: "Wrong password".to_string(),
+            });
+        }
+
+        let events = throttle.events();
+        assert_eq!(MAX_LOGIN_EVENTS, events.len());
+        assert_eq!(format!("user{}", MAX_LOGIN_EVENTS + 9), events[0].login);
+    }
+
+    #[test]
+    fn test_parallel_attempts_are_counted_before_verification() {
+        let throttle = std::sync::Arc::new(LoginThrottle::default());
+        let settings = LoginThrottleSettings {
+            base_delay_seconds: 0,
+            ..settings()
+        };
+        let now = SystemTime::now();
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(10));
+
+        // none of them is finished before all have started, wrong passwords are never released
+        let threads: Vec<_> = (0..10)
+            .map(|_| {
+                let throttle = throttle.clone();
+                let settings = settings.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    let attempt =
+                        throttle.begin_attempt("admin", Some("192.168.0.2"), &settings, now);
+                    barrier.wait();
+                    attempt.is_ok()
+                })
+            })
+            .collect();
+        let allowed = threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .filter(|allowed| *allowed)
+            .count();
+        assert_eq!(settings.max_failures as usize, allowed);
+        assert!(throttle.is_locked("admin", &settings, now));
+    }
+
+    #[test]
+    fn test_released_attempt_is_not_counted() {
+        let throttle = LoginThrottle::default();
+        let now = SystemTime::now();
+        let address = Some("192.168.0.2");
+
+        throttle.record_failure("admin", address, &settings(), now);
+        let attempt = throttle
+            .begin_attempt("admin", address, &settings(), now + seconds(2))
+            .unwrap();
+        assert!(throttle
+            .check("admin", None, &settings(), now + seconds(2))
+            .is_err());
+        throttle.release_attempt(attempt, &settings());
+
+        let records = throttle.records.lock().unwrap();
+        assert_eq!(1, records[&account_key("admin")].failures);
+        assert_eq!(1, records[&address_key("192.168.0.2")].failures);
+    }
+}
@@ -1,60 +1,235 @@
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::archive_unpacker::ArchiveLimits;
+use crate::install_manifest::{FileConflict, InstallManifest};
+use crate::mod_metadata::{ContentMetadata, ModMetadata};
+
+mod api_token;
+mod audit_log;
+mod login_throttle;
+mod mod_database;
+mod password;
+mod pending_upload;
+mod pending_volumes;
+mod persistence;
+mod role;
+mod session;
+mod totp;
+
+pub use api_token::{ApiToken, TokenOwner, TokenScope};
+pub use audit_log::{AuditAction, AuditEntry, AuditFilter, AuditLog};
+pub use login_throttle::{LoginEvent, LoginThrottle, LoginThrottleSettings};
+use mod_database::ModDatabase;
+pub use password::{PasswordHashingParams, StoredPassword};
+pub use pending_upload::{PendingUpload, PendingUploads};
+pub use pending_volumes::{PendingVolumeSet, PendingVolumes};
+use persistence::{read_with_fallback, write_json_atomically};
+pub use role::{Permission, Role};
+pub use session::{Session, SessionSettings, SessionStore};
+pub use totp::TotpSettings;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AssettoMod {
     pub checksum_md5: String,
     pub filename: String,
     pub size_in_bytes: u64,
+    #[serde(default)]
+    pub cars: Vec<ContentMetadata>,
+    #[serde(default)]
+    pub tracks: Vec<ContentMetadata>,
+    #[serde(default)]
+    pub install_manifest: InstallManifest,
+    /// Folder IDs like cars/ks_ferrari_488, newer archive with the same IDs is a new version of the mod.
+    #[serde(default)]
+    pub content_ids: Vec<String>,
+    /// Inner archive each car and track folder was unpacked from, empty when archive had none.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub content_sources: BTreeMap<String, String>,
+    #[serde(default = "default_mod_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Older versions, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<ModVersion>,
+}
+
+/// Replaced version of a mod, archive is kept in mod history directory until mod is deleted.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ModVersion {
+    pub version: u32,
+    pub checksum_md5: String,
+    pub filename: String,
+    pub size_in_bytes: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub cars: Vec<ContentMetadata>,
+    #[serde(default)]
+    pub tracks: Vec<ContentMetadata>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct User {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub api_tokens: Vec<ApiToken>,
     pub login: String,
-    pub password_hash_sha512: String,
-    pub is_admin: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash_sha512: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_admin: Option<bool>,
+    #[serde(default)]
+    pub role: Role,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totp: Option<TotpSettings>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
+    #[serde(default)]
+    pub archive_limits: ArchiveLimits,
+    #[serde(default = "default_audit_log_path")]
+    pub audit_log_path: String,
+    #[serde(default = "default_backup_count")]
+    pub backup_count: usize,
     pub bind_address: String,
-    pub mods: Vec<AssettoMod>,
+    #[serde(default)]
+    pub login_throttle: LoginThrottleSettings,
+    #[serde(default = "default_mod_database_path")]
+    pub mod_database_path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_cache_location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mods: Option<Vec<AssettoMod>>,
     pub mod_storage_location: String,
+    #[serde(default)]
+    pub password_hashing: PasswordHashingParams,
     pub port: u16,
+    #[serde(default)]
+    pub require_totp_for_admins: bool,
     pub secret_key: Option<String>,
     pub server_paths: Vec<String>,
+    #[serde(default)]
+    pub session: SessionSettings,
     pub users: Vec<User>,
 }
 
 pub trait ConfigTrait {
-    fn new(path: &str) -> Self;
-    fn add_user(
+    fn new(path: &str) -> Result<Self, String>
+    where
+        Self: Sized;
+    fn add_api_token(
         &mut self,
         login: &String,
-        new_password: &String,
-        is_admin: bool,
-    ) -> Result<(), &str>;
-    fn change_user_password(&mut self, login: &String, new_password: &String) -> Result<(), &str>;
+        name: &String,
+        scope: TokenScope,
+    ) -> Result<String, String>;
+    fn add_user(&mut self, login: &String, new_password: &String, role: Role)
+        -> Result<(), String>;
+    fn add_mod(
+        &mut self,
+        archive_path: &Path,
+        metadata: ModMetadata,
+        install_manifest: InstallManifest,
+        previous_version: Option<AssettoMod>,
+    ) -> Result<AssettoMod, String>;
+    fn authenticate_api_token(&self, token: &str) -> Option<TokenOwner>;
+    fn begin_totp_enrollment(&mut self, login: &String) -> Result<TotpSettings, String>;
+    fn change_user_password(&mut self, login: &String, new_password: &String)
+        -> Result<(), String>;
+    fn confirm_totp_enrollment(
+        &mut self,
+        login: &String,
+        code: &str,
+    ) -> Result<Vec<String>, String>;
     fn delete_mod(&mut self, checksum_md5: &String) -> Result<(), String>;
-    fn delete_user(&mut self, login: &String) -> Result<(), &str>;
+    fn delete_user(&mut self, login: &String) -> Result<(), String>;
+    fn disable_totp(&mut self, login: &String) -> Result<(), String>;
     fn get_mod_list(&self) -> Vec<AssettoMod>;
     fn get_server_paths(&self) -> Vec<String>;
-    fn is_login_data_valid(&self, login: &String, password: &String) -> bool;
-    fn is_user_admin(&self, login: &String) -> bool;
+    fn get_stored_password(&self, login: &String) -> Option<StoredPassword>;
+    fn is_totp_enabled(&self, login: &String) -> bool;
+    /// Saves last use of API token, authenticate_api_token tells when it's needed.
+    fn mark_api_token_used(&mut self, token: &str);
+    fn needs_totp_enrollment(&self, login: &String) -> bool;
+    fn get_user_role(&self, login: &String) -> Option<Role>;
     fn rebuild_mod_storage(&mut self, clear: bool) -> Result<(), String>;
-    fn set_secret_key(&mut self, secret_key: String);
+    /// Replaces hash only if user still has the one password was verified with.
+    fn replace_password_hash(
+        &mut self,
+        login: &String,
+        verified: &StoredPassword,
+        password_hash: String,
+    ) -> Result<(), String>;
+    fn reinstate_mod(&mut self, acmod: AssettoMod) -> Result<(), String>;
+    fn retire_mod(&mut self, checksum_md5: &String) -> Result<AssettoMod, String>;
+    fn revoke_api_token(&mut self, login: &String, id: &String) -> Result<(), String>;
+    fn set_user_role(&mut self, login: &String, role: Role) -> Result<(), String>;
+    fn set_require_totp_for_admins(&mut self, required: bool) -> Result<(), String>;
+    fn set_secret_key(&mut self, secret_key: String) -> Result<(), String>;
     fn user_exists(&self, login: &String) -> bool;
+    fn user_has_permission(&self, login: &String, permission: Permission) -> bool;
+    fn verify(&self) -> Vec<String>;
+    fn verify_second_factor(&mut self, login: &String, code: &str) -> bool;
+}
+
+fn default_audit_log_path() -> String {
+    "./audit_log.jsonl".to_string()
+}
+
+fn default_backup_count() -> usize {
+    3
+}
+
+fn default_mod_version() -> u32 {
+    1
+}
+
+fn default_mod_database_path() -> String {
+    "./mod_database.json".to_string()
+}
+
+// mod is already gone from database, so file which can't be removed is only reported,
+// verify lists it as missing from mod database afterwards
+fn remove_stored_file(path: &Path) {
+    match std::fs::remove_file(path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+            println!("Failed to remove {}: {}", path.display(), error)
+        }
+        _ => {}
+    }
+}
+
+// directories kept out of mod storage, every file there is treated as a mod
+fn mod_storage_sibling_path(config: &Config, name: &str) -> PathBuf {
+    Path::new(&config.mod_storage_location)
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(name)
 }
 
 pub struct ConfigObject {
+    pub audit_log: AuditLog,
     pub config: Config,
+    pub login_throttle: LoginThrottle,
+    pub mod_database: ModDatabase,
     pub path: String,
+    pub pending_uploads: PendingUploads,
+    pub pending_volumes: PendingVolumes,
+    pub sessions: SessionStore,
 }
 
-fn get_assetto_mod(path: &Path) -> Result<AssettoMod, String> {
+/// MD5 checksum and size of archive.
+pub fn archive_checksum(path: &Path) -> Result<(String, u64), String> {
     let input = File::open(path);
     if let Err(error) = input {
         return Err(error.to_string());
@@ -68,132 +243,502 @@ fn get_assetto_mod(path: &Path) -> Result<AssettoMod, String> {
         return Err(error.to_string());
     }
     let hash = hasher.finalize();
+    Ok((format!("{:x}", hash), bytes_processed.unwrap()))
+}
+
+/// Verifies password without holding config lock, Argon2 is slow on purpose. Outdated hash of
+/// valid password is replaced afterwards.
+pub fn verify_login(config_lock: &RwLock<ConfigObject>, login: &String, password: &String) -> bool {
+    let (stored_password, params) = {
+        let config = config_lock.read().unwrap();
+        (
+            config.get_stored_password(login),
+            config.config.password_hashing.clone(),
+        )
+    };
+    let stored_password = match stored_password {
+        Some(stored_password) => stored_password,
+        None => return password::verify_unknown_user(password, &params),
+    };
+    if !stored_password.verify(password) {
+        return false;
+    }
+
+    if stored_password.needs_rehash(&params) {
+        let result = password::hash_password(password, &params).and_then(|password_hash| {
+            config_lock.write().unwrap().replace_password_hash(
+                login,
+                &stored_password,
+                password_hash,
+            )
+        });
+        if let Err(error) = result {
+            println!("Error rehashing password for {}: {}", login, error);
+        }
+    }
+    true
+}
+
+fn get_assetto_mod(path: &Path) -> Result<AssettoMod, String> {
+    let (checksum_md5, size_in_bytes) = archive_checksum(path)?;
 
     Ok(AssettoMod {
-        checksum_md5: format!("{:x}", hash),
+        checksum_md5: checksum_md5,
         filename: path.file_name().unwrap().to_str().unwrap().to_string(),
-        size_in_bytes: bytes_processed.unwrap(),
+        size_in_bytes: size_in_bytes,
+        cars: vec![],
+        tracks: vec![],
+        install_manifest: InstallManifest::default(),
+        content_ids: vec![],
+        content_sources: BTreeMap::new(),
+        version: default_mod_version(),
+        updated_at: None,
+        history: vec![],
     })
 }
 
-fn write_config_to_json(path: &Path, config: &Config) {
-    let json = serde_json::to_string_pretty(config);
-    if let Ok(output) = json {
-        std::fs::write(path, output).expect("Config file writing failure");
+impl AssettoMod {
+    pub fn as_version(&self) -> ModVersion {
+        ModVersion {
+            version: self.version,
+            checksum_md5: self.checksum_md5.clone(),
+            filename: self.filename.clone(),
+            size_in_bytes: self.size_in_bytes,
+            updated_at: self.updated_at,
+            cars: self.cars.clone(),
+            tracks: self.tracks.clone(),
+        }
     }
 }
 
-fn calculate_sha512(value: &String) -> String {
-    use sha2::{Digest, Sha512};
-    let mut hasher = Sha512::new();
-    hasher.update(value);
-    let hash = hasher.finalize();
-    return format!("{:x}", hash);
+impl ConfigObject {
+    fn save_config(&self) -> Result<(), String> {
+        write_json_atomically(
+            Path::new(&self.path),
+            &self.config,
+            self.config.backup_count,
+        )
+    }
+
+    // memory is rolled back when saving fails, so it doesn't disagree with disk
+    fn save_config_or_restore(&mut self, previous: Config) -> Result<(), String> {
+        self.save_config().map_err(|error| {
+            self.config = previous;
+            error
+        })
+    }
+
+    fn find_user_mut(&mut self, login: &String) -> Result<&mut User, String> {
+        match self
+            .config
+            .users
+            .iter_mut()
+            .find(|user| user.login == *login)
+        {
+            Some(user) => Ok(user),
+            None => Err("User not found".to_string()),
+        }
+    }
+
+    /// image_cache directory next to mod storage, unless image_cache_location is set.
+    pub fn image_cache_path(&self) -> PathBuf {
+        if let Some(ref location) = self.config.image_cache_location {
+            return PathBuf::from(location);
+        }
+        mod_storage_sibling_path(&self.config, "image_cache")
+    }
+
+    /// Archives of older versions, mod_history directory next to mod storage.
+    pub fn mod_history_path(&self) -> PathBuf {
+        mod_storage_sibling_path(&self.config, "mod_history")
+    }
+
+    /// Archive of older version is kept in <mod history>/<md5>/<filename>.
+    pub fn mod_version_archive_path(&self, version: &ModVersion) -> PathBuf {
+        self.mod_history_path()
+            .join(&version.checksum_md5)
+            .join(&version.filename)
+    }
+
+    /// Installed mod with exactly the same content folder IDs.
+    pub fn find_previous_version(&self, content_ids: &[String]) -> Option<AssettoMod> {
+        if content_ids.is_empty() {
+            return None;
+        }
+        self.mod_database
+            .mods
+            .iter()
+            .find(|acmod| acmod.content_ids == content_ids)
+            .cloned()
+    }
+
+    /// Installed mods which already provide any of files, one entry per file and mod.
+    pub fn find_file_conflicts(&self, files: &[String]) -> Vec<FileConflict> {
+        let mut conflicts = vec![];
+        for file in files {
+            for acmod in self.mod_database.mods.iter() {
+                if acmod.install_manifest.contains_file(file) {
+                    conflicts.push(FileConflict {
+                        path: file.clone(),
+                        checksum_md5: acmod.checksum_md5.clone(),
+                        filename: acmod.filename.clone(),
+                    });
+                }
+            }
+        }
+        conflicts
+    }
+
+    fn is_last_admin(&self, login: &String) -> bool {
+        let admins: Vec<&User> = self
+            .config
+            .users
+            .iter()
+            .filter(|user| user.role == Role::Admin)
+            .collect();
+        admins.len() == 1 && admins[0].login == *login
+    }
+
+    fn find_mod_index(&self, checksum_md5: &String) -> Result<usize, String> {
+        match self
+            .mod_database
+            .mods
+            .iter()
+            .position(|acmod| acmod.checksum_md5 == *checksum_md5)
+        {
+            Some(index) => Ok(index),
+            None => Err("Mod not found".to_string()),
+        }
+    }
+
+    // files which other mods installed too are kept
+    fn uninstall_mod(&self, acmod: &AssettoMod) {
+        let other_manifests: Vec<&InstallManifest> = self
+            .mod_database
+            .mods
+            .iter()
+            .filter(|other| other.checksum_md5 != acmod.checksum_md5)
+            .map(|other| &other.install_manifest)
+            .collect();
+        for kept_file in acmod.install_manifest.uninstall(&other_manifests) {
+            println!("Keeping {}, other mod provides it too", kept_file);
+        }
+    }
+
+    fn save_mod_database(&self) -> Result<(), String> {
+        self.mod_database.save(
+            Path::new(&self.config.mod_database_path),
+            self.config.backup_count,
+        )
+    }
+
+    fn save_mod_database_or_restore(&mut self, previous: Vec<AssettoMod>) -> Result<(), String> {
+        self.save_mod_database().map_err(|error| {
+            self.mod_database.mods = previous;
+            error
+        })
+    }
 }
 
 impl ConfigTrait for ConfigObject {
-    fn new(path: &str) -> ConfigObject {
-        let file = std::fs::read_to_string(path);
-        let content = file.unwrap();
-
-        ConfigObject {
-            config: serde_json::from_str(content.as_ref()).unwrap(),
+    fn new(path: &str) -> Result<ConfigObject, String> {
+        let config: Config = read_with_fallback(Path::new(path), |content| {
+            serde_json::from_str(content).map_err(|error| error.to_string())
+        })?;
+
+        let mod_database = ModDatabase::load(Path::new(&config.mod_database_path))
+            .map_err(|error| format!("{}: {}", config.mod_database_path, error))?;
+
+        let pending_uploads =
+            PendingUploads::new(&mod_storage_sibling_path(&config, "pending_uploads"));
+        let pending_volumes =
+            PendingVolumes::new(&mod_storage_sibling_path(&config, "pending_volumes"));
+        let mut config_object = ConfigObject {
+            audit_log: AuditLog::new(Path::new(&config.audit_log_path)),
+            config: config,
+            login_throttle: LoginThrottle::default(),
+            mod_database: mod_database,
             path: path.to_string(),
+            pending_uploads: pending_uploads,
+            pending_volumes: pending_volumes,
+            sessions: SessionStore::default(),
+        };
+
+        // mods used to be stored in config.json, move them to the mod database
+        if let Some(legacy_mods) = config_object.config.mods.take() {
+            config_object.mod_database.import(legacy_mods);
+            config_object.save_mod_database()?;
+            config_object.save_config()?;
         }
+
+        // users used to have is_admin flag instead of role
+        let mut roles_migrated = false;
+        for user in config_object.config.users.iter_mut() {
+            if let Some(is_admin) = user.is_admin.take() {
+                user.role = if is_admin { Role::Admin } else { Role::Viewer };
+                roles_migrated = true;
+            }
+        }
+        if roles_migrated {
+            config_object.save_config()?;
+        }
+
+        Ok(config_object)
+    }
+
+    fn add_api_token(
+        &mut self,
+        login: &String,
+        name: &String,
+        scope: TokenScope,
+    ) -> Result<String, String> {
+        let previous = self.config.clone();
+        let user = match self
+            .config
+            .users
+            .iter_mut()
+            .find(|user| user.login == *login)
+        {
+            Some(user) => user,
+            None => return Err("User not found".to_string()),
+        };
+
+        let (api_token, token) = ApiToken::generate(name, scope, Utc::now());
+        user.api_tokens.push(api_token);
+        self.save_config_or_restore(previous)?;
+        Ok(token)
     }
 
     fn add_user(
         &mut self,
         login: &String,
         new_password: &String,
-        is_admin: bool,
-    ) -> Result<(), &str> {
+        role: Role,
+    ) -> Result<(), String> {
+        let previous = self.config.clone();
         if self.user_exists(login) {
-            return Err("User already exists");
+            return Err("User already exists".to_string());
         }
 
+        let password_hash = password::hash_password(new_password, &self.config.password_hashing)?;
         self.config.users.push(User {
+            api_tokens: vec![],
             login: login.clone(),
-            password_hash_sha512: calculate_sha512(new_password),
-            is_admin: is_admin,
+            password_hash: Some(password_hash),
+            password_hash_sha512: None,
+            is_admin: None,
+            role: role,
+            totp: None,
         });
 
-        write_config_to_json(Path::new(&self.path), &self.config);
+        self.save_config_or_restore(previous)
+    }
 
-        Ok(())
+    fn add_mod(
+        &mut self,
+        archive_path: &Path,
+        metadata: ModMetadata,
+        install_manifest: InstallManifest,
+        previous_version: Option<AssettoMod>,
+    ) -> Result<AssettoMod, String> {
+        let previous = self.mod_database.mods.clone();
+        let mut assetto_mod = get_assetto_mod(archive_path)?;
+        assetto_mod.cars = metadata.cars;
+        assetto_mod.tracks = metadata.tracks;
+        assetto_mod.install_manifest = install_manifest;
+        assetto_mod.content_ids = metadata.content_ids;
+        assetto_mod.content_sources = metadata.content_sources;
+        assetto_mod.updated_at = Some(Utc::now());
+        if let Some(previous_version) = previous_version {
+            assetto_mod.version = previous_version.version + 1;
+            assetto_mod.history = previous_version.history.clone();
+            assetto_mod.history.push(previous_version.as_version());
+        }
+        let exists = self
+            .mod_database
+            .mods
+            .iter()
+            .any(|acmod| acmod.checksum_md5 == assetto_mod.checksum_md5);
+        if exists {
+            return Err("Mod already exists".to_string());
+        }
+
+        self.mod_database.mods.push(assetto_mod.clone());
+        self.save_mod_database_or_restore(previous)?;
+        Ok(assetto_mod)
     }
 
-    fn change_user_password(&mut self, login: &String, new_password: &String) -> Result<(), &str> {
-        for user in self.config.users.iter_mut() {
-            if *login == user.login {
-                use sha2::{Digest, Sha512};
-                let mut hasher = Sha512::new();
-                hasher.update(new_password);
-                let hash = hasher.finalize();
+    fn authenticate_api_token(&self, token: &str) -> Option<TokenOwner> {
+        let token_hash = api_token::hash_token(token);
+        self.config.users.iter().find_map(|user| {
+            let api_token = user
+                .api_tokens
+                .iter()
+                .find(|api_token| api_token.token_hash == token_hash)?;
+            Some(TokenOwner {
+                login: user.login.clone(),
+                role: user.role,
+                scope: api_token.scope,
+                needs_marking: api_token.needs_marking(Utc::now()),
+            })
+        })
+    }
 
-                user.password_hash_sha512 = format!("{:x}", hash);
-                write_config_to_json(Path::new(&self.path), &self.config);
+    fn begin_totp_enrollment(&mut self, login: &String) -> Result<TotpSettings, String> {
+        let previous = self.config.clone();
+        let user = self.find_user_mut(login)?;
+        if let Some(ref totp) = user.totp {
+            if totp.enabled {
+                return Err("Two-factor authentication is already enabled".to_string());
+            }
+        }
 
+        let totp = TotpSettings::new();
+        user.totp = Some(totp.clone());
+        self.save_config_or_restore(previous)?;
+        Ok(totp)
+    }
+
+    fn change_user_password(
+        &mut self,
+        login: &String,
+        new_password: &String,
+    ) -> Result<(), String> {
+        let previous = self.config.clone();
+        let password_hash = password::hash_password(new_password, &self.config.password_hashing)?;
+        for user in self.config.users.iter_mut() {
+            if *login == user.login {
+                user.password_hash = Some(password_hash);
+                user.password_hash_sha512 = None;
+                self.save_config_or_restore(previous)?;
+                self.sessions.revoke_user(login);
                 return Ok(());
             }
         }
-        Err("User not found")
+        Err("User not found".to_string())
     }
 
-    fn delete_mod(&mut self, checksum_md5: &String) -> Result<(), String> {
-        let index = self
-            .config
-            .mods
-            .iter()
-            .position(|acmod| acmod.checksum_md5 == *checksum_md5);
-        if let None = index {
-            return Err("Mod not found".to_string());
+    fn confirm_totp_enrollment(
+        &mut self,
+        login: &String,
+        code: &str,
+    ) -> Result<Vec<String>, String> {
+        let previous = self.config.clone();
+        let user = self.find_user_mut(login)?;
+        let totp = match user.totp {
+            Some(ref mut totp) if !totp.enabled => totp,
+            Some(_) => return Err("Two-factor authentication is already enabled".to_string()),
+            None => return Err("Two-factor authentication setup was not started".to_string()),
+        };
+
+        if !totp.verify_code(code, Utc::now().timestamp() as u64) {
+            return Err("Wrong code".to_string());
         }
-        let index = index.unwrap();
-
-        let storage_path = Path::new(&self.config.mod_storage_location);
-        let acmod = &self.config.mods[index];
+        totp.enabled = true;
+        let recovery_codes = totp.generate_recovery_codes();
+        self.save_config_or_restore(previous)?;
+        Ok(recovery_codes)
+    }
 
-        if let Err(error) = std::fs::remove_file(storage_path.join(&acmod.filename)) {
-            return Err(error.to_string());
+    fn delete_mod(&mut self, checksum_md5: &String) -> Result<(), String> {
+        let previous = self.mod_database.mods.clone();
+        let index = self.find_mod_index(checksum_md5)?;
+        let acmod = self.mod_database.mods.remove(index);
+        self.save_mod_database_or_restore(previous)?;
+
+        // files are removed only once database doesn't refer to them
+        self.uninstall_mod(&acmod);
+        remove_stored_file(&Path::new(&self.config.mod_storage_location).join(&acmod.filename));
+        for version in acmod.history.iter() {
+            let _ = std::fs::remove_dir_all(self.mod_history_path().join(&version.checksum_md5));
         }
-        self.config.mods.remove(index);
-        write_config_to_json(Path::new(&self.path), &self.config);
         Ok(())
     }
 
-    fn delete_user(&mut self, login: &String) -> Result<(), &str> {
+    fn delete_user(&mut self, login: &String) -> Result<(), String> {
+        let previous = self.config.clone();
+        if !self.user_exists(login) {
+            return Err("User not found".to_string());
+        }
+        if self.is_last_admin(login) {
+            return Err("Cannot delete the last administrator".to_string());
+        }
+
         self.config.users.retain(|user| user.login != *login);
+        self.save_config_or_restore(previous)?;
+        self.sessions.revoke_user(login);
         Ok(())
     }
 
+    fn disable_totp(&mut self, login: &String) -> Result<(), String> {
+        let previous = self.config.clone();
+        let user = self.find_user_mut(login)?;
+        if user.totp.take().is_none() {
+            return Err("Two-factor authentication is not enabled".to_string());
+        }
+        self.save_config_or_restore(previous)
+    }
+
     fn get_mod_list(&self) -> Vec<AssettoMod> {
-        return self.config.mods.clone();
+        return self.mod_database.mods.clone();
     }
 
     fn get_server_paths(&self) -> Vec<String> {
         return self.config.server_paths.clone();
     }
 
-    fn is_login_data_valid(&self, login: &String, password: &String) -> bool {
-        for user in self.config.users.iter() {
-            if *login == user.login {
-                return calculate_sha512(password) == user.password_hash_sha512;
-            }
+    fn get_stored_password(&self, login: &String) -> Option<StoredPassword> {
+        let user = self.config.users.iter().find(|user| user.login == *login)?;
+        if let Some(ref password_hash) = user.password_hash {
+            return Some(StoredPassword::Argon2(password_hash.clone()));
         }
-        false
+        user.password_hash_sha512
+            .clone()
+            .map(StoredPassword::LegacySha512)
     }
 
-    fn is_user_admin(&self, login: &String) -> bool {
-        for user in self.config.users.iter() {
-            if *login == user.login {
-                return user.is_admin;
+    fn is_totp_enabled(&self, login: &String) -> bool {
+        self.config.users.iter().any(|user| {
+            user.login == *login && user.totp.as_ref().map_or(false, |totp| totp.enabled)
+        })
+    }
+
+    fn mark_api_token_used(&mut self, token: &str) {
+        let previous = self.config.clone();
+        let token_hash = api_token::hash_token(token);
+        let api_token = self
+            .config
+            .users
+            .iter_mut()
+            .flat_map(|user| user.api_tokens.iter_mut())
+            .find(|api_token| api_token.token_hash == token_hash);
+        let marked = match api_token {
+            Some(api_token) => api_token.mark_used(Utc::now()),
+            None => false,
+        };
+        if marked {
+            if let Err(error) = self.save_config_or_restore(previous) {
+                println!("Error saving API token usage: {}", error);
             }
         }
-        false
+    }
+
+    fn needs_totp_enrollment(&self, login: &String) -> bool {
+        self.config.require_totp_for_admins
+            && self.get_user_role(login) == Some(Role::Admin)
+            && !self.is_totp_enabled(login)
+    }
+
+    fn get_user_role(&self, login: &String) -> Option<Role> {
+        self.config
+            .users
+            .iter()
+            .find(|user| user.login == *login)
+            .map(|user| user.role)
     }
 
     fn rebuild_mod_storage(&mut self, clear: bool) -> Result<(), String> {
+        let previous = self.mod_database.mods.clone();
         let _ = std::fs::create_dir_all(&self.config.mod_storage_location);
         let paths = std::fs::read_dir(&self.config.mod_storage_location);
         if let Err(error) = paths {
@@ -201,9 +746,9 @@ impl ConfigTrait for ConfigObject {
         }
 
         if clear {
-            self.config.mods.clear();
+            self.mod_database.mods.clear();
         }
-        let mut mod_list: Vec<AssettoMod> = self.config.mods.clone();
+        let mut mod_list: Vec<AssettoMod> = self.mod_database.mods.clone();
 
         for path in paths.unwrap() {
             if let Err(error) = path {
@@ -222,19 +767,209 @@ impl ConfigTrait for ConfigObject {
             mod_list.push(assetto_mod);
         }
 
-        self.config.mods = mod_list;
+        self.mod_database.mods = mod_list;
 
-        write_config_to_json(Path::new(&self.path), &self.config);
+        self.save_mod_database_or_restore(previous)
+    }
 
-        Ok(())
+    fn replace_password_hash(
+        &mut self,
+        login: &String,
+        verified: &StoredPassword,
+        password_hash: String,
+    ) -> Result<(), String> {
+        if self.get_stored_password(login).as_ref() != Some(verified) {
+            return Err("Password was changed meanwhile".to_string());
+        }
+        let previous = self.config.clone();
+        let user = self.find_user_mut(login)?;
+        user.password_hash = Some(password_hash);
+        user.password_hash_sha512 = None;
+        self.save_config_or_restore(previous)
+    }
+
+    fn reinstate_mod(&mut self, acmod: AssettoMod) -> Result<(), String> {
+        let previous = self.mod_database.mods.clone();
+        let history_path = self.mod_version_archive_path(&acmod.as_version());
+        let storage_path = Path::new(&self.config.mod_storage_location).join(&acmod.filename);
+
+        std::fs::copy(&history_path, &storage_path).map_err(|error| {
+            format!(
+                "Failed to move {} from mod history: {}",
+                acmod.filename, error
+            )
+        })?;
+        let _ = std::fs::remove_dir_all(history_path.parent().unwrap());
+
+        self.mod_database.mods.push(acmod);
+        self.save_mod_database_or_restore(previous)
+    }
+
+    fn retire_mod(&mut self, checksum_md5: &String) -> Result<AssettoMod, String> {
+        let previous = self.mod_database.mods.clone();
+        let index = self.find_mod_index(checksum_md5)?;
+        let acmod = &self.mod_database.mods[index];
+        let storage_path = Path::new(&self.config.mod_storage_location).join(&acmod.filename);
+        let history_path = self.mod_version_archive_path(&acmod.as_version());
+
+        // rename doesn't work across file systems, history can be on other disk than mod storage,
+        // archive is copied first and removed from mod storage only once database is saved
+        let created_history = !history_path.exists();
+        std::fs::create_dir_all(history_path.parent().unwrap())
+            .and_then(|_| std::fs::copy(&storage_path, &history_path))
+            .map_err(|error| {
+                format!(
+                    "Failed to move {} to mod history: {}",
+                    acmod.filename, error
+                )
+            })?;
+
+        let acmod = self.mod_database.mods.remove(index);
+        if let Err(error) = self.save_mod_database_or_restore(previous) {
+            if created_history {
+                let _ = std::fs::remove_dir_all(history_path.parent().unwrap());
+            }
+            return Err(error);
+        }
+
+        self.uninstall_mod(&acmod);
+        remove_stored_file(&storage_path);
+        Ok(acmod)
+    }
+
+    fn revoke_api_token(&mut self, login: &String, id: &String) -> Result<(), String> {
+        let previous = self.config.clone();
+        let user = match self
+            .config
+            .users
+            .iter_mut()
+            .find(|user| user.login == *login)
+        {
+            Some(user) => user,
+            None => return Err("User not found".to_string()),
+        };
+
+        let token_count = user.api_tokens.len();
+        user.api_tokens.retain(|api_token| api_token.id != *id);
+        if user.api_tokens.len() == token_count {
+            return Err("API token not found".to_string());
+        }
+        self.save_config_or_restore(previous)
+    }
+
+    fn set_user_role(&mut self, login: &String, role: Role) -> Result<(), String> {
+        let previous = self.config.clone();
+        if role != Role::Admin && self.is_last_admin(login) {
+            return Err(
+                "Cannot remove administrator rights from the last administrator".to_string(),
+            );
+        }
+
+        for user in self.config.users.iter_mut() {
+            if *login == user.login {
+                user.role = role;
+                return self.save_config_or_restore(previous);
+            }
+        }
+        Err("User not found".to_string())
+    }
+
+    fn set_require_totp_for_admins(&mut self, required: bool) -> Result<(), String> {
+        let previous = self.config.clone();
+        self.config.require_totp_for_admins = required;
+        self.save_config_or_restore(previous)
     }
 
-    fn set_secret_key(&mut self, secret_key: String) {
+    fn set_secret_key(&mut self, secret_key: String) -> Result<(), String> {
+        let previous = self.config.clone();
         self.config.secret_key = Some(secret_key);
-        write_config_to_json(Path::new(&self.path), &self.config);
+        self.save_config_or_restore(previous)
     }
 
     fn user_exists(&self, login: &String) -> bool {
         self.config.users.iter().any(|user| user.login == *login)
     }
+
+    fn user_has_permission(&self, login: &String, permission: Permission) -> bool {
+        match self.get_user_role(login) {
+            Some(role) => role.has_permission(permission),
+            None => false,
+        }
+    }
+
+    fn verify(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        if !self
+            .config
+            .users
+            .iter()
+            .any(|user| user.role == Role::Admin)
+        {
+            problems.push("No administrator account exists".to_string());
+        }
+
+        for server_path in self.config.server_paths.iter() {
+            if !Path::new(server_path).is_dir() {
+                problems.push(format!("Server path {} is not a directory", server_path));
+            }
+        }
+
+        let storage_path = Path::new(&self.config.mod_storage_location);
+        for acmod in self.mod_database.mods.iter() {
+            match get_assetto_mod(&storage_path.join(&acmod.filename)) {
+                Ok(stored_mod) => {
+                    if stored_mod.checksum_md5 != acmod.checksum_md5 {
+                        problems.push(format!(
+                            "Mod {} checksum mismatch, expected {}, found {}",
+                            acmod.filename, acmod.checksum_md5, stored_mod.checksum_md5
+                        ));
+                    }
+                }
+                Err(error) => {
+                    problems.push(format!("Mod {} is not readable: {}", acmod.filename, error))
+                }
+            }
+        }
+
+        if let Ok(paths) = std::fs::read_dir(storage_path) {
+            for path in paths.flatten() {
+                let file_name = path.file_name().to_string_lossy().to_string();
+                let known = self
+                    .mod_database
+                    .mods
+                    .iter()
+                    .any(|acmod| acmod.filename == file_name);
+                if !known {
+                    problems.push(format!("File {} is missing from mod database", file_name));
+                }
+            }
+        }
+
+        problems
+    }
+
+    fn verify_second_factor(&mut self, login: &String, code: &str) -> bool {
+        let previous = self.config.clone();
+        let user = match self.find_user_mut(login) {
+            Ok(user) => user,
+            Err(_) => return false,
+        };
+        let totp = match user.totp {
+            Some(ref mut totp) if totp.enabled => totp,
+            _ => return false,
+        };
+
+        // both change stored state, last used time step or remaining recovery codes
+        let valid =
+            totp.verify_code(code, Utc::now().timestamp() as u64) || totp.use_recovery_code(code);
+        // code is rejected when it can't be marked used, otherwise it could be used again
+        if valid {
+            if let Err(error) = self.save_config_or_restore(previous) {
+                println!("Error saving two-factor state for {}: {}", login, error);
+                return false;
+            }
+        }
+        valid
+    }
 }
@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::persistence::{any_copy_exists, read_with_fallback, write_json_atomically};
+use super::AssettoMod;
+use crate::install_manifest::InstallManifest;
+
+pub const MOD_DATABASE_VERSION: u32 = 4;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ModDatabase {
+    pub version: u32,
+    pub mods: Vec<AssettoMod>,
+}
+
+type Migration = fn(Value) -> Result<Value, String>;
+
+// MIGRATIONS[n] upgrades schema version n to n + 1
+const MIGRATIONS: [Migration; 4] = [
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+];
+
+// Version 0 is the bare mod array which used to be stored in config.json
+fn migrate_v0_to_v1(value: Value) -> Result<Value, String> {
+    if !value.is_array() {
+        return Err("Expected mod array in unversioned mod database".to_string());
+    }
+    Ok(serde_json::json!({
+        "version": 1,
+        "mods": value,
+    }))
+}
+
+// Version 2 added car and track metadata, mods imported before it have none
+fn migrate_v1_to_v2(mut value: Value) -> Result<Value, String> {
+    let mods = match value.get_mut("mods").and_then(|mods| mods.as_array_mut()) {
+        Some(mods) => mods,
+        None => return Err("Expected mod array in mod database version 1".to_string()),
+    };
+    for acmod in mods.iter_mut() {
+        if let Some(acmod) = acmod.as_object_mut() {
+            acmod.insert("cars".to_string(), serde_json::json!([]));
+            acmod.insert("tracks".to_string(), serde_json::json!([]));
+        }
+    }
+    value["version"] = serde_json::json!(2);
+    Ok(value)
+}
+
+// Version 3 added install manifest, files of mods installed before it are never uninstalled
+fn migrate_v2_to_v3(mut value: Value) -> Result<Value, String> {
+    let mods = match value.get_mut("mods").and_then(|mods| mods.as_array_mut()) {
+        Some(mods) => mods,
+        None => return Err("Expected mod array in mod database version 2".to_string()),
+    };
+    for acmod in mods.iter_mut() {
+        if let Some(acmod) = acmod.as_object_mut() {
+            acmod.insert(
+                "install_manifest".to_string(),
+                serde_json::json!({"server_paths": {}}),
+            );
+        }
+    }
+    value["version"] = serde_json::json!(3);
+    Ok(value)
+}
+
+// content/cars/<id>/... -> cars/<id>
+fn content_id_of(path: &str) -> Option<String> {
+    let mut parts = path.split(|character| character == '/' || character == '\\');
+    if parts.next() != Some("content") {
+        return None;
+    }
+    let kind = parts
+        .next()
+        .filter(|kind| *kind == "cars" || *kind == "tracks")?;
+    let id = parts.next()?;
+    // file directly in content/cars isn't a folder of the mod
+    parts.next()?;
+    Some(format!("{}/{}", kind, id))
+}
+
+// Version 4 added versioning, content IDs of older mods are taken from their install manifest
+fn migrate_v3_to_v4(mut value: Value) -> Result<Value, String> {
+    let mods = match value.get_mut("mods").and_then(|mods| mods.as_array_mut()) {
+        Some(mods) => mods,
+        None => return Err("Expected mod array in mod database version 3".to_string()),
+    };
+    for acmod in mods.iter_mut() {
+        let manifest: InstallManifest = acmod
+            .get("install_manifest")
+            .cloned()
+            .and_then(|manifest| serde_json::from_value(manifest).ok())
+            .unwrap_or_default();
+        let content_ids: BTreeSet<String> = manifest
+            .server_paths
+            .values()
+            .flat_map(|installed| installed.files.iter())
+            .filter_map(|file| content_id_of(file))
+            .collect();
+
+        if let Some(acmod) = acmod.as_object_mut() {
+            acmod.insert("content_ids".to_string(), serde_json::json!(content_ids));
+            acmod.insert("version".to_string(), serde_json::json!(1));
+        }
+    }
+    value["version"] = serde_json::json!(4);
+    Ok(value)
+}
+
+fn migrate(mut value: Value) -> Result<ModDatabase, String> {
+    let mut version = match value.get("version") {
+        Some(version) => match version.as_u64() {
+            Some(version) => version as u32,
+            None => return Err("Mod database version is not a number".to_string()),
+        },
+        None => 0,
+    };
+
+    if version > MOD_DATABASE_VERSION {
+        return Err(format!(
+            "Mod database version {} is newer than supported version {}",
+            version, MOD_DATABASE_VERSION
+        ));
+    }
+
+    while version < MOD_DATABASE_VERSION {
+        value = MIGRATIONS[version as usize](value)?;
+        version += 1;
+    }
+
+    serde_json::from_value(value).map_err(|error| error.to_string())
+}
+
+impl ModDatabase {
+    pub fn new() -> ModDatabase {
+        ModDatabase {
+            version: MOD_DATABASE_VERSION,
+            mods: vec![],
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<ModDatabase, String> {
+        if !any_copy_exists(path) {
+            return Ok(ModDatabase::new());
+        }
+
+        read_with_fallback(path, |content| {
+            let value: Value = serde_json::from_str(content).map_err(|error| error.to_string())?;
+            migrate(value)
+        })
+    }
+
+    pub fn save(&self, path: &Path, backup_count: usize) -> Result<(), String> {
+        write_json_atomically(path, self, backup_count)
+    }
+
+    pub fn import(&mut self, mods: Vec<AssettoMod>) {
+        for acmod in mods {
+            let exists = self
+                .mods
+                .iter()
+                .any(|existing| existing.checksum_md5 == acmod.checksum_md5);
+            if !exists {
+                self.mods.push(acmod);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_mod(checksum_md5: &str) -> AssettoMod {
+        AssettoMod {
+            checksum_md5: checksum_md5.to_string(),
+            filename: format!("{}.7z", checksum_md5),
+            size_in_bytes: 1024,
+            cars: vec![],
+            tracks: vec![],
+            install_manifest: InstallManifest::default(),
+            content_ids: vec![],
+            content_sources: BTreeMap::new(),
+            version: 1,
+            updated_at: None,
+            history: vec![],
+        }
+    }
+
+    #[test]
+    fn test_migrate_unversioned_mod_array() {
+        let value = serde_json::json!([
+            {"checksum_md5": "aaa", "filename": "aaa.7z", "size_in_bytes": 1},
+            {"checksum_md5": "bbb", "filename": "bbb.zip", "size_in_bytes": 2},
+        ]);
+
+        let database = migrate(value).unwrap();
+        assert_eq!(MOD_DATABASE_VERSION, database.version);
+        assert_eq!(2, database.mods.len());
+        assert_eq!("bbb.zip", database.mods[1].filename);
+    }
+
+    #[test]
+    fn test_migrate_v1_adds_empty_metadata() {
+        let value = serde_json::json!({
+            "version": 1,
+            "mods": [{"checksum_md5": "aaa", "filename": "aaa.7z", "size_in_bytes": 1}],
+        });
+
+        let database = migrate(value).unwrap();
+        assert_eq!(MOD_DATABASE_VERSION, database.version);
+        assert!(database.mods[0].cars.is_empty());
+        assert!(database.mods[0].tracks.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_v3_takes_content_ids_from_manifest() {
+        let value = serde_json::json!({
+            "version": 3,
+            "mods": [{
+                "checksum_md5": "aaa",
+                "filename": "aaa.7z",
+                "size_in_bytes": 1,
+                "install_manifest": {"server_paths": {
+                    "/srv/ac1": {"files": [
+                        "content/cars/abc_gt3/data.acd",
+                        "content/cars/abc_gt3/data/car.ini",
+                        "content/tracks/ring/data/surfaces.ini",
+                    ]},
+                    "/srv/ac2": {"files": ["content/cars/abc_gt3/data.acd"]},
+                }},
+            }],
+        });
+
+        let database = migrate(value).unwrap();
+        assert_eq!(
+            vec!["cars/abc_gt3", "tracks/ring"],
+            database.mods[0].content_ids
+        );
+        assert_eq!(1, database.mods[0].version);
+        assert!(database.mods[0].history.is_empty());
+    }
+
+    #[test]
+    fn test_migrate_current_version() {
+        let value = serde_json::json!({
+            "version": MOD_DATABASE_VERSION,
+            "mods": [{
+                "checksum_md5": "aaa",
+                "filename": "aaa.7z",
+                "size_in_bytes": 1,
+                "cars": [{"id": "abc_gt3", "name": "ABC GT3"}],
+                "tracks": [],
+            }],
+        });
+
+        let database = migrate(value).unwrap();
+        assert_eq!(1, database.mods.len());
+        assert_eq!("abc_gt3", database.mods[0].cars[0].id);
+    }
+
+    #[test]
+    fn test_migrate_newer_version() {
+        let value = serde_json::json!({
+            "version": MOD_DATABASE_VERSION + 1,
+            "mods": [],
+        });
+
+        assert!(migrate(value).is_err());
+    }
+
+    #[test]
+    fn test_import_skips_known_mods() {
+        let mut database = ModDatabase::new();
+        database.import(vec![test_mod("aaa"), test_mod("bbb")]);
+        database.import(vec![test_mod("bbb"), test_mod("ccc")]);
+
+        assert_eq!(3, database.mods.len());
+    }
+}
@@ -0,0 +1,177 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+
+use crate::common::constant_time_eq;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PasswordHashingParams {
+    pub memory_cost_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordHashingParams {
+    fn default() -> Self {
+        PasswordHashingParams {
+            memory_cost_kib: 19456,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+fn argon2_from_params(params: &PasswordHashingParams) -> Result<Argon2<'static>, String> {
+    let params = Params::new(
+        params.memory_cost_kib,
+        params.iterations,
+        params.parallelism,
+        None,
+    )
+    .map_err(|error| error.to_string())?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// Returns Argon2id hash with random salt in PHC string format.
+pub fn hash_password(password: &str, params: &PasswordHashingParams) -> Result<String, String> {
+    let argon2 = argon2_from_params(params)?;
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = argon2
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|error| error.to_string())?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    let parsed_hash = match PasswordHash::new(password_hash) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(_) => return false,
+    };
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_ok()
+}
+
+/// True if password_hash was created with different algorithm or cost parameters.
+pub fn needs_rehash(password_hash: &str, params: &PasswordHashingParams) -> bool {
+    let parsed_hash = match PasswordHash::new(password_hash) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(_) => return true,
+    };
+    if parsed_hash.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+    match Params::try_from(&parsed_hash) {
+        Ok(hash_params) => {
+            hash_params.m_cost() != params.memory_cost_kib
+                || hash_params.t_cost() != params.iterations
+                || hash_params.p_cost() != params.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
+// Unsalted hashes used before Argon2id, only accepted to migrate existing users
+pub fn verify_legacy_sha512(password: &str, password_hash_sha512: &str) -> bool {
+    use sha2::{Digest, Sha512};
+    let mut hasher = Sha512::new();
+    hasher.update(password);
+    let hash = hasher.finalize();
+    constant_time_eq(&format!("{:x}", hash), password_hash_sha512)
+}
+
+/// Password hash of user, copied out of config so it can be verified without holding config lock.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoredPassword {
+    Argon2(String),
+    LegacySha512(String),
+}
+
+impl StoredPassword {
+    pub fn verify(&self, password: &str) -> bool {
+        match self {
+            StoredPassword::Argon2(password_hash) => verify_password(password, password_hash),
+            StoredPassword::LegacySha512(password_hash_sha512) => {
+                verify_legacy_sha512(password, password_hash_sha512)
+            }
+        }
+    }
+
+    /// Legacy SHA-512 hashes and hashes with outdated parameters are replaced on login.
+    pub fn needs_rehash(&self, params: &PasswordHashingParams) -> bool {
+        match self {
+            StoredPassword::Argon2(password_hash) => needs_rehash(password_hash, params),
+            StoredPassword::LegacySha512(_) => true,
+        }
+    }
+}
+
+/// Hashes password of user which doesn't exist, so the answer takes about as long as for existing
+/// user and user names can't be told apart by response time.
+pub fn verify_unknown_user(password: &str, params: &PasswordHashingParams) -> bool {
+    let _ = hash_password(password, params);
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fast_params() -> PasswordHashingParams {
+        PasswordHashingParams {
+            memory_cost_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
+    #[test]
+    fn test_hash_password_uses_salt() {
+        let first_hash = hash_password("hunter2", &fast_params()).unwrap();
+        let second_hash = hash_password("hunter2", &fast_params()).unwrap();
+
+        assert!(first_hash.starts_with("$argon2id$"));
+        assert_ne!(first_hash, second_hash);
+    }
+
+    #[test]
+    fn test_verify_password() {
+        let hash = hash_password("hunter2", &fast_params()).unwrap();
+
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+        assert!(!verify_password("hunter2", "not a phc string"));
+    }
+
+    #[test]
+    fn test_needs_rehash() {
+        let hash = hash_password("hunter2", &fast_params()).unwrap();
+        let mut stronger_params = fast_params();
+        stronger_params.iterations = 3;
+
+        assert!(!needs_rehash(&hash, &fast_params()));
+        assert!(needs_rehash(&hash, &stronger_params));
+    }
+
+    #[test]
+    fn test_verify_legacy_sha512() {
+        let hunter2_hash = "6b97ed68d14eb3f1aa959ce5d49c7dc612e1eb1dafd73b1e705847483fd6a6c809f2ceb4e8df6ff9984c6298ff0285cace6614bf8daa9f0070101b6c89899e22";
+
+        assert!(verify_legacy_sha512("hunter2", hunter2_hash));
+        assert!(!verify_legacy_sha512("hunter3", hunter2_hash));
+    }
+
+    #[test]
+    fn test_stored_password() {
+        let hash = hash_password("hunter2", &fast_params()).unwrap();
+        let argon2 = StoredPassword::Argon2(hash);
+        let legacy = StoredPassword::LegacySha512("6b97ed68d14eb3f1aa959ce5d49c7dc612e1eb1dafd73b1e705847483fd6a6c809f2ceb4e8df6ff9984c6298ff0285cace6614bf8daa9f0070101b6c89899e22".to_string());
+
+        assert!(argon2.verify("hunter2"));
+        assert!(legacy.verify("hunter2"));
+        assert!(!argon2.needs_rehash(&fast_params()));
+        assert!(legacy.needs_rehash(&fast_params()));
+        assert!(!verify_unknown_user("hunter2", &fast_params()));
+    }
+}
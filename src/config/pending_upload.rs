@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use super::session::generate_token;
+use crate::install_manifest::FileConflict;
+
+/// Upload which conflicts with installed mods, waiting for uploader or admin to decide what to do.
+#[derive(Clone, Debug)]
+pub struct PendingUpload {
+    pub id: String,
+    pub file_name: String,
+    pub uploader: String,
+    pub conflicts: Vec<FileConflict>,
+    pub created_at: SystemTime,
+}
+
+/// Archives are kept in <path>/<id>/<file_name> until resolved, list itself is in-memory,
+/// so pending uploads are lost on server restart.
+pub struct PendingUploads {
+    path: PathBuf,
+    uploads: Mutex<Vec<PendingUpload>>,
+}
+
+impl PendingUploads {
+    /// Removes archives left over from previous run.
+    pub fn new(path: &Path) -> PendingUploads {
+        let _ = std::fs::remove_dir_all(path);
+        PendingUploads {
+            path: path.to_path_buf(),
+            uploads: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn add(
+        &self,
+        source_path: &Path,
+        file_name: &str,
+        uploader: &str,
+        conflicts: Vec<FileConflict>,
+    ) -> Result<PendingUpload, String> {
+        let upload = PendingUpload {
+            id: generate_token(),
+            file_name: file_name.to_string(),
+            uploader: uploader.to_string(),
+            conflicts: conflicts,
+            created_at: SystemTime::now(),
+        };
+
+        let archive_path = self.archive_path(&upload);
+        std::fs::create_dir_all(archive_path.parent().unwrap())
+            .and_then(|_| std::fs::copy(source_path, &archive_path))
+            .map_err(|error| {
+                let _ = std::fs::remove_dir_all(self.path.join(&upload.id));
+                format!("Failed to keep {}: {}", file_name, error)
+            })?;
+
+        self.uploads.lock().unwrap().push(upload.clone());
+        Ok(upload)
+    }
+
+    pub fn archive_path(&self, upload: &PendingUpload) -> PathBuf {
+        self.path.join(&upload.id).join(&upload.file_name)
+    }
+
+    pub fn list(&self) -> Vec<PendingUpload> {
+        self.uploads.lock().unwrap().clone()
+    }
+
+    /// Removes upload from the list, archive stays on disk until discard.
+    pub fn take(&self, id: &str) -> Option<PendingUpload> {
+        let mut uploads = self.uploads.lock().unwrap();
+        let index = uploads.iter().position(|upload| upload.id == id)?;
+        Some(uploads.remove(index))
+    }
+
+    pub fn discard(&self, upload: &PendingUpload) {
+        if let Err(error) = std::fs::remove_dir_all(self.path.join(&upload.id)) {
+            println!(
+                "Error removing pending upload {}: {}",
+                upload.file_name, error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_add_take_and_discard() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let source_path = temp_dir.path().join("upload");
+        std::fs::write(&source_path, "archive").unwrap();
+        let pending_uploads = PendingUploads::new(&temp_dir.path().join("pending_uploads"));
+
+        let upload = pending_uploads
+            .add(&source_path, "car.zip", "admin", vec![])
+            .unwrap();
+        let archive_path = pending_uploads.archive_path(&upload);
+        assert_eq!("archive", std::fs::read_to_string(&archive_path).unwrap());
+        assert_eq!(1, pending_uploads.list().len());
+
+        assert!(pending_uploads.take("unknown").is_none());
+        let upload = pending_uploads.take(&upload.id).unwrap();
+        assert!(pending_uploads.list().is_empty());
+        assert!(archive_path.exists());
+
+        pending_uploads.discard(&upload);
+        assert!(!archive_path.exists());
+    }
+
+    #[test]
+    fn test_new_removes_leftovers() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let path = temp_dir.path().join("pending_uploads");
+        std::fs::create_dir_all(path.join("old")).unwrap();
+
+        PendingUploads::new(&path);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,270 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use super::session::generate_token;
+use crate::archive_unpacker::{Volume, VolumeKind};
+
+// sets nobody completed within a day are dropped when next volume arrives
+const MAX_SET_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_SETS_PER_UPLOADER: usize = 4;
+// volumes of all sets together
+const MAX_TOTAL_SIZE_GB: u64 = 20;
+
+/// Volumes of multi-volume archive received so far, volume sets of different uploaders are kept
+/// apart even when named the same.
+#[derive(Clone, Debug)]
+pub struct PendingVolumeSet {
+    pub id: String,
+    pub set_name: String,
+    pub kind: VolumeKind,
+    pub uploader: String,
+    /// Volume number and file name, ordered by number.
+    pub volumes: Vec<(u32, String)>,
+    /// Bytes of all volumes received.
+    pub size: u64,
+    pub created_at: SystemTime,
+}
+
+impl PendingVolumeSet {
+    pub fn joined_file_name(&self) -> String {
+        Volume {
+            set_name: self.set_name.clone(),
+            kind: self.kind,
+            number: 1,
+        }
+        .joined_file_name()
+    }
+}
+
+/// Volumes are kept in <path>/<id>/<file_name> under their original names, unrar looks for next
+/// volume next to the previous one. List itself is in-memory, so sets are lost on server restart.
+pub struct PendingVolumes {
+    path: PathBuf,
+    sets: Mutex<Vec<PendingVolumeSet>>,
+}
+
+impl PendingVolumes {
+    /// Removes volumes left over from previous run.
+    pub fn new(path: &Path) -> PendingVolumes {
+        let _ = std::fs::remove_dir_all(path);
+        PendingVolumes {
+            path: path.to_path_buf(),
+            sets: Mutex::new(vec![]),
+        }
+    }
+
+    /// Adds volume to set of the same name and uploader, volume uploaded again replaces the old one.
+    /// Sets older than a day are removed first, new volume must fit into limits of pending sets.
+    pub fn add(
+        &self,
+        source_path: &Path,
+        file_name: &str,
+        volume: &Volume,
+        uploader: &str,
+        now: SystemTime,
+    ) -> Result<PendingVolumeSet, String> {
+        let mut sets = self.sets.lock().unwrap();
+        self.remove_expired(&mut sets, now);
+
+        let size = std::fs::metadata(source_path)
+            .map(|metadata| metadata.len())
+            .map_err(|error| format!("Failed to keep {}: {}", file_name, error))?;
+        let index = sets.iter().position(|set| {
+            set.uploader == uploader && set.set_name == volume.set_name && set.kind == volume.kind
+        });
+        let replaced_size = index.map_or(0, |index| {
+            let directory = self.path.join(&sets[index].id);
+            sets[index]
+                .volumes
+                .iter()
+                .filter(|(number, _)| *number == volume.number)
+                .filter_map(|(_, old_file_name)| {
+                    std::fs::metadata(directory.join(old_file_name)).ok()
+                })
+                .map(|metadata| metadata.len())
+                .sum()
+        });
+        let total_size: u64 = sets.iter().map(|set| set.size).sum();
+        if total_size - replaced_size + size > MAX_TOTAL_SIZE_GB * 1024 * 1024 * 1024 {
+            return Err(format!(
+                "Pending volumes would exceed {} GB, complete or discard other sets first",
+                MAX_TOTAL_SIZE_GB
+            ));
+        }
+
+        let index = match index {
+            Some(index) => index,
+            None => {
+                let uploader_sets = sets.iter().filter(|set| set.uploader == uploader).count();
+                if uploader_sets >= MAX_SETS_PER_UPLOADER {
+                    return Err(format!(
+                        "{} incomplete volume sets of {} are pending already",
+                        uploader_sets, uploader
+                    ));
+                }
+                sets.push(PendingVolumeSet {
+                    id: generate_token(),
+                    set_name: volume.set_name.clone(),
+                    kind: volume.kind,
+                    uploader: uploader.to_string(),
+                    volumes: vec![],
+                    size: 0,
+                    created_at: now,
+                });
+                sets.len() - 1
+            }
+        };
+        let set = &mut sets[index];
+
+        let directory = self.path.join(&set.id);
+        if let Some(position) = set
+            .volumes
+            .iter()
+            .position(|(number, _)| *number == volume.number)
+        {
+            let (_, old_file_name) = set.volumes.remove(position);
+            let _ = std::fs::remove_file(directory.join(old_file_name));
+            set.size -= replaced_size;
+        }
+        std::fs::create_dir_all(&directory)
+            .and_then(|_| std::fs::copy(source_path, directory.join(file_name)))
+            .map_err(|error| format!("Failed to keep {}: {}", file_name, error))?;
+        set.volumes.push((volume.number, file_name.to_string()));
+        set.volumes.sort();
+        set.size += size;
+        Ok(set.clone())
+    }
+
+    fn remove_expired(&self, sets: &mut Vec<PendingVolumeSet>, now: SystemTime) {
+        let (expired, kept): (Vec<PendingVolumeSet>, Vec<PendingVolumeSet>) =
+            sets.drain(..).partition(|set| {
+                now.duration_since(set.created_at)
+                    .map_or(false, |age| age > MAX_SET_AGE)
+            });
+        *sets = kept;
+        for set in expired {
+            self.discard(&set);
+        }
+    }
+
+    /// Volume number and path, ordered by number.
+    pub fn volume_paths(&self, set: &PendingVolumeSet) -> Vec<(u32, PathBuf)> {
+        set.volumes
+            .iter()
+            .map(|(number, file_name)| (*number, self.path.join(&set.id).join(file_name)))
+            .collect()
+    }
+
+    pub fn list(&self) -> Vec<PendingVolumeSet> {
+        self.sets.lock().unwrap().clone()
+    }
+
+    /// Removes set from the list, volumes stay on disk until discard.
+    pub fn take(&self, id: &str) -> Option<PendingVolumeSet> {
+        let mut sets = self.sets.lock().unwrap();
+        let index = sets.iter().position(|set| set.id == id)?;
+        Some(sets.remove(index))
+    }
+
+    pub fn discard(&self, set: &PendingVolumeSet) {
+        if let Err(error) = std::fs::remove_dir_all(self.path.join(&set.id)) {
+            println!(
+                "Error removing volumes of {}: {}",
+                set.joined_file_name(),
+                error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    use crate::archive_unpacker::parse_volume_name;
+
+    #[test]
+    fn test_volumes_are_grouped_by_set_and_uploader() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let source_path = temp_dir.path().join("upload");
+        std::fs::write(&source_path, "volume").unwrap();
+        let pending_volumes = PendingVolumes::new(&temp_dir.path().join("pending_volumes"));
+        let add = |file_name: &str, uploader: &str| {
+            let volume = parse_volume_name(file_name).unwrap();
+            pending_volumes
+                .add(
+                    &source_path,
+                    file_name,
+                    &volume,
+                    uploader,
+                    SystemTime::now(),
+                )
+                .unwrap()
+        };
+
+        let set = add("pack.part2.rar", "admin");
+        add("other.7z.001", "admin");
+        add("pack.part1.rar", "user");
+        add("pack.part2.rar", "admin");
+        let set_again = add("pack.part1.rar", "admin");
+        assert_eq!(set.id, set_again.id);
+        assert_eq!(
+            vec![
+                (1, "pack.part1.rar".to_string()),
+                (2, "pack.part2.rar".to_string())
+            ],
+            set_again.volumes
+        );
+        assert_eq!("pack.tar", set_again.joined_file_name());
+        assert_eq!(12, set_again.size);
+        assert_eq!(3, pending_volumes.list().len());
+
+        let paths = pending_volumes.volume_paths(&set_again);
+        assert!(paths.iter().all(|(_, path)| path.is_file()));
+        let set = pending_volumes.take(&set.id).unwrap();
+        assert_eq!(2, pending_volumes.list().len());
+        pending_volumes.discard(&set);
+        assert!(paths.iter().all(|(_, path)| !path.exists()));
+    }
+
+    #[test]
+    fn test_pending_sets_are_limited() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let source_path = temp_dir.path().join("upload");
+        std::fs::write(&source_path, "volume").unwrap();
+        let pending_volumes = PendingVolumes::new(&temp_dir.path().join("pending_volumes"));
+        let now = SystemTime::now();
+        let add = |file_name: &str, now: SystemTime| {
+            let volume = parse_volume_name(file_name).unwrap();
+            pending_volumes.add(&source_path, file_name, &volume, "admin", now)
+        };
+
+        for set_name in ["a", "b", "c", "d"] {
+            add(&format!("{}.part1.rar", set_name), now).unwrap();
+        }
+        assert_eq!(
+            "4 incomplete volume sets of admin are pending already",
+            add("e.part1.rar", now).unwrap_err()
+        );
+        // more volumes of pending sets are still accepted
+        assert!(add("a.part2.rar", now).is_ok());
+
+        let expired_path = pending_volumes.volume_paths(&pending_volumes.list()[0])[0]
+            .1
+            .clone();
+        let later = now + MAX_SET_AGE + Duration::from_secs(1);
+        let set = add("e.part1.rar", later).unwrap();
+        assert_eq!(
+            vec![set.id],
+            pending_volumes
+                .list()
+                .into_iter()
+                .map(|set| set.id)
+                .collect::<Vec<String>>()
+        );
+        assert!(!expired_path.exists());
+    }
+}
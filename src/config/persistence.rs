@@ -0,0 +1,172 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+fn backup_path(path: &Path, index: usize) -> PathBuf {
+    let mut file_name = path.file_name().unwrap().to_os_string();
+    file_name.push(format!(".{}", index));
+    path.with_file_name(file_name)
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+fn sync_parent_directory(path: &Path) -> std::io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if parent.as_os_str().len() > 0 => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+// path.1 is the newest backup, path.<backup_count> the oldest one
+fn rotate_backups(path: &Path, backup_count: usize) -> std::io::Result<()> {
+    if backup_count == 0 || !path.exists() {
+        return Ok(());
+    }
+
+    for index in (1..backup_count).rev() {
+        let source = backup_path(path, index);
+        if source.exists() {
+            std::fs::rename(&source, backup_path(path, index + 1))?;
+        }
+    }
+    std::fs::copy(path, backup_path(path, 1))?;
+    Ok(())
+}
+
+/// Writes value to temporary file, syncs it and renames it over path,
+/// so path always contains either old or new content, never a partial write.
+pub fn write_json_atomically<T: Serialize>(
+    path: &Path,
+    value: &T,
+    backup_count: usize,
+) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value).map_err(|error| error.to_string())?;
+    let temporary_path = temporary_path(path);
+
+    let write_result = (|| -> std::io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temporary_path)?;
+        file.write_all(json.as_bytes())?;
+        file.sync_all()?;
+
+        rotate_backups(path, backup_count)?;
+        std::fs::rename(&temporary_path, path)?;
+        sync_parent_directory(path)
+    })();
+
+    if let Err(error) = write_result {
+        let _ = std::fs::remove_file(&temporary_path);
+        return Err(format!("Failed to write {}: {}", path.display(), error));
+    }
+    Ok(())
+}
+
+/// Parses path, if it is missing or corrupt then backups are tried from newest to oldest.
+pub fn read_with_fallback<T, F>(path: &Path, parse: F) -> Result<T, String>
+where
+    F: Fn(&str) -> Result<T, String>,
+{
+    let mut candidates = vec![path.to_path_buf()];
+    let mut index = 1;
+    while backup_path(path, index).exists() {
+        candidates.push(backup_path(path, index));
+        index += 1;
+    }
+
+    let mut first_error: Option<String> = None;
+    for candidate in candidates {
+        let result = std::fs::read_to_string(&candidate)
+            .map_err(|error| error.to_string())
+            .and_then(|content| parse(&content));
+        match result {
+            Ok(value) => {
+                if let Some(ref error) = first_error {
+                    println!(
+                        "Error loading {}: {}, using backup {}",
+                        path.display(),
+                        error,
+                        candidate.display()
+                    );
+                }
+                return Ok(value);
+            }
+            Err(error) => {
+                if first_error.is_none() {
+                    first_error = Some(error);
+                }
+            }
+        }
+    }
+
+    Err(first_error.unwrap())
+}
+
+pub fn any_copy_exists(path: &Path) -> bool {
+    path.exists() || backup_path(path, 1).exists()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    fn parse_number(content: &str) -> Result<u32, String> {
+        serde_json::from_str(content).map_err(|error| error.to_string())
+    }
+
+    #[test]
+    fn test_write_json_atomically_keeps_backups() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let path = temp_dir.path().join("value.json");
+
+        for value in 1..=4 {
+            write_json_atomically(&path, &value, 2).unwrap();
+        }
+
+        assert_eq!(4, read_with_fallback(&path, parse_number).unwrap());
+        assert_eq!(
+            3,
+            parse_number(&std::fs::read_to_string(backup_path(&path, 1)).unwrap()).unwrap()
+        );
+        assert_eq!(
+            2,
+            parse_number(&std::fs::read_to_string(backup_path(&path, 2)).unwrap()).unwrap()
+        );
+        assert!(!backup_path(&path, 3).exists());
+        assert!(!temporary_path(&path).exists());
+    }
+
+    #[test]
+    fn test_read_with_fallback_uses_newest_valid_backup() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let path = temp_dir.path().join("value.json");
+
+        write_json_atomically(&path, &1, 3).unwrap();
+        write_json_atomically(&path, &2, 3).unwrap();
+        write_json_atomically(&path, &3, 3).unwrap();
+        std::fs::write(&path, "{\"trunc").unwrap();
+        std::fs::write(backup_path(&path, 1), "").unwrap();
+
+        assert_eq!(1, read_with_fallback(&path, parse_number).unwrap());
+    }
+
+    #[test]
+    fn test_read_with_fallback_no_valid_copy() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let path = temp_dir.path().join("value.json");
+
+        assert!(!any_copy_exists(&path));
+        assert!(read_with_fallback(&path, parse_number).is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,
+    Uploader,
+    Operator,
+    Admin,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Permission {
+    DownloadMods,
+    UploadMods,
+    DeleteMods,
+    ManageUsers,
+}
+
+impl Default for Role {
+    fn default() -> Self {
+        Role::Viewer
+    }
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Viewer, Role::Uploader, Role::Operator, Role::Admin];
+
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::DownloadMods => true,
+            Permission::UploadMods => *self != Role::Viewer,
+            Permission::DeleteMods => *self == Role::Operator || *self == Role::Admin,
+            Permission::ManageUsers => *self == Role::Admin,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Uploader => "uploader",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn display_name(&self) -> &'static str {
+        match self {
+            Role::Viewer => "Viewer",
+            Role::Uploader => "Uploader",
+            Role::Operator => "Server operator",
+            Role::Admin => "Administrator",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .iter()
+            .find(|role| role.as_str() == value)
+            .copied()
+            .ok_or(format!("Unknown role: {}", value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_permissions() {
+        assert!(Role::Viewer.has_permission(Permission::DownloadMods));
+        assert!(!Role::Viewer.has_permission(Permission::UploadMods));
+
+        assert!(Role::Uploader.has_permission(Permission::UploadMods));
+        assert!(!Role::Uploader.has_permission(Permission::DeleteMods));
+
+        assert!(Role::Operator.has_permission(Permission::DeleteMods));
+        assert!(!Role::Operator.has_permission(Permission::ManageUsers));
+
+        assert!(Role::Admin.has_permission(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn test_role_from_str() {
+        for role in Role::ALL.iter() {
+            assert_eq!(*role, role.as_str().parse().unwrap());
+        }
+        assert!("superuser".parse::<Role>().is_err());
+    }
+}
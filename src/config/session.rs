@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SessionSettings {
+    pub idle_timeout_minutes: u64,
+    pub absolute_timeout_hours: u64,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        SessionSettings {
+            idle_timeout_minutes: 120,
+            absolute_timeout_hours: 24,
+        }
+    }
+}
+
+impl SessionSettings {
+    fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_minutes * 60)
+    }
+
+    fn absolute_timeout(&self) -> Duration {
+        Duration::from_secs(self.absolute_timeout_hours * 60 * 60)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub id: String,
+    pub csrf_token: String,
+    pub login: String,
+    pub remote_address: Option<String>,
+    // password was accepted, but second factor wasn't entered yet
+    pub pending_totp: bool,
+    pub created_at: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+impl Session {
+    fn is_expired(&self, settings: &SessionSettings, now: SystemTime) -> bool {
+        let elapsed = |since: SystemTime| now.duration_since(since).unwrap_or_default();
+        elapsed(self.last_seen) > settings.idle_timeout()
+            || elapsed(self.created_at) > settings.absolute_timeout()
+    }
+}
+
+/// In-memory store of logged in sessions, cookies only hold the session id.
+/// Sessions are lost on server restart.
+#[derive(Default)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+pub(super) fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+impl SessionStore {
+    pub fn create(
+        &self,
+        login: &str,
+        remote_address: Option<String>,
+        settings: &SessionSettings,
+        now: SystemTime,
+    ) -> String {
+        self.insert(login, remote_address, false, settings, now)
+    }
+
+    /// Session which only allows entering second factor, replaced with full session afterwards.
+    pub fn create_pending_totp(
+        &self,
+        login: &str,
+        remote_address: Option<String>,
+        settings: &SessionSettings,
+        now: SystemTime,
+    ) -> String {
+        self.insert(login, remote_address, true, settings, now)
+    }
+
+    fn insert(
+        &self,
+        login: &str,
+        remote_address: Option<String>,
+        pending_totp: bool,
+        settings: &SessionSettings,
+        now: SystemTime,
+    ) -> String {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| !session.is_expired(settings, now));
+
+        let id = generate_token();
+        sessions.insert(
+            id.clone(),
+            Session {
+                id: id.clone(),
+                csrf_token: generate_token(),
+                login: login.to_string(),
+                remote_address: remote_address,
+                pending_totp: pending_totp,
+                created_at: now,
+                last_seen: now,
+            },
+        );
+        id
+    }
+
+    /// Returns session and marks it as used, expired sessions are removed.
+    pub fn touch(&self, id: &str, settings: &SessionSettings, now: SystemTime) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let expired = match sessions.get_mut(id) {
+            Some(session) => {
+                if !session.is_expired(settings, now) {
+                    session.last_seen = now;
+                    return Some(session.clone());
+                }
+                true
+            }
+            None => false,
+        };
+        if expired {
+            sessions.remove(id);
+        }
+        None
+    }
+
+    pub fn list_for_user(&self, login: &str) -> Vec<Session> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut user_sessions: Vec<Session> = sessions
+            .values()
+            .filter(|session| session.login == login && !session.pending_totp)
+            .cloned()
+            .collect();
+        user_sessions.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        user_sessions
+    }
+
+    /// Removes session only if it belongs to login, returns false otherwise.
+    pub fn revoke(&self, login: &str, id: &str) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some(session) if session.login == login => {
+                sessions.remove(id);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes every session of login, returns number of removed sessions.
+    pub fn revoke_user(&self, login: &str) -> usize {
+        let mut sessions = self.sessions.lock().unwrap();
+        let count = sessions.len();
+        sessions.retain(|_, session| session.login != login);
+        count - sessions.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> SessionSettings {
+        SessionSettings {
+            idle_timeout_minutes: 10,
+            absolute_timeout_hours: 1,
+        }
+    }
+
+    fn minutes(count: u64) -> Duration {
+        Duration::from_secs(count * 60)
+    }
+
+    #[test]
+    fn test_session_touch() {
+        let store = SessionStore::default();
+        let now = SystemTime::now();
+        let id = store.create("admin", None, &settings(), now);
+
+        assert_eq!(64, id.len());
+        let session = store.touch(&id, &settings(), now).unwrap();
+        assert_eq!("admin", session.login);
+        assert_ne!(session.id, session.csrf_token);
+        assert!(!session.pending_totp);
+        assert!(store.touch("unknown", &settings(), now).is_none());
+    }
+
+    #[test]
+    fn test_pending_totp_session_is_not_listed() {
+        let store = SessionStore::default();
+        let now = SystemTime::now();
+        let id = store.create_pending_totp("admin", None, &settings(), now);
+
+        assert!(store.touch(&id, &settings(), now).unwrap().pending_totp);
+        assert!(store.list_for_user("admin").is_empty());
+    }
+
+    #[test]
+    fn test_session_idle_timeout() {
+        let store = SessionStore::default();
+        let now = SystemTime::now();
+        let id = store.create("admin", None, &settings(), now);
+
+        // every touch resets idle timer
+        let now = now + minutes(9);
+        assert!(store.touch(&id, &settings(), now).is_some());
+        let now = now + minutes(9);
+        assert!(store.touch(&id, &settings(), now).is_some());
+
+        let now = now + minutes(11);
+        assert!(store.touch(&id, &settings(), now).is_none());
+        assert!(store.list_for_user("admin").is_empty());
+    }
+
+    #[test]
+    fn test_session_absolute_timeout() {
+        let store = SessionStore::default();
+        let mut now = SystemTime::now();
+        let id = store.create("admin", None, &settings(), now);
+
+        for _ in 0..6 {
+            now += minutes(9);
+            assert!(store.touch(&id, &settings(), now).is_some());
+        }
+        now += minutes(9);
+        assert!(store.touch(&id, &settings(), now).is_none());
+    }
+
+    #[test]
+    fn test_session_revoke() {
+        let store = SessionStore::default();
+        let now = SystemTime::now();
+        let first = store.create("admin", None, &settings(), now);
+        let second = store.create("admin", Some("127.0.0.1".to_string()), &settings(), now);
+        let other = store.create("user", None, &settings(), now);
+
+        assert!(!store.revoke("user", &first));
+        assert!(store.revoke("admin", &first));
+        assert!(store.touch(&first, &settings(), now).is_none());
+        assert_eq!(1, store.list_for_user("admin").len());
+
+        store.create("admin", None, &settings(), now);
+        assert_eq!(2, store.revoke_user("admin"));
+        assert!(store.touch(&second, &settings(), now).is_none());
+        assert!(store.touch(&other, &settings(), now).is_some());
+    }
+}
@@ -0,0 +1,210 @@
+use hmac::{Hmac, Mac, NewMac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+use super::api_token::hash_token;
+use crate::common::constant_time_eq;
+
+const ISSUER: &str = "AssettoSync";
+const CODE_DIGITS: u32 = 6;
+const TIME_STEP_SECONDS: u64 = 30;
+// codes from previous and next time step are accepted too, phone clocks drift
+const ALLOWED_STEP_SKEW: u64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TotpSettings {
+    pub secret: String,
+    pub enabled: bool,
+    #[serde(default)]
+    pub last_used_step: Option<u64>,
+    #[serde(default)]
+    pub recovery_code_hashes: Vec<String>,
+}
+
+impl TotpSettings {
+    /// Secret is not used for login until enrolment is confirmed with valid code.
+    pub fn new() -> TotpSettings {
+        let mut bytes = [0u8; 20];
+        OsRng.fill_bytes(&mut bytes);
+        TotpSettings {
+            secret: base32::encode(base32::Alphabet::RFC4648 { padding: false }, &bytes),
+            enabled: false,
+            last_used_step: None,
+            recovery_code_hashes: vec![],
+        }
+    }
+
+    /// otpauth:// URI understood by authenticator apps, usually scanned as QR code.
+    pub fn provisioning_uri(&self, login: &str) -> String {
+        format!(
+            "otpauth://totp/{issuer}:{login}?secret={secret}&issuer={issuer}&digits={digits}&period={period}",
+            issuer = ISSUER,
+            login = url_encode(login),
+            secret = self.secret,
+            digits = CODE_DIGITS,
+            period = TIME_STEP_SECONDS,
+        )
+    }
+
+    /// Checks code against current time step, every step can be used only once.
+    pub fn verify_code(&mut self, code: &str, unix_time: u64) -> bool {
+        let key = match base32::decode(base32::Alphabet::RFC4648 { padding: false }, &self.secret) {
+            Some(key) => key,
+            None => return false,
+        };
+        let code = code.trim();
+        if code.len() != CODE_DIGITS as usize || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+            return false;
+        }
+
+        let current_step = unix_time / TIME_STEP_SECONDS;
+        let first_step = current_step.saturating_sub(ALLOWED_STEP_SKEW);
+        for step in first_step..=current_step + ALLOWED_STEP_SKEW {
+            if let Some(last_used_step) = self.last_used_step {
+                if step <= last_used_step {
+                    continue;
+                }
+            }
+            let expected = format!("{:0width$}", hotp(&key, step), width = CODE_DIGITS as usize);
+            if constant_time_eq(&expected, code) {
+                self.last_used_step = Some(step);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Replaces recovery codes, returns plain codes which are only shown to user once.
+    pub fn generate_recovery_codes(&mut self) -> Vec<String> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let mut bytes = [0u8; 8];
+                OsRng.fill_bytes(&mut bytes);
+                bytes
+                    .chunks(2)
+                    .map(|chunk| format!("{:02x}{:02x}", chunk[0], chunk[1]))
+                    .collect::<Vec<String>>()
+                    .join("-")
+            })
+            .collect();
+        self.recovery_code_hashes = codes.iter().map(|code| hash_token(code)).collect();
+        codes
+    }
+
+    /// Removes recovery code if it matches, so it can't be used again.
+    pub fn use_recovery_code(&mut self, code: &str) -> bool {
+        let code_hash = hash_token(&code.trim().to_lowercase());
+        let code_count = self.recovery_code_hashes.len();
+        self.recovery_code_hashes
+            .retain(|recovery_code_hash| *recovery_code_hash != code_hash);
+        self.recovery_code_hashes.len() != code_count
+    }
+}
+
+// RFC 4226 HOTP with HMAC-SHA1, which is what authenticator apps use by default
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_varkey(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(CODE_DIGITS)
+}
+
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // secret "12345678901234567890" from RFC 6238 test vectors
+    fn rfc_settings() -> TotpSettings {
+        TotpSettings {
+            secret: base32::encode(
+                base32::Alphabet::RFC4648 { padding: false },
+                b"12345678901234567890",
+            ),
+            enabled: true,
+            last_used_step: None,
+            recovery_code_hashes: vec![],
+        }
+    }
+
+    #[test]
+    fn test_hotp_rfc_vectors() {
+        let key = b"12345678901234567890";
+        assert_eq!(287082, hotp(key, 59 / TIME_STEP_SECONDS));
+        assert_eq!(81804, hotp(key, 1111111109 / TIME_STEP_SECONDS));
+        assert_eq!(279037, hotp(key, 2000000000 / TIME_STEP_SECONDS));
+    }
+
+    #[test]
+    fn test_verify_code() {
+        let mut settings = rfc_settings();
+
+        assert!(!settings.verify_code("000000", 1111111109));
+        assert!(!settings.verify_code("not a code", 1111111109));
+        assert!(settings.verify_code("081804", 1111111109));
+    }
+
+    #[test]
+    fn test_verify_code_requires_exactly_six_digits() {
+        let mut settings = rfc_settings();
+
+        assert!(!settings.verify_code("81804", 1111111109));
+        assert!(!settings.verify_code("+81804", 1111111109));
+        assert!(!settings.verify_code("0081804", 1111111109));
+        assert!(!settings.verify_code("08180a", 1111111109));
+        assert!(settings.verify_code(" 081804 ", 1111111109));
+    }
+
+    #[test]
+    fn test_verify_code_accepts_skew_and_rejects_replay() {
+        let mut settings = rfc_settings();
+
+        assert!(settings.verify_code("081804", 1111111109 + TIME_STEP_SECONDS));
+        assert!(!settings.verify_code("081804", 1111111109));
+        assert!(!rfc_settings().verify_code("081804", 1111111109 + 3 * TIME_STEP_SECONDS));
+    }
+
+    #[test]
+    fn test_recovery_codes_are_single_use() {
+        let mut settings = TotpSettings::new();
+        let codes = settings.generate_recovery_codes();
+
+        assert_eq!(RECOVERY_CODE_COUNT, codes.len());
+        assert!(!settings.recovery_code_hashes.contains(&codes[0]));
+        assert!(settings.use_recovery_code(&codes[0].to_uppercase()));
+        assert!(!settings.use_recovery_code(&codes[0]));
+        assert!(settings.use_recovery_code(&codes[1]));
+        assert_eq!(RECOVERY_CODE_COUNT - 2, settings.recovery_code_hashes.len());
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        let settings = TotpSettings::new();
+        let uri = settings.provisioning_uri("john doe");
+
+        assert!(uri.starts_with("otpauth://totp/AssettoSync:john%20doe?secret="));
+        assert!(uri.contains(&settings.secret));
+    }
+}
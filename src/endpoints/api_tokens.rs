@@ -0,0 +1,199 @@
+use std::net::SocketAddr;
+use std::sync::RwLock;
+
+use rocket::request::{FlashMessage, Form};
+use rocket::response::{Flash, Redirect};
+use rocket::State;
+use rocket_contrib::templates::Template;
+
+use super::guards::AuthenticatedUser;
+use crate::config::{AuditAction, AuditEntry, ConfigObject, ConfigTrait, Permission, TokenScope};
+
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+
+#[derive(serde::Serialize)]
+struct ApiTokenTemplate {
+    id: String,
+    name: String,
+    scope: &'static str,
+    created_at: String,
+    last_used: String,
+}
+
+#[derive(serde::Serialize)]
+struct ScopeTemplate {
+    value: &'static str,
+    name: &'static str,
+}
+
+#[derive(serde::Serialize)]
+struct ApiTokensTemplateContext {
+    can_manage_users: bool,
+    csrf_token: String,
+    flash: Option<String>,
+    flash_type: Option<String>,
+    new_token: Option<String>,
+    scopes: Vec<ScopeTemplate>,
+    tokens: Vec<ApiTokenTemplate>,
+    user_name: String,
+}
+
+fn build_context(user: &AuthenticatedUser, config: &ConfigObject) -> ApiTokensTemplateContext {
+    let mut context = ApiTokensTemplateContext {
+        can_manage_users: user.role.has_permission(Permission::ManageUsers),
+        csrf_token: user.csrf_token.clone(),
+        flash: None,
+        flash_type: None,
+        new_token: None,
+        scopes: TokenScope::ALL
+            .iter()
+            .map(|scope| ScopeTemplate {
+                value: scope.as_str(),
+                name: scope.display_name(),
+            })
+            .collect(),
+        tokens: vec![],
+        user_name: user.user_name.clone(),
+    };
+
+    let config_user = config
+        .config
+        .users
+        .iter()
+        .find(|config_user| config_user.login == user.user_name);
+    if let Some(config_user) = config_user {
+        for api_token in config_user.api_tokens.iter() {
+            context.tokens.push(ApiTokenTemplate {
+                id: api_token.id.clone(),
+                name: api_token.name.clone(),
+                scope: api_token.scope.display_name(),
+                created_at: api_token.created_at.format(DATE_FORMAT).to_string(),
+                last_used: match api_token.last_used {
+                    Some(last_used) => last_used.format(DATE_FORMAT).to_string(),
+                    None => "never".to_string(),
+                },
+            });
+        }
+    }
+    context
+}
+
+#[get("/api_tokens")]
+pub fn api_tokens(
+    flash: Option<FlashMessage<'_, '_>>,
+    user: AuthenticatedUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Template {
+    let config = config_lock.read().unwrap();
+    let mut context = build_context(&user, &config);
+
+    if let Some(ref msg) = flash {
+        context.flash = Some(msg.msg().to_string());
+        if msg.name() == "error" {
+            context.flash_type = Some("Error".to_string());
+        }
+    }
+    Template::render("api_tokens", &context)
+}
+
+#[derive(FromForm)]
+pub struct CreateApiTokenData {
+    csrf_token: String,
+    name: String,
+    scope: String,
+}
+
+// Renders page directly instead of redirecting, plain token must not end up in flash cookie
+#[post("/api_tokens/create", data = "<data>")]
+pub fn api_tokens_create_post(
+    data: Form<CreateApiTokenData>,
+    user: AuthenticatedUser,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Template, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+
+    let name = data.name.trim().to_string();
+    if name.is_empty() {
+        return Err(Flash::error(
+            Redirect::to(uri!(api_tokens)),
+            "Token name cannot be empty",
+        ));
+    }
+
+    let scope: TokenScope = match data.scope.parse() {
+        Ok(scope) => scope,
+        Err(error) => return Err(Flash::error(Redirect::to(uri!(api_tokens)), error)),
+    };
+
+    let token = match config.add_api_token(&user.user_name, &name, scope) {
+        Ok(token) => token,
+        Err(error) => return Err(Flash::error(Redirect::to(uri!(api_tokens)), error)),
+    };
+
+    let mut entry = AuditEntry::new(
+        &user.user_name,
+        AuditAction::ApiTokenCreate,
+        Some(&name),
+        remote.map(|address| address.ip().to_string()),
+    );
+    entry.details = Some(format!("scope {}", scope.as_str()));
+    config.audit_log.record(entry);
+
+    let mut context = build_context(&user, &config);
+    context.flash = Some(format!(
+        "Token {} created, copy it now, it won't be shown again",
+        name
+    ));
+    context.new_token = Some(token);
+    Ok(Template::render("api_tokens", &context))
+}
+
+#[derive(FromForm)]
+pub struct RevokeApiTokenData {
+    csrf_token: String,
+    id: String,
+}
+
+#[post("/api_tokens/revoke", data = "<data>")]
+pub fn api_tokens_revoke_post(
+    data: Form<RevokeApiTokenData>,
+    user: AuthenticatedUser,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+
+    // name has to be looked up before token is gone from config
+    let name = config
+        .config
+        .users
+        .iter()
+        .find(|config_user| config_user.login == user.user_name)
+        .and_then(|config_user| {
+            config_user
+                .api_tokens
+                .iter()
+                .find(|api_token| api_token.id == data.id)
+        })
+        .map(|api_token| api_token.name.clone());
+    if let Err(error) = config.revoke_api_token(&user.user_name, &data.id) {
+        return Err(Flash::error(Redirect::to(uri!(api_tokens)), error));
+    }
+
+    let mut entry = AuditEntry::new(
+        &user.user_name,
+        AuditAction::ApiTokenRevoke,
+        name.as_deref(),
+        remote.map(|address| address.ip().to_string()),
+    );
+    entry.details = Some(format!("id {}", data.id));
+    config.audit_log.record(entry);
+
+    Ok(Flash::success(
+        Redirect::to(uri!(api_tokens)),
+        "Token revoked",
+    ))
+}
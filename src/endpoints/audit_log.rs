@@ -0,0 +1,213 @@
+use std::sync::RwLock;
+
+use rocket::http::Status;
+use rocket::response::status;
+use rocket::State;
+use rocket_contrib::json::Json;
+use rocket_contrib::templates::Template;
+
+use super::guards::{AdminUser, JsonError};
+use crate::config::{AuditAction, AuditEntry, AuditFilter, ConfigObject};
+
+const MAX_AUDIT_ENTRIES: usize = 1000;
+
+#[derive(serde::Serialize)]
+struct AuditEntryTemplate {
+    time: String,
+    actor: String,
+    action: &'static str,
+    target: String,
+    remote_address: String,
+    details: String,
+}
+
+#[derive(serde::Serialize)]
+struct ActionTemplate {
+    value: &'static str,
+    selected: bool,
+}
+
+#[derive(serde::Serialize)]
+struct AuditLogTemplateContext {
+    actions: Vec<ActionTemplate>,
+    actor: String,
+    entries: Vec<AuditEntryTemplate>,
+    error: Option<String>,
+    from: String,
+    to: String,
+    user_name: String,
+}
+
+#[get("/audit_log?<actor>&<action>&<from>&<to>")]
+pub fn audit_log(
+    actor: Option<String>,
+    action: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    user: AdminUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Template {
+    let config = config_lock.read().unwrap();
+
+    let mut context = AuditLogTemplateContext {
+        actions: AuditAction::ALL
+            .iter()
+            .map(|audit_action| ActionTemplate {
+                value: audit_action.as_str(),
+                selected: action.as_deref() == Some(audit_action.as_str()),
+            })
+            .collect(),
+        actor: actor.clone().unwrap_or_default(),
+        entries: vec![],
+        error: None,
+        from: from.clone().unwrap_or_default(),
+        to: to.clone().unwrap_or_default(),
+        user_name: user.user_name,
+    };
+
+    let entries = AuditFilter::parse(
+        actor.as_deref(),
+        action.as_deref(),
+        from.as_deref(),
+        to.as_deref(),
+    )
+    .and_then(|filter| config.audit_log.read(&filter, MAX_AUDIT_ENTRIES));
+    match entries {
+        Ok(entries) => {
+            for entry in entries {
+                context.entries.push(AuditEntryTemplate {
+                    time: entry.time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+                    actor: entry.actor,
+                    action: entry.action.as_str(),
+                    target: entry.target.unwrap_or_default(),
+                    remote_address: entry.remote_address.unwrap_or("unknown".to_string()),
+                    details: entry.details.unwrap_or_default(),
+                });
+            }
+        }
+        Err(error) => context.error = Some(error),
+    }
+    Template::render("audit_log", &context)
+}
+
+#[get("/audit_log.json?<actor>&<action>&<from>&<to>")]
+pub fn audit_log_json(
+    actor: Option<String>,
+    action: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    _user: AdminUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Json<Vec<AuditEntry>>, status::Custom<Json<JsonError>>> {
+    let config = config_lock.read().unwrap();
+
+    let filter = AuditFilter::parse(
+        actor.as_deref(),
+        action.as_deref(),
+        from.as_deref(),
+        to.as_deref(),
+    )
+    .map_err(|error| status::Custom(Status::BadRequest, Json(JsonError { error: error })))?;
+
+    match config.audit_log.read(&filter, MAX_AUDIT_ENTRIES) {
+        Ok(entries) => Ok(Json(entries)),
+        Err(error) => Err(status::Custom(
+            Status::InternalServerError,
+            Json(JsonError { error: error }),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+
+    use crate::config::{AuditAction, AuditEntry, Role};
+    use crate::tests::test_server::{csrf_token, login, test_client};
+
+    fn audit_entries(client: &rocket::local::Client, query: &str) -> Vec<AuditEntry> {
+        let mut response = client.get(format!("/audit_log.json{}", query)).dispatch();
+        assert_eq!(Status::Ok, response.status());
+        serde_json::from_str(&response.body_string().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_audit_log_records_actions() {
+        let (client, _temp_dir) = test_client(&[("admin", Role::Admin), ("user", Role::Viewer)]);
+        login(&client, "user");
+        login(&client, "admin");
+        client
+            .post("/user_management/change_password")
+            .header(ContentType::Form)
+            .body(format!(
+                "login=user&password1=new&password2=new&csrf_token={}",
+                csrf_token(&client, "admin")
+            ))
+            .dispatch();
+
+        let entries = audit_entries(&client, "");
+        assert_eq!(3, entries.len());
+        assert_eq!(AuditAction::PasswordChange, entries[0].action);
+        assert_eq!("admin", entries[0].actor);
+        assert_eq!(Some("user".to_string()), entries[0].target);
+
+        let entries = audit_entries(&client, "?actor=user&action=login");
+        assert_eq!(1, entries.len());
+        assert_eq!(AuditAction::Login, entries[0].action);
+
+        let response = client.get("/audit_log.json?from=yesterday").dispatch();
+        assert_eq!(Status::BadRequest, response.status());
+        assert_eq!(
+            Status::Ok,
+            client.get("/audit_log?action=logout").dispatch().status()
+        );
+    }
+
+    #[test]
+    fn test_audit_log_records_user_management() {
+        let (client, _temp_dir) = test_client(&[("admin", Role::Admin)]);
+        login(&client, "admin");
+        let csrf_token = csrf_token(&client, "admin");
+        let requests = [
+            (
+                "/user_management/add",
+                "login=user&password1=pass&password2=pass&role=viewer",
+            ),
+            ("/user_management/set_role", "login=user&role=operator"),
+            ("/user_management/delete", "login=user"),
+            ("/api_tokens/create", "name=ci&scope=read"),
+        ];
+        for (path, body) in requests.iter() {
+            client
+                .post(*path)
+                .header(ContentType::Form)
+                .body(format!("{}&csrf_token={}", body, csrf_token))
+                .dispatch();
+        }
+
+        let entries = audit_entries(&client, "");
+        let actions: Vec<AuditAction> = entries.iter().map(|entry| entry.action).collect();
+        assert_eq!(
+            vec![
+                AuditAction::ApiTokenCreate,
+                AuditAction::UserDelete,
+                AuditAction::UserSetRole,
+                AuditAction::UserAdd,
+                AuditAction::Login,
+            ],
+            actions
+        );
+        assert_eq!(Some("ci".to_string()), entries[0].target);
+        assert_eq!(Some("user".to_string()), entries[2].target);
+        assert_eq!(Some("role operator".to_string()), entries[2].details);
+    }
+
+    #[test]
+    fn test_audit_log_is_admin_only() {
+        let (client, _temp_dir) = test_client(&[("user", Role::Operator)]);
+        login(&client, "user");
+
+        let response = client.get("/audit_log.json").dispatch();
+        assert_eq!(Status::Forbidden, response.status());
+    }
+}
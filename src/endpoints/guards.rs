@@ -0,0 +1,339 @@
+use std::marker::PhantomData;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use rocket::http::{Cookie, Cookies, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{status, Flash, Redirect};
+use rocket::{Outcome, State};
+use rocket_contrib::json::Json;
+
+use crate::common::constant_time_eq;
+use crate::config::{ConfigObject, ConfigTrait, Permission, Role, Session};
+
+// Paths used by AssettoSync client, these get JSON errors instead of redirects
+const API_PATHS: [&str; 5] = [
+    "/audit_log.json",
+    "/mods.json",
+    "/mod_management/download",
+    "/mod_management/preview.json",
+    "/mod_management/upload.json",
+];
+
+pub const SESSION_COOKIE: &str = "session_id";
+
+fn get_session_id_from_cookie(cookies: &mut Cookies) -> Option<String> {
+    let cookie = cookies.get_private(SESSION_COOKIE);
+    match cookie {
+        Some(cookie) => Some(cookie.value().to_string()),
+        None => None,
+    }
+}
+
+/// User with active session who still exists in config, fails with 401 otherwise.
+pub struct AuthenticatedUser {
+    pub user_name: String,
+    pub role: Role,
+    pub session_id: String,
+    pub csrf_token: String,
+}
+
+fn is_csrf_token_valid(expected: &str, token: &str) -> bool {
+    constant_time_eq(expected, token)
+}
+
+fn csrf_error() -> Flash<Redirect> {
+    Flash::error(
+        Redirect::to(uri!(super::index)),
+        "Invalid form token, reload the page and try again",
+    )
+}
+
+impl AuthenticatedUser {
+    /// Every state-changing form has to send back csrf_token of the session.
+    pub fn verify_csrf_token(&self, token: &str) -> Result<(), Flash<Redirect>> {
+        if !is_csrf_token_valid(&self.csrf_token, token) {
+            return Err(csrf_error());
+        }
+        Ok(())
+    }
+}
+
+// Set in request cache, so 403 catcher can send admin to two-factor setup page
+struct TotpEnrollmentRequired(bool);
+
+// Set in request cache when session cookie is unknown or expired, 401 catcher only removes cookie
+// then, session waiting for second factor has to survive visits of other pages
+struct InvalidSession(bool);
+
+fn session_from_request(request: &Request) -> request::Outcome<(Session, Role), ()> {
+    let session_id = match get_session_id_from_cookie(&mut request.cookies()) {
+        Some(session_id) => session_id,
+        None => return Outcome::Failure((Status::Unauthorized, ())),
+    };
+
+    let config_lock = request.guard::<State<RwLock<ConfigObject>>>()?;
+    let config = config_lock.read().unwrap();
+
+    let session =
+        match config
+            .sessions
+            .touch(&session_id, &config.config.session, SystemTime::now())
+        {
+            Some(session) => session,
+            None => {
+                request.local_cache(|| InvalidSession(true));
+                return Outcome::Failure((Status::Unauthorized, ()));
+            }
+        };
+
+    // sessions are revoked on user deletion, but config file could be edited by hand
+    match config.get_user_role(&session.login) {
+        Some(role) => Outcome::Success((session, role)),
+        None => {
+            request.local_cache(|| InvalidSession(true));
+            Outcome::Failure((Status::Unauthorized, ()))
+        }
+    }
+}
+
+fn authenticated_user(request: &Request) -> request::Outcome<AuthenticatedUser, ()> {
+    let (session, role) = session_from_request(request)?;
+    if session.pending_totp {
+        return Outcome::Failure((Status::Unauthorized, ()));
+    }
+
+    Outcome::Success(AuthenticatedUser {
+        user_name: session.login,
+        role: role,
+        session_id: session.id,
+        csrf_token: session.csrf_token,
+    })
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for AuthenticatedUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let user = authenticated_user(request)?;
+
+        let config_lock = request.guard::<State<RwLock<ConfigObject>>>()?;
+        let config = config_lock.read().unwrap();
+        if config.needs_totp_enrollment(&user.user_name) {
+            request.local_cache(|| TotpEnrollmentRequired(true));
+            return Outcome::Failure((Status::Forbidden, ()));
+        }
+
+        Outcome::Success(user)
+    }
+}
+
+/// Logged in user who may not have set up required two-factor authentication yet.
+pub struct TotpSetupUser(pub AuthenticatedUser);
+
+impl<'a, 'r> FromRequest<'a, 'r> for TotpSetupUser {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(TotpSetupUser(authenticated_user(request)?))
+    }
+}
+
+/// Session of user whose password was accepted, but who still has to enter second factor.
+pub struct PendingLogin {
+    pub user_name: String,
+    pub session_id: String,
+    pub csrf_token: String,
+}
+
+impl PendingLogin {
+    pub fn verify_csrf_token(&self, token: &str) -> Result<(), Flash<Redirect>> {
+        if !is_csrf_token_valid(&self.csrf_token, token) {
+            return Err(csrf_error());
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for PendingLogin {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let (session, _) = session_from_request(request)?;
+        if !session.pending_totp {
+            return Outcome::Failure((Status::Unauthorized, ()));
+        }
+
+        Outcome::Success(PendingLogin {
+            user_name: session.login,
+            session_id: session.id,
+            csrf_token: session.csrf_token,
+        })
+    }
+}
+
+pub trait RequiredPermission {
+    fn permission() -> Permission;
+}
+
+macro_rules! required_permission {
+    ($name:ident, $permission:expr) => {
+        pub struct $name;
+
+        impl RequiredPermission for $name {
+            fn permission() -> Permission {
+                $permission
+            }
+        }
+    };
+}
+
+required_permission!(CanDownloadMods, Permission::DownloadMods);
+required_permission!(CanUploadMods, Permission::UploadMods);
+required_permission!(CanDeleteMods, Permission::DeleteMods);
+required_permission!(CanManageUsers, Permission::ManageUsers);
+
+/// User whose role grants permission P, fails with 403 otherwise.
+/// Accepts API token from Authorization header, limited by its scope, or session cookie.
+pub struct Authorized<P: RequiredPermission> {
+    pub user_name: String,
+    pub role: Role,
+    // None for API token requests, browsers never send Authorization header on their own
+    csrf_token: Option<String>,
+    permission: PhantomData<P>,
+}
+
+impl<P: RequiredPermission> Authorized<P> {
+    pub fn csrf_token(&self) -> String {
+        self.csrf_token.clone().unwrap_or_default()
+    }
+
+    pub fn verify_csrf_token(&self, token: &str) -> Result<(), Flash<Redirect>> {
+        match self.csrf_token {
+            Some(ref csrf_token) if !is_csrf_token_valid(csrf_token, token) => Err(csrf_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<'a, 'r, P: RequiredPermission> FromRequest<'a, 'r> for Authorized<P> {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let (user_name, role, allowed, csrf_token) =
+            match request.headers().get_one("Authorization") {
+                Some(authorization) => {
+                    let token = match authorization.strip_prefix("Bearer ") {
+                        Some(token) => token.trim(),
+                        None => return Outcome::Failure((Status::Unauthorized, ())),
+                    };
+
+                    let config_lock = request.guard::<State<RwLock<ConfigObject>>>()?;
+                    let owner = config_lock.read().unwrap().authenticate_api_token(token);
+                    let owner = match owner {
+                        Some(owner) => owner,
+                        None => return Outcome::Failure((Status::Unauthorized, ())),
+                    };
+                    // last use is saved every few minutes, other requests only need to read
+                    if owner.needs_marking {
+                        config_lock.write().unwrap().mark_api_token_used(token);
+                    }
+                    let allowed = owner.scope.allows(P::permission());
+                    (owner.login, owner.role, allowed, None)
+                }
+                None => {
+                    let user = request.guard::<AuthenticatedUser>()?;
+                    (user.user_name, user.role, true, Some(user.csrf_token))
+                }
+            };
+
+        if !allowed || !role.has_permission(P::permission()) {
+            return Outcome::Failure((Status::Forbidden, ()));
+        }
+
+        Outcome::Success(Authorized {
+            user_name: user_name,
+            role: role,
+            csrf_token: csrf_token,
+            permission: PhantomData,
+        })
+    }
+}
+
+pub type AdminUser = Authorized<CanManageUsers>;
+
+#[derive(serde::Serialize)]
+pub struct JsonError {
+    pub error: String,
+}
+
+type CatcherResponse = Result<status::Custom<Json<JsonError>>, Flash<Redirect>>;
+
+fn is_api_request(request: &Request) -> bool {
+    let path = request.uri().path();
+    let wants_json = match request.format() {
+        Some(format) => format.is_json(),
+        None => false,
+    };
+    let has_token = request.headers().contains("Authorization");
+    wants_json || has_token || API_PATHS.contains(&path)
+}
+
+#[catch(401)]
+pub fn unauthorized(request: &Request) -> CatcherResponse {
+    if request.local_cache(|| InvalidSession(false)).0 {
+        request
+            .cookies()
+            .remove_private(Cookie::named(SESSION_COOKIE));
+    }
+
+    if is_api_request(request) {
+        return Ok(status::Custom(
+            Status::Unauthorized,
+            Json(JsonError {
+                error: "Not logged in".to_string(),
+            }),
+        ));
+    }
+    Err(Flash::error(
+        Redirect::to(uri!(super::login_page_get)),
+        "You need to be logged in to view this page",
+    ))
+}
+
+#[catch(403)]
+pub fn forbidden(request: &Request) -> CatcherResponse {
+    let totp_enrollment_required = request.local_cache(|| TotpEnrollmentRequired(false)).0;
+
+    if is_api_request(request) {
+        return Ok(status::Custom(
+            Status::Forbidden,
+            Json(JsonError {
+                error: "Access denied".to_string(),
+            }),
+        ));
+    }
+    if totp_enrollment_required {
+        return Err(Flash::error(
+            Redirect::to(uri!(super::two_factor)),
+            "Administrators have to enable two-factor authentication",
+        ));
+    }
+    Err(Flash::error(
+        Redirect::to(uri!(super::index)),
+        "Access denied",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_csrf_token_valid() {
+        assert!(is_csrf_token_valid("abcdef", "abcdef"));
+        assert!(!is_csrf_token_valid("abcdef", "abcdeg"));
+        assert!(!is_csrf_token_valid("abcdef", "abc"));
+        assert!(!is_csrf_token_valid("abcdef", ""));
+    }
+}
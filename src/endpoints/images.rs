@@ -0,0 +1,86 @@
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::State;
+
+use super::guards::{Authorized, CanDownloadMods};
+use crate::config::ConfigObject;
+use crate::image_cache::ImageCache;
+
+pub struct CachedImageResponse {
+    path: PathBuf,
+}
+
+impl<'a> Responder<'a> for CachedImageResponse {
+    fn respond_to(self, _: &Request) -> response::Result<'a> {
+        let content_type = match self
+            .path
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("png") => ContentType::PNG,
+            _ => ContentType::JPEG,
+        };
+        let file = File::open(&self.path).map_err(|_| Status::NotFound)?;
+        // name is hash of content, so image under given URL never changes
+        Response::build()
+            .header(content_type)
+            .raw_header("Cache-Control", "public, max-age=31536000, immutable")
+            .sized_body(file)
+            .ok()
+    }
+}
+
+pub fn image_url(name: &str) -> String {
+    uri!(cached_image: name).to_string()
+}
+
+#[get("/images/<name>")]
+pub fn cached_image(
+    name: String,
+    _user: Authorized<CanDownloadMods>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Option<CachedImageResponse> {
+    let config = config_lock.read().unwrap();
+    let image_cache = ImageCache::new(&config.image_cache_path());
+
+    image_cache
+        .path_of(&name)
+        .map(|path| CachedImageResponse { path: path })
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+
+    use crate::config::Role;
+    use crate::tests::test_server::{config, login, test_client};
+
+    #[test]
+    fn test_cached_image() {
+        let (client, _temp_dir) = test_client(&[("user", Role::Viewer)]);
+        let image_cache_path = config(&client).read().unwrap().image_cache_path();
+        std::fs::create_dir_all(&image_cache_path).unwrap();
+        let name = format!("{}.png", "0".repeat(64));
+        std::fs::write(image_cache_path.join(&name), "png").unwrap();
+
+        let url = super::image_url(&name);
+        assert_eq!(
+            Status::SeeOther,
+            client.get(url.clone()).dispatch().status()
+        );
+
+        login(&client, "user");
+        let mut response = client.get(url).dispatch();
+        assert_eq!(Status::Ok, response.status());
+        assert_eq!(Some(ContentType::PNG), response.content_type());
+        assert_eq!(Some("png".to_string()), response.body_string());
+
+        let response = client.get("/images/config.json").dispatch();
+        assert_eq!(Status::NotFound, response.status());
+    }
+}
@@ -1,7 +1,10 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::RwLock;
+use std::time::SystemTime;
 
-use crate::config::{ConfigObject, ConfigTrait};
+use super::guards::{PendingLogin, TotpSetupUser, SESSION_COOKIE};
+use crate::config::{AuditAction, AuditEntry, ConfigObject, ConfigTrait, LoginEvent};
 use rocket::http::{Cookie, Cookies};
 use rocket::request::{FlashMessage, Form};
 use rocket::response::{Flash, Redirect};
@@ -27,27 +30,365 @@ pub struct LoginData {
     password: String,
 }
 
+fn record_login_event(
+    config: &ConfigObject,
+    login: &str,
+    remote_address: &Option<String>,
+    action: AuditAction,
+    message: &str,
+) {
+    println!(
+        "Login of {} from {}: {}",
+        login,
+        remote_address.as_deref().unwrap_or("unknown address"),
+        message
+    );
+    config.login_throttle.add_event(LoginEvent {
+        time: SystemTime::now(),
+        login: login.to_string(),
+        remote_address: remote_address.clone(),
+        success: action != AuditAction::LoginFailed,
+        message: message.to_string(),
+    });
+
+    let mut entry = AuditEntry::new(login, action, None, remote_address.clone());
+    entry.details = Some(message.to_string());
+    config.audit_log.record(entry);
+}
+
 #[post("/login", data = "<data>")]
 pub fn login_page_post(
     data: Form<LoginData>,
     mut cookies: Cookies,
+    remote: Option<SocketAddr>,
     config_lock: State<RwLock<ConfigObject>>,
 ) -> Result<Redirect, Flash<Redirect>> {
+    let now = SystemTime::now();
+    let remote_address = remote.map(|address| address.ip().to_string());
+
+    // password is not checked at all while throttled, so guessing can't continue in background.
+    // Attempt counts as failure until password is verified, parallel guesses are throttled too
     let config = config_lock.read().unwrap();
+    let attempt = config.login_throttle.begin_attempt(
+        &data.login,
+        remote_address.as_deref(),
+        &config.config.login_throttle,
+        now,
+    );
+    let attempt = match attempt {
+        Ok(attempt) => attempt,
+        Err(wait_seconds) => {
+            record_login_event(
+                &config,
+                &data.login,
+                &remote_address,
+                AuditAction::LoginFailed,
+                "Throttled",
+            );
+            return Err(Flash::error(
+                Redirect::to(uri!(login_page_get)),
+                format!(
+                    "Too many failed login attempts, try again in {} seconds",
+                    wait_seconds
+                ),
+            ));
+        }
+    };
 
-    let authentication_success = config.is_login_data_valid(&data.login, &data.password);
+    // other requests aren't blocked while password is verified
+    drop(config);
+    let authentication_success =
+        crate::config::verify_login(&config_lock, &data.login, &data.password);
+    let config = config_lock.read().unwrap();
     if !authentication_success {
+        record_login_event(
+            &config,
+            &data.login,
+            &remote_address,
+            AuditAction::LoginFailed,
+            "Wrong username/password",
+        );
         return Err(Flash::error(
             Redirect::to(uri!(login_page_get)),
             "Wrong username/password",
         ));
     }
-    cookies.add_private(Cookie::new("user_name", data.login.clone()));
+
+    // throttle counters are only cleared after second factor, password alone doesn't reset them
+    config
+        .login_throttle
+        .release_attempt(attempt, &config.config.login_throttle);
+    if config.is_totp_enabled(&data.login) {
+        record_login_event(
+            &config,
+            &data.login,
+            &remote_address,
+            AuditAction::LoginPasswordAccepted,
+            "Password accepted, waiting for two-factor code",
+        );
+        let session_id = config.sessions.create_pending_totp(
+            &data.login,
+            remote_address,
+            &config.config.session,
+            now,
+        );
+        cookies.add_private(Cookie::new(SESSION_COOKIE, session_id));
+        return Ok(Redirect::to(uri!(login_totp_get)));
+    }
+
+    config.login_throttle.record_success(&data.login);
+    record_login_event(
+        &config,
+        &data.login,
+        &remote_address,
+        AuditAction::Login,
+        "Logged in",
+    );
+
+    let session_id =
+        config
+            .sessions
+            .create(&data.login, remote_address, &config.config.session, now);
+    cookies.add_private(Cookie::new(SESSION_COOKIE, session_id));
+    Ok(Redirect::to(uri!(super::index::index)))
+}
+
+#[get("/login/totp")]
+pub fn login_totp_get(
+    flash: Option<FlashMessage<'_, '_>>,
+    pending_login: PendingLogin,
+) -> Template {
+    let mut context = HashMap::new();
+    context.insert("csrf_token", pending_login.csrf_token);
+    context.insert("user_name", pending_login.user_name);
+    if let Some(ref msg) = flash {
+        context.insert("flash", msg.msg().to_string());
+        if msg.name() == "error" {
+            context.insert("flash_type", "Error".to_string());
+        }
+    }
+
+    Template::render("login_totp", &context)
+}
+
+#[derive(FromForm)]
+pub struct LoginTotpData {
+    code: String,
+    csrf_token: String,
+}
+
+#[post("/login/totp", data = "<data>")]
+pub fn login_totp_post(
+    data: Form<LoginTotpData>,
+    pending_login: PendingLogin,
+    mut cookies: Cookies,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Redirect, Flash<Redirect>> {
+    pending_login.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+    let now = SystemTime::now();
+    let remote_address = remote.map(|address| address.ip().to_string());
+    let login = pending_login.user_name;
+
+    let throttled = config.login_throttle.check(
+        &login,
+        remote_address.as_deref(),
+        &config.config.login_throttle,
+        now,
+    );
+    if let Err(wait_seconds) = throttled {
+        record_login_event(
+            &config,
+            &login,
+            &remote_address,
+            AuditAction::LoginFailed,
+            "Throttled",
+        );
+        return Err(Flash::error(
+            Redirect::to(uri!(login_totp_get)),
+            format!(
+                "Too many failed login attempts, try again in {} seconds",
+                wait_seconds
+            ),
+        ));
+    }
+
+    if !config.verify_second_factor(&login, &data.code) {
+        config.login_throttle.record_failure(
+            &login,
+            remote_address.as_deref(),
+            &config.config.login_throttle,
+            now,
+        );
+        record_login_event(
+            &config,
+            &login,
+            &remote_address,
+            AuditAction::LoginFailed,
+            "Wrong two-factor code",
+        );
+        return Err(Flash::error(
+            Redirect::to(uri!(login_totp_get)),
+            "Wrong code",
+        ));
+    }
+
+    config.login_throttle.record_success(&login);
+    record_login_event(
+        &config,
+        &login,
+        &remote_address,
+        AuditAction::Login,
+        "Logged in with two-factor code",
+    );
+
+    // new session id after login is complete, pending one is never upgraded in place
+    config.sessions.revoke(&login, &pending_login.session_id);
+    let session_id = config
+        .sessions
+        .create(&login, remote_address, &config.config.session, now);
+    cookies.add_private(Cookie::new(SESSION_COOKIE, session_id));
     Ok(Redirect::to(uri!(super::index::index)))
 }
 
-#[post("/logout")]
-pub fn logout(mut cookies: Cookies) -> Redirect {
-    cookies.remove_private(Cookie::named("user_name"));
-    Redirect::to(uri!(login_page_get))
+#[derive(FromForm)]
+pub struct LogoutData {
+    csrf_token: String,
+}
+
+#[post("/logout", data = "<data>")]
+pub fn logout(
+    data: Form<LogoutData>,
+    user: Option<TotpSetupUser>,
+    mut cookies: Cookies,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Redirect, Flash<Redirect>> {
+    if let Some(TotpSetupUser(user)) = user {
+        user.verify_csrf_token(&data.csrf_token)?;
+        let config = config_lock.read().unwrap();
+        config.sessions.revoke(&user.user_name, &user.session_id);
+        config.audit_log.record(AuditEntry::new(
+            &user.user_name,
+            AuditAction::Logout,
+            None,
+            remote.map(|address| address.ip().to_string()),
+        ));
+    }
+    cookies.remove_private(Cookie::named(SESSION_COOKIE));
+    Ok(Redirect::to(uri!(login_page_get)))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+    use rocket::local::{Client, LocalResponse};
+
+    use super::SESSION_COOKIE;
+
+    use crate::config::{Role, TotpSettings};
+    use crate::tests::test_server::{config, login, test_client, PASSWORD};
+
+    fn enable_totp(client: &Client, login: &str) -> Vec<String> {
+        let mut config = config(client).write().unwrap();
+        let user = config
+            .config
+            .users
+            .iter_mut()
+            .find(|user| user.login == login)
+            .unwrap();
+        let mut totp = TotpSettings::new();
+        totp.enabled = true;
+        let recovery_codes = totp.generate_recovery_codes();
+        user.totp = Some(totp);
+        recovery_codes
+    }
+
+    fn page_csrf_token(client: &Client, path: &str) -> String {
+        let body = client.get(path).dispatch().body_string().unwrap();
+        let start = body.find("name=\"csrf_token\" value=\"").unwrap() + 25;
+        body[start..start + 64].to_string()
+    }
+
+    fn post_login_totp(client: &Client, code: &str, csrf_token: &str) -> Option<String> {
+        let response = client
+            .post("/login/totp")
+            .header(ContentType::Form)
+            .body(format!("code={}&csrf_token={}", code, csrf_token))
+            .dispatch();
+        assert_eq!(Status::SeeOther, response.status());
+        response
+            .headers()
+            .get_one("Location")
+            .map(|location| location.to_string())
+    }
+
+    fn removes_session_cookie(response: &LocalResponse) -> bool {
+        response.headers().get("Set-Cookie").any(|cookie| {
+            cookie.starts_with(&format!("{}=", SESSION_COOKIE)) && cookie.contains("Max-Age=0")
+        })
+    }
+
+    #[test]
+    fn test_unknown_session_cookie_is_removed() {
+        let (client, _temp_dir) = test_client(&[("admin", Role::Admin)]);
+        login(&client, "admin");
+        config(&client)
+            .read()
+            .unwrap()
+            .sessions
+            .revoke_user("admin");
+
+        let response = client.get("/").dispatch();
+        assert_eq!(Some("/login"), response.headers().get_one("Location"));
+        assert!(removes_session_cookie(&response));
+    }
+
+    #[test]
+    fn test_login_with_totp_requires_second_step() {
+        let (client, _temp_dir) = test_client(&[("admin", Role::Admin)]);
+        let recovery_codes = enable_totp(&client, "admin");
+
+        let response = client
+            .post("/login")
+            .header(ContentType::Form)
+            .body(format!("login=admin&password={}", PASSWORD))
+            .dispatch();
+        assert_eq!(Some("/login/totp"), response.headers().get_one("Location"));
+
+        // password alone doesn't give access to anything, but the pending session stays
+        let response = client.get("/").dispatch();
+        assert_eq!(Some("/login"), response.headers().get_one("Location"));
+        assert!(!removes_session_cookie(&response));
+
+        let csrf_token = page_csrf_token(&client, "/login/totp");
+        assert_eq!(
+            Some("/login/totp".to_string()),
+            post_login_totp(&client, "000000", &csrf_token)
+        );
+        assert_eq!(
+            Some("/".to_string()),
+            post_login_totp(&client, &recovery_codes[0], &csrf_token)
+        );
+        assert_eq!(Status::Ok, client.get("/").dispatch().status());
+    }
+
+    #[test]
+    fn test_admin_without_totp_is_sent_to_setup() {
+        let (client, _temp_dir) = test_client(&[("admin", Role::Admin), ("user", Role::Viewer)]);
+        config(&client)
+            .write()
+            .unwrap()
+            .config
+            .require_totp_for_admins = true;
+
+        login(&client, "admin");
+        let response = client.get("/").dispatch();
+        assert_eq!(Some("/two_factor"), response.headers().get_one("Location"));
+        assert_eq!(Status::Ok, client.get("/two_factor").dispatch().status());
+
+        login(&client, "user");
+        assert_eq!(Status::Ok, client.get("/").dispatch().status());
+    }
 }
@@ -1,42 +1,76 @@
-use rocket::http::Cookies;
-use rocket::Route;
+use rocket::{Catcher, Route};
 
+mod api_tokens;
+mod audit_log;
+mod guards;
+mod images;
 mod index;
 mod login;
 mod mod_management;
 mod mods_json;
+mod sessions;
 mod style_css;
+mod two_factor;
 mod user_management;
 
+use api_tokens::*;
+use audit_log::*;
+use images::*;
 use index::*;
 use login::*;
 use mod_management::*;
 use mods_json::*;
+use sessions::*;
 use style_css::*;
+use two_factor::*;
 use user_management::*;
 
-fn get_user_name_from_cookie(cookies: &mut Cookies) -> Option<String> {
-    let cookie = cookies.get_private("user_name");
-    match cookie {
-        Some(cookie) => Some(cookie.value().to_string()),
-        None => None,
-    }
-}
-
 pub fn get_routes() -> Vec<Route> {
     routes![
+        api_tokens,
+        api_tokens_create_post,
+        api_tokens_revoke_post,
+        audit_log,
+        audit_log_json,
+        cached_image,
         index,
         login_page_get,
         login_page_post,
+        login_totp_get,
+        login_totp_post,
         logout,
         mod_delete,
+        mod_discard_volumes,
         mod_download,
         mod_management,
+        mod_preview,
+        mod_resolve_conflict,
+        mod_restore_version,
         mod_upload,
+        mod_upload_json,
         mods_json,
+        sessions,
+        sessions_revoke_all_post,
+        sessions_revoke_post,
         style_css,
+        two_factor,
+        two_factor_confirm_post,
+        two_factor_disable_post,
+        two_factor_setup_post,
         user_management,
+        user_management_add_post,
         user_management_change_password_get,
         user_management_change_password_post,
+        user_management_delete_post,
+        user_management_disable_totp_post,
+        user_management_login_events,
+        user_management_require_totp_post,
+        user_management_revoke_sessions_post,
+        user_management_set_role_post,
+        user_management_unlock_post,
     ]
 }
+
+pub fn get_catchers() -> Vec<Catcher> {
+    catchers![guards::unauthorized, guards::forbidden]
+}
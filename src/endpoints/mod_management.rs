@@ -1,34 +1,203 @@
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::RwLock;
+use std::time::SystemTime;
 
-use fs_extra::dir::CopyOptions;
-use rocket::http::{ContentType, Cookies};
-use rocket::request::{FlashMessage, Request};
-use rocket::response::{Flash, Redirect};
+use chrono::{DateTime, Utc};
+use rocket::http::{ContentType, Status};
+use rocket::request::{FlashMessage, Form, Request};
+use rocket::response::{status, Flash, Redirect};
 use rocket::{Data, State};
+use rocket_contrib::json::Json;
 use rocket_contrib::templates::Template;
 use rocket_multipart_form_data::{
     MultipartFormData, MultipartFormDataField, MultipartFormDataOptions, Repetition,
 };
-use tempdir::TempDir;
 
-use super::get_user_name_from_cookie;
-use crate::config::{ConfigObject, ConfigTrait};
-use crate::{archive_unpacker, common, config, install_task};
+use super::guards::{Authorized, CanDeleteMods, CanDownloadMods, CanUploadMods, JsonError};
+use super::images::image_url;
+use crate::config::{
+    AuditAction, AuditEntry, ConfigObject, ConfigTrait, PendingUpload, PendingVolumeSet, Permission,
+};
+use crate::install_report::{InstallError, InstallReport};
+use crate::mod_installer::{ConflictResolution, ImportResult, InstallPreview};
+use crate::mod_metadata::ContentMetadata;
+use crate::{archive_unpacker, config, mod_installer};
+
+#[derive(serde::Serialize)]
+struct ImageTemplate {
+    url: String,
+    thumbnail_url: String,
+}
+
+#[derive(serde::Serialize)]
+struct ContentTemplate {
+    id: String,
+    name: String,
+    details: String,
+    /// Inner archive folder was unpacked from.
+    source: Option<String>,
+    images: Vec<ImageTemplate>,
+}
+
+#[derive(serde::Serialize)]
+struct VersionTemplate {
+    version: u32,
+    checksum_md5: String,
+    filename: String,
+    updated_at: String,
+}
 
 #[derive(serde::Serialize)]
 struct ModTemplate {
     checksum_md5: String,
     filename: String,
     size_in_megabytes: u64,
+    version: u32,
+    updated_at: String,
+    cars: Vec<ContentTemplate>,
+    tracks: Vec<ContentTemplate>,
+    history: Vec<VersionTemplate>,
+}
+
+// mods uploaded before versioning have no timestamp
+fn format_updated_at(updated_at: &Option<DateTime<Utc>>) -> String {
+    match updated_at {
+        Some(updated_at) => updated_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+// content_type is cars or tracks, the way sources are keyed
+fn content_templates(
+    contents: &Vec<ContentMetadata>,
+    content_type: &str,
+    sources: &BTreeMap<String, String>,
+) -> Vec<ContentTemplate> {
+    contents
+        .iter()
+        .map(|content| {
+            let mut details: Vec<String> = vec![];
+            for value in [&content.brand, &content.class, &content.country].iter() {
+                if let Some(value) = value {
+                    details.push(value.clone());
+                }
+            }
+            if let Some(ref author) = content.author {
+                details.push(format!("by {}", author));
+            }
+            if let Some(ref version) = content.version {
+                details.push(format!("version {}", version));
+            }
+            for (key, value) in content.specs.iter() {
+                details.push(format!("{}: {}", key, value));
+            }
+            if !content.tags.is_empty() {
+                details.push(format!("tags: {}", content.tags.join(" ")));
+            }
+
+            // every skin has its own preview, page only shows the first one
+            let mut images = vec![];
+            let mut has_skin_preview = false;
+            for image in content.images.iter() {
+                if image.skin.is_some() {
+                    if has_skin_preview {
+                        continue;
+                    }
+                    has_skin_preview = true;
+                }
+                images.push(ImageTemplate {
+                    url: image_url(&image.image),
+                    thumbnail_url: image_url(&image.thumbnail),
+                });
+            }
+
+            ContentTemplate {
+                id: match content.layout {
+                    Some(ref layout) => format!("{}/{}", content.id, layout),
+                    None => content.id.clone(),
+                },
+                name: content.name.clone().unwrap_or_else(|| content.id.clone()),
+                details: details.join(", "),
+                source: sources
+                    .get(&format!("{}/{}", content_type, content.id))
+                    .cloned(),
+                images: images,
+            }
+        })
+        .collect()
+}
+
+#[derive(serde::Serialize)]
+struct ConflictTemplate {
+    filename: String,
+    files: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct PendingUploadTemplate {
+    id: String,
+    file_name: String,
+    uploader: String,
+    conflicts: Vec<ConflictTemplate>,
+}
+
+// conflicting files are grouped by mod which provides them
+fn pending_upload_template(upload: &PendingUpload) -> PendingUploadTemplate {
+    let mut conflicts: Vec<ConflictTemplate> = vec![];
+    for conflict in upload.conflicts.iter() {
+        match conflicts
+            .iter_mut()
+            .find(|existing| existing.filename == conflict.filename)
+        {
+            Some(existing) => existing.files.push(conflict.path.clone()),
+            None => conflicts.push(ConflictTemplate {
+                filename: conflict.filename.clone(),
+                files: vec![conflict.path.clone()],
+            }),
+        }
+    }
+
+    PendingUploadTemplate {
+        id: upload.id.clone(),
+        file_name: upload.file_name.clone(),
+        uploader: upload.uploader.clone(),
+        conflicts: conflicts,
+    }
+}
+
+#[derive(serde::Serialize)]
+struct PendingVolumeSetTemplate {
+    id: String,
+    file_name: String,
+    uploader: String,
+    volumes: Vec<String>,
+}
+
+fn pending_volume_set_template(set: &PendingVolumeSet) -> PendingVolumeSetTemplate {
+    PendingVolumeSetTemplate {
+        id: set.id.clone(),
+        file_name: set.joined_file_name(),
+        uploader: set.uploader.clone(),
+        volumes: set
+            .volumes
+            .iter()
+            .map(|(_, volume)| volume.clone())
+            .collect(),
+    }
 }
 
 #[derive(serde::Serialize)]
 struct ModManagementTemplateContext {
+    csrf_token: String,
     flash: Option<String>,
     flash_type: Option<String>,
+    can_delete: bool,
     mods: Vec<ModTemplate>,
+    pending_uploads: Vec<PendingUploadTemplate>,
+    pending_volume_sets: Vec<PendingVolumeSetTemplate>,
     user_name: String,
 }
 
@@ -55,22 +224,20 @@ impl<'a> Responder<'a> for AssettoModResponse {
 #[get("/mod_management/download?<hash>")]
 pub fn mod_download(
     hash: String,
-    mut cookies: Cookies,
+    user: Authorized<CanDownloadMods>,
+    remote: Option<SocketAddr>,
     config_lock: State<RwLock<ConfigObject>>,
 ) -> Result<AssettoModResponse, Flash<Redirect>> {
-    let user_name = get_user_name_from_cookie(&mut cookies);
-    if user_name.is_none() {
-        return Err(Flash::error(
-            Redirect::to(uri!(mod_management)),
-            "You need to be logged in to view this page",
-        ));
-    }
-    std::mem::drop(cookies);
-
     let config = config_lock.read().unwrap();
 
-    for acmod in config.config.mods.iter() {
+    for acmod in config.mod_database.mods.iter() {
         if acmod.checksum_md5 == hash {
+            config.audit_log.record(AuditEntry::new(
+                &user.user_name,
+                AuditAction::ModDownload,
+                Some(&acmod.filename),
+                remote.map(|address| address.ip().to_string()),
+            ));
             let mod_storage = Path::new(&config.config.mod_storage_location);
             return Ok(AssettoModResponse {
                 acmod: acmod.clone(),
@@ -80,40 +247,45 @@ pub fn mod_download(
     }
 
     return Err(Flash::error(
-        Redirect::to(uri!(mod_management)),
+        Redirect::to(uri!(super::index)),
         "Mod hash not found",
     ));
 }
 
-#[get("/mod_management/delete?<hash>")]
-pub fn mod_delete(
+#[derive(FromForm)]
+pub struct DeleteModData {
+    csrf_token: String,
     hash: String,
-    mut cookies: Cookies,
+}
+
+#[post("/mod_management/delete", data = "<data>")]
+pub fn mod_delete(
+    data: Form<DeleteModData>,
+    user: Authorized<CanDeleteMods>,
+    remote: Option<SocketAddr>,
     config_lock: State<RwLock<ConfigObject>>,
 ) -> Result<Flash<Redirect>, Flash<Redirect>> {
-    let user_name = get_user_name_from_cookie(&mut cookies);
-    if user_name.is_none() {
-        return Err(Flash::error(
-            Redirect::to(uri!(super::login_page_get)),
-            "You need to be logged in to view this page",
-        ));
-    }
-    std::mem::drop(cookies);
-
+    user.verify_csrf_token(&data.csrf_token)?;
     let mut config = config_lock.write().unwrap();
-    let user_name = user_name.unwrap();
-    let is_admin = config.is_user_admin(&user_name);
 
-    if !is_admin {
-        return Err(Flash::error(
-            Redirect::to(uri!(super::index)),
-            "Access denied",
-        ));
+    // filename has to be looked up before mod is gone from database
+    let filename = config
+        .get_mod_list()
+        .into_iter()
+        .find(|acmod| acmod.checksum_md5 == data.hash)
+        .map(|acmod| acmod.filename);
+    if let Err(error) = config.delete_mod(&data.hash) {
+        return Err(Flash::error(Redirect::to(uri!(mod_management)), error));
     }
 
-    if let Err(error) = config.delete_mod(&hash) {
-        return Err(Flash::error(Redirect::to(uri!(super::index)), error));
-    }
+    let mut entry = AuditEntry::new(
+        &user.user_name,
+        AuditAction::ModDelete,
+        filename.as_deref(),
+        remote.map(|address| address.ip().to_string()),
+    );
+    entry.details = Some(format!("md5 {}", data.hash));
+    config.audit_log.record(entry);
 
     Ok(Flash::success(
         Redirect::to(uri!(mod_management)),
@@ -124,41 +296,57 @@ pub fn mod_delete(
 #[get("/mod_management")]
 pub fn mod_management(
     flash: Option<FlashMessage<'_, '_>>,
-    mut cookies: Cookies,
+    user: Authorized<CanUploadMods>,
     config_lock: State<RwLock<ConfigObject>>,
-) -> Result<Template, Flash<Redirect>> {
-    let user_name = get_user_name_from_cookie(&mut cookies);
-    if user_name.is_none() {
-        return Err(Flash::error(
-            Redirect::to(uri!(super::login_page_get)),
-            "You need to be logged in to view this page",
-        ));
-    }
-    std::mem::drop(cookies);
-
+) -> Template {
     let config = config_lock.read().unwrap();
-    let user_name = user_name.unwrap();
-    let is_admin = config.is_user_admin(&user_name);
-
-    if !is_admin {
-        return Err(Flash::error(
-            Redirect::to(uri!(super::index)),
-            "Access denied",
-        ));
-    }
 
+    let can_delete = user.role.has_permission(Permission::DeleteMods);
     let mut context = ModManagementTemplateContext {
+        csrf_token: user.csrf_token(),
         flash: None,
         flash_type: None,
+        can_delete: can_delete,
         mods: vec![],
-        user_name: user_name,
+        // users who can't delete mods only see their own uploads
+        pending_uploads: config
+            .pending_uploads
+            .list()
+            .iter()
+            .filter(|upload| can_delete || upload.uploader == user.user_name)
+            .map(pending_upload_template)
+            .collect(),
+        pending_volume_sets: config
+            .pending_volumes
+            .list()
+            .iter()
+            .filter(|set| can_delete || set.uploader == user.user_name)
+            .map(pending_volume_set_template)
+            .collect(),
+        user_name: user.user_name,
     };
 
-    for acmod in config.config.mods.iter() {
+    for acmod in config.mod_database.mods.iter() {
         context.mods.push(ModTemplate {
             checksum_md5: acmod.checksum_md5.clone(),
             filename: acmod.filename.clone(),
             size_in_megabytes: acmod.size_in_bytes / 1024 / 1024,
+            version: acmod.version,
+            updated_at: format_updated_at(&acmod.updated_at),
+            cars: content_templates(&acmod.cars, "cars", &acmod.content_sources),
+            tracks: content_templates(&acmod.tracks, "tracks", &acmod.content_sources),
+            // newest first
+            history: acmod
+                .history
+                .iter()
+                .rev()
+                .map(|version| VersionTemplate {
+                    version: version.version,
+                    checksum_md5: version.checksum_md5.clone(),
+                    filename: version.filename.clone(),
+                    updated_at: format_updated_at(&version.updated_at),
+                })
+                .collect(),
         });
     }
 
@@ -168,174 +356,1001 @@ pub fn mod_management(
             context.flash_type = Some("Error".to_string());
         }
     }
-    Ok(Template::render("mod_management", &context))
-}
-
-fn install_mod(archive_path: &PathBuf, server_paths: &Vec<String>) {
-    let temp_dir = TempDir::new("acsync_server_unpack");
-    let temp_dir_output = TempDir::new("acsync_server_install");
-    if let Err(error) = temp_dir {
-        println!("Error creating temp_dir: {}", error.to_string());
-        return;
-    }
-    if let Err(error) = temp_dir_output {
-        println!("Error creating temp_dir: {}", error.to_string());
-        return;
-    }
-    let temp_dir = temp_dir.unwrap();
-    let temp_dir_output = temp_dir_output.unwrap();
-    let temporary_directory = temp_dir.path();
-    let output_directory = temp_dir_output.path();
-    let _ = std::fs::create_dir_all(output_directory.join("content/cars"));
-    let _ = std::fs::create_dir_all(output_directory.join("content/tracks"));
-    let _ = archive_unpacker::unpack_archive(Path::new(archive_path), temporary_directory);
-    for task in
-        install_task::determine_install_tasks(&common::recursive_ls(temporary_directory)).unwrap()
-    {
-        let target_path = Path::new(output_directory).join(task.target_path);
-        println!("{} -> {:?}", task.source_path, target_path);
-        let result = fs_extra::dir::move_dir(task.source_path, target_path, &CopyOptions::new());
-        if let Err(error) = result {
-            println!("Error while installing mod: {}", error.to_string());
-            return;
+    Template::render("mod_management", &context)
+}
+
+fn parse_upload_form(content_type: &ContentType, data: Data) -> Result<MultipartFormData, String> {
+    let options = MultipartFormDataOptions::with_multipart_form_data_fields(vec![
+        MultipartFormDataField::file("file[]")
+            .size_limit(4 * 1024 * 1024 * 1024)
+            .repetition(Repetition::fixed(50)),
+        MultipartFormDataField::text("csrf_token"),
+    ]);
+
+    MultipartFormData::parse(content_type, data, options)
+        .map_err(|error| format!("Invalid upload form: {:?}", error))
+}
+
+fn form_csrf_token(multipart_form_data: &MultipartFormData) -> &str {
+    multipart_form_data
+        .texts
+        .get("csrf_token")
+        .and_then(|fields| fields.first())
+        .map(|field| field.text.as_str())
+        .unwrap_or("")
+}
+
+// one report per uploaded file, uploads conflicting with installed mods are kept as pending.
+// Archive is planned and unpacked without holding config lock, write lock is only taken to check
+// installed mods again and install
+fn install_upload(
+    config_lock: &RwLock<ConfigObject>,
+    user_name: &str,
+    remote_address: &Option<String>,
+    source_path: &Path,
+    file_name: &str,
+) -> InstallReport {
+    let mut report = InstallReport::new(file_name);
+    let (known_checksums, limits) = {
+        let config = config_lock.read().unwrap();
+        let known_checksums: Vec<String> = config
+            .get_mod_list()
+            .into_iter()
+            .map(|acmod| acmod.checksum_md5)
+            .collect();
+        (known_checksums, config.config.archive_limits.clone())
+    };
+    let result = mod_installer::plan_import(source_path, file_name, &known_checksums, &limits)
+        .and_then(|planned| {
+            // conflicting upload is kept as it is, no need to unpack it
+            let conflicts = mod_installer::check_import(&config_lock.read().unwrap(), &planned)?;
+            if !conflicts.is_empty() {
+                return Ok(ImportResult::Conflicts(conflicts));
+            }
+            let prepared = mod_installer::prepare_import(planned, &limits)?;
+            mod_installer::install_import(
+                &mut config_lock.write().unwrap(),
+                prepared,
+                ConflictResolution::Abort,
+            )
+        });
+
+    let config = config_lock.read().unwrap();
+    match result {
+        Ok(ImportResult::Imported {
+            installed,
+            replaced,
+            server_paths,
+            warnings,
+        }) => {
+            let mut entry = AuditEntry::new(
+                user_name,
+                AuditAction::ModUpload,
+                Some(file_name),
+                remote_address.clone(),
+            );
+            if installed.version > 1 {
+                entry.details = Some(format!("version {}", installed.version));
+            }
+            config.audit_log.record(entry);
+
+            report.checksum_md5 = Some(installed.checksum_md5);
+            report.version = Some(installed.version);
+            report.server_paths = server_paths;
+            report.replaced = replaced.into_iter().map(|acmod| acmod.filename).collect();
+            report.warnings = warnings;
         }
+        Ok(ImportResult::Conflicts(conflicts)) => {
+            let pending = config
+                .pending_uploads
+                .add(source_path, file_name, user_name, conflicts);
+            match pending {
+                Ok(pending) => {
+                    report.pending_upload_id = Some(pending.id);
+                    report.conflicts = pending.conflicts;
+                }
+                Err(error) => report.error = Some(InstallError::Storage(error)),
+            }
+        }
+        Err(error) => report.error = Some(error),
     }
+    report
+}
 
-    let copy_options = fs_extra::file::CopyOptions {
-        overwrite: true,
-        skip_exist: true,
-        buffer_size: 65536,
+// set is installed as one archive once its last volume arrives, in this or any later request.
+// Volumes are kept until it's installed or waits as pending upload, failed set can be discarded
+fn install_volume_set(
+    config_lock: &RwLock<ConfigObject>,
+    user_name: &str,
+    remote_address: &Option<String>,
+    set: &PendingVolumeSet,
+) -> InstallReport {
+    let file_name = set.joined_file_name();
+    let joined = {
+        let config = config_lock.read().unwrap();
+        mod_installer::join_volume_set(&config.pending_volumes, set, &config.config.archive_limits)
     };
+    match joined {
+        Ok(None) => {
+            let mut report = InstallReport::new(&file_name);
+            report.received_volumes = set
+                .volumes
+                .iter()
+                .map(|(_, volume)| volume.clone())
+                .collect();
+            report
+        }
+        Ok(Some((_joining_directory, joined_path))) => {
+            let report = install_upload(
+                config_lock,
+                user_name,
+                remote_address,
+                &joined_path,
+                &file_name,
+            );
+            if report.error.is_none() {
+                let config = config_lock.read().unwrap();
+                config.pending_volumes.take(&set.id);
+                config.pending_volumes.discard(set);
+            }
+            report
+        }
+        Err(error) => InstallReport::failed(&file_name, error),
+    }
+}
 
-    // for output_dir in server_paths
-    for output_dir_str in server_paths {
-        let output_dir = Path::new(output_dir_str);
-        for entry in common::recursive_ls(output_directory) {
-            let path = Path::new(&entry.path);
-            let without_prefix = path.strip_prefix(output_directory).unwrap();
-            let target_path = output_dir.join(without_prefix);
-
-            if entry.is_file {
-                let mut extension: String = "".to_string();
-                if path.extension().is_some() {
-                    extension = path.extension().unwrap().to_str().unwrap().to_string();
-                }
-                let parent_dir_name = path
-                    .parent()
-                    .unwrap()
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap();
-                if extension == "acd" || extension == "ini" || parent_dir_name == "data" {
-                    let _ = std::fs::create_dir_all(&target_path.parent().unwrap());
-                    let _ = fs_extra::file::copy(path, target_path, &copy_options);
-                }
-            } else {
-                let dirname = path.file_name().unwrap().to_str().unwrap();
-                if dirname == "skins" {
-                    let _ = std::fs::create_dir_all(&target_path);
+fn install_uploads(
+    config_lock: &RwLock<ConfigObject>,
+    user_name: &str,
+    remote_address: Option<String>,
+    multipart_form_data: &MultipartFormData,
+) -> Vec<InstallReport> {
+    let mut reports = vec![];
+    let file_fields = match multipart_form_data.files.get("file[]") {
+        Some(file_fields) => file_fields,
+        None => return reports,
+    };
+
+    // volumes are only collected here, sets are reported once every file is in
+    let mut volume_sets: Vec<PendingVolumeSet> = vec![];
+    for file in file_fields {
+        let file_name = match file.file_name {
+            Some(ref file_name) => file_name.clone(),
+            None => {
+                reports.push(InstallReport::failed(
+                    "",
+                    InstallError::Rejected("Missing file name".to_string()),
+                ));
+                continue;
+            }
+        };
+
+        let report = match archive_unpacker::parse_volume_name(&file_name) {
+            Some(volume) => {
+                let added = config_lock.read().unwrap().pending_volumes.add(
+                    &file.path,
+                    &file_name,
+                    &volume,
+                    user_name,
+                    SystemTime::now(),
+                );
+                match added {
+                    Ok(set) => {
+                        volume_sets.retain(|other| other.id != set.id);
+                        volume_sets.push(set);
+                        continue;
+                    }
+                    Err(error) => InstallReport::failed(&file_name, InstallError::Storage(error)),
                 }
             }
+            None => install_upload(
+                config_lock,
+                user_name,
+                &remote_address,
+                &file.path,
+                &file_name,
+            ),
+        };
+        if let Some(ref error) = report.error {
+            println!("Error while uploading '{}': {}", file_name, error);
         }
+        for warning in report.warnings.iter() {
+            println!("Warning while uploading '{}': {}", file_name, warning);
+        }
+        reports.push(report);
+    }
+
+    for set in volume_sets.iter() {
+        let report = install_volume_set(config_lock, user_name, &remote_address, set);
+        if let Some(ref error) = report.error {
+            println!("Error while uploading '{}': {}", report.file_name, error);
+        }
+        for warning in report.warnings.iter() {
+            println!(
+                "Warning while uploading '{}': {}",
+                report.file_name, warning
+            );
+        }
+        reports.push(report);
     }
+    reports
 }
 
 #[post("/mod_management/upload", data = "<data>")]
 pub fn mod_upload(
     content_type: &ContentType,
     data: Data,
-    mut cookies: Cookies,
+    user: Authorized<CanUploadMods>,
+    remote: Option<SocketAddr>,
     config_lock: State<RwLock<ConfigObject>>,
 ) -> Result<Flash<Redirect>, Flash<Redirect>> {
-    let user_name = get_user_name_from_cookie(&mut cookies);
-    if user_name.is_none() {
-        return Err(Flash::error(
-            Redirect::to(uri!(super::index)),
-            "Access denied",
-        ));
+    let multipart_form_data = parse_upload_form(content_type, data)
+        .map_err(|error| Flash::error(Redirect::to(uri!(mod_management)), error))?;
+    user.verify_csrf_token(form_csrf_token(&multipart_form_data))?;
+
+    let reports = install_uploads(
+        &config_lock,
+        &user.user_name,
+        remote.map(|address| address.ip().to_string()),
+        &multipart_form_data,
+    );
+
+    let good_mods_count = reports
+        .iter()
+        .filter(|report| report.is_installed())
+        .count();
+    let failures: Vec<String> = reports
+        .iter()
+        .filter_map(|report| {
+            report
+                .error
+                .as_ref()
+                .map(|error| format!("{} ({})", report.file_name, error))
+        })
+        .collect();
+    let conflicting_mods_count = reports
+        .iter()
+        .filter(|report| report.pending_upload_id.is_some())
+        .count();
+    let incomplete_sets: Vec<String> = reports
+        .iter()
+        .filter(|report| !report.received_volumes.is_empty())
+        .map(|report| {
+            format!(
+                "{} (received {})",
+                report.file_name,
+                report.received_volumes.join(", ")
+            )
+        })
+        .collect();
+
+    let warnings: Vec<String> = reports
+        .iter()
+        .flat_map(|report| {
+            report
+                .warnings
+                .iter()
+                .map(move |warning| format!("{} ({})", report.file_name, warning))
+        })
+        .collect();
+
+    let mut message = format!("{} mods uploaded successfully", good_mods_count);
+    if !failures.is_empty() {
+        message += &format!(", {} failed: {}", failures.len(), failures.join(", "));
+    }
+    if conflicting_mods_count > 0 {
+        message += &format!(
+            ", {} conflict with installed mods and wait for decision",
+            conflicting_mods_count
+        );
+    }
+    if !incomplete_sets.is_empty() {
+        message += &format!(
+            ", waiting for remaining volumes of {}",
+            incomplete_sets.join(", ")
+        );
+    }
+    if !warnings.is_empty() {
+        message += &format!(", inner archives left unpacked: {}", warnings.join(", "));
     }
-    std::mem::drop(cookies);
 
-    let mut config = config_lock.write().unwrap();
-    let mod_path = Path::new(&config.config.mod_storage_location);
+    if !failures.is_empty() || conflicting_mods_count > 0 {
+        return Err(Flash::error(Redirect::to(uri!(mod_management)), message));
+    }
+    Ok(Flash::success(
+        Redirect::to(uri!(mod_management)),
+        format!("{}.", message),
+    ))
+}
 
+/// Same as upload form, but responds with report of every archive.
+#[post("/mod_management/upload.json", data = "<data>")]
+pub fn mod_upload_json(
+    content_type: &ContentType,
+    data: Data,
+    user: Authorized<CanUploadMods>,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Json<Vec<InstallReport>>, status::Custom<Json<JsonError>>> {
+    let multipart_form_data = parse_upload_form(content_type, data)
+        .map_err(|error| json_error(Status::BadRequest, error))?;
+    user.verify_csrf_token(form_csrf_token(&multipart_form_data))
+        .map_err(|_| {
+            json_error(
+                Status::Forbidden,
+                "Invalid form token, reload the page and try again".to_string(),
+            )
+        })?;
+
+    Ok(Json(install_uploads(
+        &config_lock,
+        &user.user_name,
+        remote.map(|address| address.ip().to_string()),
+        &multipart_form_data,
+    )))
+}
+
+fn json_error(status: Status, error: String) -> status::Custom<Json<JsonError>> {
+    status::Custom(status, Json(JsonError { error: error }))
+}
+
+#[post("/mod_management/preview.json", data = "<data>")]
+pub fn mod_preview(
+    content_type: &ContentType,
+    data: Data,
+    user: Authorized<CanUploadMods>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Json<InstallPreview>, status::Custom<Json<JsonError>>> {
     let options = MultipartFormDataOptions::with_multipart_form_data_fields(vec![
-        MultipartFormDataField::file("file[]")
-            .size_limit(4 * 1024 * 1024 * 1024)
-            .repetition(Repetition::fixed(50)),
+        MultipartFormDataField::file("file").size_limit(4 * 1024 * 1024 * 1024),
+        MultipartFormDataField::text("csrf_token"),
     ]);
+    let multipart_form_data = MultipartFormData::parse(content_type, data, options)
+        .map_err(|error| json_error(Status::BadRequest, format!("{:?}", error)))?;
 
-    let multipart_form_data = MultipartFormData::parse(content_type, data, options).unwrap();
-
-    let mut good_mods_count = 0;
-    let mut bad_mods_count = 0;
-    let archives = multipart_form_data.files.get("file[]");
-    let server_paths = config.get_server_paths();
-    if let Some(file_fields) = archives {
-        for file in file_fields {
-            if file.file_name.is_none() {
-                bad_mods_count += 1;
-                continue;
-            }
+    user.verify_csrf_token(form_csrf_token(&multipart_form_data))
+        .map_err(|_| {
+            json_error(
+                Status::Forbidden,
+                "Invalid form token, reload the page and try again".to_string(),
+            )
+        })?;
 
-            let file_name = file.file_name.clone().unwrap();
-            let path = std::path::Path::new(&file_name);
-            let extension = path.extension();
-            if extension.is_none() {
-                bad_mods_count += 1;
-                continue;
-            }
+    let file = match multipart_form_data
+        .files
+        .get("file")
+        .and_then(|fields| fields.first())
+    {
+        Some(file) => file,
+        None => return Err(json_error(Status::BadRequest, "Missing file".to_string())),
+    };
+    let file_name = match file.file_name {
+        Some(ref file_name) => file_name.clone(),
+        None => {
+            return Err(json_error(
+                Status::BadRequest,
+                "Missing file name".to_string(),
+            ))
+        }
+    };
 
-            let extension = extension.unwrap().to_os_string();
-            if extension != "7z" && extension != "rar" && extension != "zip" {
-                bad_mods_count += 1;
-                continue;
-            }
+    // server paths and limits are copied, so reading the archive doesn't block other requests
+    let (server_paths, limits) = {
+        let config = config_lock.read().unwrap();
+        (
+            config.get_server_paths(),
+            config.config.archive_limits.clone(),
+        )
+    };
+    mod_installer::preview_archive(&file.path, &file_name, &server_paths, &limits)
+        .map(Json)
+        .map_err(|error| json_error(Status::UnprocessableEntity, error.to_string()))
+}
 
-            let output_path = mod_path.join(file_name);
-            if output_path.exists() {
-                bad_mods_count += 1;
-                continue;
-            }
+#[derive(FromForm)]
+pub struct RestoreVersionData {
+    csrf_token: String,
+    hash: String,
+    version_hash: String,
+}
 
-            let result = std::fs::copy(&file.path, &output_path);
-            if let Err(error) = result {
-                println!(
-                    "Error while copying file from '{:?}' to '{:?}', reason: {}",
-                    &file.path, output_path, error
-                );
-                bad_mods_count += 1;
-                continue;
-            }
+#[post("/mod_management/restore_version", data = "<data>")]
+pub fn mod_restore_version(
+    data: Form<RestoreVersionData>,
+    user: Authorized<CanDeleteMods>,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
 
-            install_mod(&output_path, &server_paths);
-            good_mods_count += 1;
+    let restored = match mod_installer::restore_version(&mut config, &data.hash, &data.version_hash)
+    {
+        Ok(restored) => restored,
+        Err(error) => {
+            return Err(Flash::error(
+                Redirect::to(uri!(mod_management)),
+                error.to_string(),
+            ))
         }
+    };
+
+    let mut entry = AuditEntry::new(
+        &user.user_name,
+        AuditAction::ModRestore,
+        Some(&restored.filename),
+        remote.map(|address| address.ip().to_string()),
+    );
+    entry.details = Some(format!(
+        "md5 {} restored as version {}, replaced md5 {}",
+        data.version_hash, restored.version, data.hash
+    ));
+    config.audit_log.record(entry);
+
+    Ok(Flash::success(
+        Redirect::to(uri!(mod_management)),
+        format!(
+            "{} restored as version {}.",
+            restored.filename, restored.version
+        ),
+    ))
+}
+
+#[derive(FromForm)]
+pub struct ResolveConflictData {
+    action: String,
+    csrf_token: String,
+    id: String,
+}
+
+#[post("/mod_management/resolve_conflict", data = "<data>")]
+pub fn mod_resolve_conflict(
+    data: Form<ResolveConflictData>,
+    user: Authorized<CanUploadMods>,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let error = |message: String| Flash::error(Redirect::to(uri!(mod_management)), message);
+    let resolution: ConflictResolution = data.action.parse().map_err(error)?;
+    let mut config = config_lock.write().unwrap();
+
+    let can_delete = user.role.has_permission(Permission::DeleteMods);
+    let upload = config
+        .pending_uploads
+        .list()
+        .into_iter()
+        .find(|upload| upload.id == data.id)
+        .filter(|upload| can_delete || upload.uploader == user.user_name);
+    let upload = match upload {
+        Some(upload) => upload,
+        None => return Err(error("Pending upload not found".to_string())),
+    };
+    // keeping both overwrites files of installed mods, which is as destructive as replacing them
+    if resolution != ConflictResolution::Abort && !can_delete {
+        return Err(error(
+            "Installing over files of other mods requires permission to delete mods".to_string(),
+        ));
     }
 
-    if let Err(error) = config.rebuild_mod_storage(false) {
-        return Err(Flash::error(
+    let upload = match config.pending_uploads.take(&upload.id) {
+        Some(upload) => upload,
+        None => return Err(error("Pending upload not found".to_string())),
+    };
+    if resolution == ConflictResolution::Abort {
+        config.pending_uploads.discard(&upload);
+        return Ok(Flash::success(
             Redirect::to(uri!(mod_management)),
-            format!(
-                "{} mods uploaded successfully, {} failed. Error while rebuilding mod storage: {}.",
-                good_mods_count, bad_mods_count, error
-            ),
+            format!("Upload of {} aborted.", upload.file_name),
         ));
     }
 
-    if bad_mods_count > 0 {
-        return Err(Flash::error(
-            Redirect::to(uri!(mod_management)),
-            format!(
-                "{} mods uploaded successfully, {} failed",
-                good_mods_count, bad_mods_count
-            ),
+    let archive_path = config.pending_uploads.archive_path(&upload);
+    let result =
+        mod_installer::import_archive(&mut config, &archive_path, &upload.file_name, resolution);
+    config.pending_uploads.discard(&upload);
+    let replaced = match result {
+        Ok(ImportResult::Imported { replaced, .. }) => replaced,
+        Ok(ImportResult::Conflicts(_)) => {
+            return Err(error(format!("{} still conflicts", upload.file_name)))
+        }
+        Err(message) => {
+            println!("Error while uploading '{}': {}", upload.file_name, message);
+            return Err(error(format!(
+                "Failed to install {}: {}",
+                upload.file_name, message
+            )));
+        }
+    };
+
+    let remote_address = remote.map(|address| address.ip().to_string());
+    for acmod in replaced.iter() {
+        let mut entry = AuditEntry::new(
+            &user.user_name,
+            AuditAction::ModDelete,
+            Some(&acmod.filename),
+            remote_address.clone(),
+        );
+        entry.details = Some(format!(
+            "md5 {}, replaced by {}",
+            acmod.checksum_md5, upload.file_name
         ));
+        config.audit_log.record(entry);
+    }
+    let mut entry = AuditEntry::new(
+        &user.user_name,
+        AuditAction::ModUpload,
+        Some(&upload.file_name),
+        remote_address,
+    );
+    if upload.uploader != user.user_name {
+        entry.details = Some(format!("uploaded by {}", upload.uploader));
+    }
+    config.audit_log.record(entry);
+
+    Ok(Flash::success(
+        Redirect::to(uri!(mod_management)),
+        format!(
+            "{} installed, {} mods replaced.",
+            upload.file_name,
+            replaced.len()
+        ),
+    ))
+}
+
+#[derive(FromForm)]
+pub struct DiscardVolumesData {
+    csrf_token: String,
+    id: String,
+}
+
+#[post("/mod_management/discard_volumes", data = "<data>")]
+pub fn mod_discard_volumes(
+    data: Form<DiscardVolumesData>,
+    user: Authorized<CanUploadMods>,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let config = config_lock.read().unwrap();
+
+    let can_delete = user.role.has_permission(Permission::DeleteMods);
+    let set = config
+        .pending_volumes
+        .list()
+        .into_iter()
+        .find(|set| set.id == data.id)
+        .filter(|set| can_delete || set.uploader == user.user_name)
+        .and_then(|set| config.pending_volumes.take(&set.id));
+    let set = match set {
+        Some(set) => set,
+        None => {
+            return Err(Flash::error(
+                Redirect::to(uri!(mod_management)),
+                "Pending volumes not found",
+            ))
+        }
+    };
+    config.pending_volumes.discard(&set);
+
+    let mut entry = AuditEntry::new(
+        &user.user_name,
+        AuditAction::ModDiscardVolumes,
+        Some(&set.joined_file_name()),
+        remote.map(|address| address.ip().to_string()),
+    );
+    if set.uploader != user.user_name {
+        entry.details = Some(format!("uploaded by {}", set.uploader));
     }
+    config.audit_log.record(entry);
 
     Ok(Flash::success(
         Redirect::to(uri!(mod_management)),
-        format!("{} mods uploaded successfully.", good_mods_count),
+        format!("Volumes of {} discarded.", set.joined_file_name()),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+    use rocket::local::Client;
+
+    use crate::config::{ConfigTrait, Role};
+    use crate::install_manifest::InstallManifest;
+    use crate::mod_metadata::ModMetadata;
+    use crate::tests::mocks::unpack_archive_mock::mock_unpacker as unpacker;
+    use crate::tests::test_archive::{archive_content, with_mock_archives};
+    use crate::tests::test_server::{config, csrf_token, login, test_client};
+
+    fn add_test_mod(client: &Client) -> String {
+        let mut config = config(client).write().unwrap();
+        let mod_path = std::path::Path::new(&config.config.mod_storage_location).join("car.zip");
+        std::fs::write(&mod_path, "not really a zip").unwrap();
+        config
+            .add_mod(
+                &mod_path,
+                ModMetadata::default(),
+                InstallManifest::default(),
+                None,
+            )
+            .unwrap()
+            .checksum_md5
+    }
+
+    fn mod_exists(client: &Client, hash: &String) -> bool {
+        let config = config(client).read().unwrap();
+        config
+            .get_mod_list()
+            .iter()
+            .any(|acmod| acmod.checksum_md5 == *hash)
+    }
+
+    fn post_delete(client: &Client, body: String) -> Status {
+        client
+            .post("/mod_management/delete")
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch()
+            .status()
+    }
+
+    #[test]
+    fn test_mod_delete_is_not_available_through_get() {
+        let (client, _temp_dir) = test_client(&[("admin", Role::Admin)]);
+        login(&client, "admin");
+        let hash = add_test_mod(&client);
+
+        let response = client
+            .get(format!("/mod_management/delete?hash={}", hash))
+            .dispatch();
+        assert_eq!(Status::NotFound, response.status());
+        assert!(mod_exists(&client, &hash));
+    }
+
+    #[test]
+    fn test_mod_delete_rejects_forged_request() {
+        let (client, _temp_dir) = test_client(&[("admin", Role::Admin)]);
+        login(&client, "admin");
+        let hash = add_test_mod(&client);
+
+        assert_eq!(
+            Status::UnprocessableEntity,
+            post_delete(&client, format!("hash={}", hash))
+        );
+        assert!(mod_exists(&client, &hash));
+
+        let response = client
+            .post("/mod_management/delete")
+            .header(ContentType::Form)
+            .body(format!("hash={}&csrf_token=forged", hash))
+            .dispatch();
+        assert_eq!(Status::SeeOther, response.status());
+        assert_eq!(Some("/"), response.headers().get_one("Location"));
+        assert!(mod_exists(&client, &hash));
+
+        let token = csrf_token(&client, "admin");
+        assert_eq!(
+            Status::SeeOther,
+            post_delete(&client, format!("hash={}&csrf_token={}", hash, token))
+        );
+        assert!(!mod_exists(&client, &hash));
+    }
+
+    #[test]
+    fn test_csrf_token_of_other_session_is_rejected() {
+        let (client, _temp_dir) =
+            test_client(&[("admin", Role::Admin), ("operator", Role::Operator)]);
+        login(&client, "operator");
+        let other_token = csrf_token(&client, "operator");
+        login(&client, "admin");
+        let hash = add_test_mod(&client);
+
+        post_delete(&client, format!("hash={}&csrf_token={}", hash, other_token));
+        assert!(mod_exists(&client, &hash));
+    }
+
+    fn add_pending_upload(client: &Client, uploader: &str) -> String {
+        let config = config(client).read().unwrap();
+        let source_path = std::path::Path::new(&config.config.mod_storage_location)
+            .parent()
+            .unwrap()
+            .join("upload");
+        std::fs::write(&source_path, "not really a zip").unwrap();
+        config
+            .pending_uploads
+            .add(&source_path, "car.zip", uploader, vec![])
+            .unwrap()
+            .id
+    }
+
+    fn post_resolve(client: &Client, login: &str, id: &str, action: &str) {
+        let response = client
+            .post("/mod_management/resolve_conflict")
+            .header(ContentType::Form)
+            .body(format!(
+                "action={}&csrf_token={}&id={}",
+                action,
+                csrf_token(client, login),
+                id
+            ))
+            .dispatch();
+        assert_eq!(Status::SeeOther, response.status());
+    }
+
+    fn pending_upload_count(client: &Client) -> usize {
+        config(client).read().unwrap().pending_uploads.list().len()
+    }
+
+    #[test]
+    fn test_resolve_conflict_permissions() {
+        let (client, _temp_dir) = test_client(&[
+            ("operator", Role::Operator),
+            ("uploader", Role::Uploader),
+            ("other", Role::Uploader),
+        ]);
+        let id = add_pending_upload(&client, "uploader");
+
+        // other uploaders don't see it, uploader can't replace or overwrite installed mods
+        login(&client, "other");
+        post_resolve(&client, "other", &id, "abort");
+        assert_eq!(1, pending_upload_count(&client));
+        login(&client, "uploader");
+        post_resolve(&client, "uploader", &id, "replace");
+        assert_eq!(1, pending_upload_count(&client));
+        post_resolve(&client, "uploader", &id, "keep_both");
+        assert_eq!(1, pending_upload_count(&client));
+
+        login(&client, "operator");
+        post_resolve(&client, "operator", &id, "abort");
+        assert_eq!(0, pending_upload_count(&client));
+        assert!(config(&client).read().unwrap().get_mod_list().is_empty());
+    }
+
+    const BOUNDARY: &str = "X-BOUNDARY";
+
+    fn multipart_upload(client: &Client, user: &str, field: &str, file_name: &str) -> String {
+        multipart_file(client, user, field, file_name, "not an archive")
+    }
+
+    fn multipart_file(
+        client: &Client,
+        user: &str,
+        field: &str,
+        file_name: &str,
+        content: &str,
+    ) -> String {
+        format!(
+            "--{boundary}\r\n\
+             Content-Disposition: form-data; name=\"csrf_token\"\r\n\r\n\
+             {token}\r\n\
+             --{boundary}\r\n\
+             Content-Disposition: form-data; name=\"{field}\"; filename=\"{file_name}\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\n\
+             {content}\r\n\
+             --{boundary}--\r\n",
+            boundary = BOUNDARY,
+            token = csrf_token(client, user),
+            field = field,
+            file_name = file_name,
+            content = content
+        )
+    }
+
+    fn upload_json(
+        client: &Client,
+        user: &str,
+        file_name: &str,
+        content: &str,
+    ) -> serde_json::Value {
+        let mut response = client
+            .post("/mod_management/upload.json")
+            .header(multipart_content_type())
+            .body(multipart_file(client, user, "file[]", file_name, content))
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+        serde_json::from_str(&response.body_string().unwrap()).unwrap()
+    }
+
+    fn multipart_content_type() -> ContentType {
+        ContentType::with_params("multipart", "form-data", ("boundary", BOUNDARY))
+    }
+
+    #[test]
+    fn test_preview_rejects_unsupported_archive() {
+        let (client, _temp_dir) = test_client(&[("uploader", Role::Uploader)]);
+        login(&client, "uploader");
+
+        let mut response = client
+            .post("/mod_management/preview.json")
+            .header(multipart_content_type())
+            .body(multipart_upload(&client, "uploader", "file", "car.txt"))
+            .dispatch();
+
+        assert_eq!(Status::UnprocessableEntity, response.status());
+        assert!(response
+            .body_string()
+            .unwrap()
+            .contains("Unsupported file extension: txt"));
+    }
+
+    #[test]
+    fn test_upload_json_reports_failed_archive() {
+        let (client, _temp_dir) = test_client(&[("uploader", Role::Uploader)]);
+        login(&client, "uploader");
+
+        let mut response = client
+            .post("/mod_management/upload.json")
+            .header(multipart_content_type())
+            .body(multipart_upload(&client, "uploader", "file[]", "car.txt"))
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+
+        let reports: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(1, reports.as_array().unwrap().len());
+        assert_eq!("car.txt", reports[0]["file_name"]);
+        assert_eq!("rejected", reports[0]["error"]["kind"]);
+        assert!(reports[0].get("checksum_md5").is_none());
+        assert!(config(&client).read().unwrap().get_mod_list().is_empty());
+    }
+
+    #[test]
+    fn test_upload_json_holds_incomplete_volume_set() {
+        let (client, _temp_dir) = test_client(&[("uploader", Role::Uploader)]);
+        login(&client, "uploader");
+
+        let mut response = client
+            .post("/mod_management/upload.json")
+            .header(multipart_content_type())
+            .body(multipart_upload(
+                &client,
+                "uploader",
+                "file[]",
+                "pack.7z.001",
+            ))
+            .dispatch();
+        assert_eq!(Status::Ok, response.status());
+
+        let reports: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        assert_eq!(1, reports.as_array().unwrap().len());
+        assert_eq!("pack.7z", reports[0]["file_name"]);
+        assert_eq!(
+            serde_json::json!(["pack.7z.001"]),
+            reports[0]["received_volumes"]
+        );
+        assert!(reports[0].get("error").is_none());
+
+        let config = config(&client).read().unwrap();
+        let sets = config.pending_volumes.list();
+        assert_eq!(1, sets.len());
+        assert_eq!("uploader", sets[0].uploader);
+        assert!(config.get_mod_list().is_empty());
+    }
+
+    #[test]
+    fn test_upload_with_conflicts_is_kept_pending() {
+        let (client, _temp_dir) = test_client(&[("uploader", Role::Uploader)]);
+        login(&client, "uploader");
+        let car: &[&str] = &["content/cars/car/data.acd"];
+        let pack: &[&str] = &["content/cars/car/data.acd", "content/cars/other/data.acd"];
+
+        with_mock_archives(&[("car.zip", car), ("pack.zip", pack)], || {
+            let reports = upload_json(&client, "uploader", "car.zip", &archive_content("car.zip"));
+            assert!(reports[0].get("checksum_md5").is_some());
+            let reports = upload_json(
+                &client,
+                "uploader",
+                "pack.zip",
+                &archive_content("pack.zip"),
+            );
+            assert!(reports[0].get("checksum_md5").is_none());
+            assert_eq!(
+                "content/cars/car/data.acd",
+                reports[0]["conflicts"][0]["path"]
+            );
+
+            let config = config(&client).read().unwrap();
+            let pending_uploads = config.pending_uploads.list();
+            assert_eq!(1, pending_uploads.len());
+            assert_eq!(
+                reports[0]["pending_upload_id"],
+                pending_uploads[0].id.as_str()
+            );
+            assert_eq!("pack.zip", pending_uploads[0].file_name);
+            assert_eq!(1, config.get_mod_list().len());
+        });
+    }
+
+    const RAR_VOLUME: &str = "Rar!\x1a\x07\x01\x00";
+
+    #[test]
+    fn test_volume_set_completed_later_is_installed_once() {
+        let (client, _temp_dir) = test_client(&[("uploader", Role::Uploader)]);
+        login(&client, "uploader");
+        let car: &[&str] = &["content/cars/car/data.acd"];
+
+        // unpacked set is packed into pack.tar, which is known to the mock by name
+        with_mock_archives(&[("pack.tar", car)], || {
+            let list_context = unpacker::rar_list_entries_context();
+            list_context
+                .expect()
+                .returning(|archive_path: &std::path::Path| {
+                    if !archive_path.with_file_name("pack.part2.rar").exists() {
+                        return Err(compress_tools::Error::from("Missing volume".to_string()));
+                    }
+                    Ok(vec![])
+                });
+            let unpack_context = unpacker::rar_unpack_context();
+            unpack_context.expect().times(1).returning(
+                |_: &std::path::Path, destination_path: &std::path::Path| {
+                    let path = destination_path.join("content/cars/car/data.acd");
+                    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    std::fs::write(&path, "data.acd").unwrap();
+                    Ok(())
+                },
+            );
+
+            let reports = upload_json(&client, "uploader", "pack.part1.rar", RAR_VOLUME);
+            assert_eq!(
+                serde_json::json!(["pack.part1.rar"]),
+                reports[0]["received_volumes"]
+            );
+            assert!(config(&client).read().unwrap().get_mod_list().is_empty());
+
+            let reports = upload_json(&client, "uploader", "pack.part2.rar", RAR_VOLUME);
+            assert_eq!(1, reports.as_array().unwrap().len());
+            assert_eq!("pack.tar", reports[0]["file_name"]);
+            assert!(reports[0].get("error").is_none());
+            assert!(reports[0].get("checksum_md5").is_some());
+
+            let config = config(&client).read().unwrap();
+            let mods = config.get_mod_list();
+            assert_eq!(1, mods.len());
+            assert_eq!("pack.tar", mods[0].filename);
+            assert!(config.pending_volumes.list().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_discard_volumes_permissions() {
+        let (client, _temp_dir) = test_client(&[
+            ("operator", Role::Operator),
+            ("uploader", Role::Uploader),
+            ("other", Role::Uploader),
+        ]);
+        login(&client, "uploader");
+        upload_json(&client, "uploader", "pack.7z.001", "not an archive");
+        let id = config(&client).read().unwrap().pending_volumes.list()[0]
+            .id
+            .clone();
+        let post_discard = |login: &str| {
+            let response = client
+                .post("/mod_management/discard_volumes")
+                .header(ContentType::Form)
+                .body(format!(
+                    "csrf_token={}&id={}",
+                    csrf_token(&client, login),
+                    id
+                ))
+                .dispatch();
+            assert_eq!(Status::SeeOther, response.status());
+        };
+
+        login(&client, "other");
+        post_discard("other");
+        assert_eq!(
+            1,
+            config(&client).read().unwrap().pending_volumes.list().len()
+        );
+
+        login(&client, "operator");
+        post_discard("operator");
+        assert!(config(&client)
+            .read()
+            .unwrap()
+            .pending_volumes
+            .list()
+            .is_empty());
+    }
+}
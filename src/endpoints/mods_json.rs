@@ -1,38 +1,59 @@
+use std::collections::BTreeMap;
 use std::sync::RwLock;
 
-use rocket::http::Cookies;
+use chrono::{DateTime, Utc};
 use rocket::State;
 use rocket_contrib::json::Json;
 
+use super::guards::{Authorized, CanDownloadMods};
+use super::images::image_url;
 use crate::config::ConfigObject;
+use crate::mod_metadata::ContentMetadata;
 
 #[derive(serde::Serialize)]
 pub struct JsonModTemplate {
     checksum_md5: String,
     filename: String,
     size_in_bytes: u64,
+    version: u32,
+    updated_at: Option<DateTime<Utc>>,
+    cars: Vec<ContentMetadata>,
+    tracks: Vec<ContentMetadata>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    content_sources: BTreeMap<String, String>,
+}
+
+// database keeps names in image cache, clients get URLs they can fetch
+fn with_image_urls(contents: &Vec<ContentMetadata>) -> Vec<ContentMetadata> {
+    let mut contents = contents.clone();
+    for content in contents.iter_mut() {
+        for image in content.images.iter_mut() {
+            image.image = image_url(&image.image);
+            image.thumbnail = image_url(&image.thumbnail);
+        }
+    }
+    contents
 }
 
 #[get("/mods.json")]
 pub fn mods_json(
-    mut cookies: Cookies,
+    _user: Authorized<CanDownloadMods>,
     config_lock: State<RwLock<ConfigObject>>,
-) -> Result<Json<Vec<JsonModTemplate>>, &'static str> {
-    let user_name = super::get_user_name_from_cookie(&mut cookies);
-    if user_name.is_none() {
-        return Err("Not logged in");
-    }
-    std::mem::drop(cookies);
-
+) -> Json<Vec<JsonModTemplate>> {
     let config = config_lock.read().unwrap();
 
     let mut mods = vec![];
-    for acmod in config.config.mods.iter() {
+    for acmod in config.mod_database.mods.iter() {
         mods.push(JsonModTemplate {
             checksum_md5: acmod.checksum_md5.clone(),
             filename: acmod.filename.clone(),
             size_in_bytes: acmod.size_in_bytes,
+            version: acmod.version,
+            updated_at: acmod.updated_at,
+            cars: with_image_urls(&acmod.cars),
+            tracks: with_image_urls(&acmod.tracks),
+            content_sources: acmod.content_sources.clone(),
         });
     }
-    Ok(Json(mods))
+    Json(mods)
 }
@@ -0,0 +1,135 @@
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use rocket::http::{Cookie, Cookies};
+use rocket::request::{FlashMessage, Form};
+use rocket::response::{Flash, Redirect};
+use rocket::State;
+use rocket_contrib::templates::Template;
+
+use super::guards::{AuthenticatedUser, SESSION_COOKIE};
+use crate::config::{ConfigObject, Permission};
+
+#[derive(serde::Serialize)]
+struct SessionTemplate {
+    id: String,
+    remote_address: String,
+    created: String,
+    last_seen: String,
+    is_current: bool,
+}
+
+#[derive(serde::Serialize)]
+struct SessionsTemplateContext {
+    can_manage_users: bool,
+    csrf_token: String,
+    flash: Option<String>,
+    flash_type: Option<String>,
+    sessions: Vec<SessionTemplate>,
+    user_name: String,
+}
+
+fn format_age(time: SystemTime, now: SystemTime) -> String {
+    let minutes = now.duration_since(time).unwrap_or_default().as_secs() / 60;
+    match minutes {
+        0 => "just now".to_string(),
+        1..=59 => format!("{} min ago", minutes),
+        _ => format!("{} h {} min ago", minutes / 60, minutes % 60),
+    }
+}
+
+#[get("/sessions")]
+pub fn sessions(
+    flash: Option<FlashMessage<'_, '_>>,
+    user: AuthenticatedUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Template {
+    let config = config_lock.read().unwrap();
+    let now = SystemTime::now();
+
+    let mut context = SessionsTemplateContext {
+        can_manage_users: user.role.has_permission(Permission::ManageUsers),
+        csrf_token: user.csrf_token.clone(),
+        flash: None,
+        flash_type: None,
+        sessions: vec![],
+        user_name: user.user_name.clone(),
+    };
+
+    for session in config.sessions.list_for_user(&user.user_name) {
+        context.sessions.push(SessionTemplate {
+            remote_address: session.remote_address.unwrap_or("unknown".to_string()),
+            created: format_age(session.created_at, now),
+            last_seen: format_age(session.last_seen, now),
+            is_current: session.id == user.session_id,
+            id: session.id,
+        });
+    }
+
+    if let Some(ref msg) = flash {
+        context.flash = Some(msg.msg().to_string());
+        if msg.name() == "error" {
+            context.flash_type = Some("Error".to_string());
+        }
+    }
+    Template::render("sessions", &context)
+}
+
+#[derive(FromForm)]
+pub struct RevokeSessionData {
+    csrf_token: String,
+    id: String,
+}
+
+#[post("/sessions/revoke", data = "<data>")]
+pub fn sessions_revoke_post(
+    data: Form<RevokeSessionData>,
+    user: AuthenticatedUser,
+    mut cookies: Cookies,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let config = config_lock.read().unwrap();
+
+    if !config.sessions.revoke(&user.user_name, &data.id) {
+        return Err(Flash::error(
+            Redirect::to(uri!(sessions)),
+            "Session not found",
+        ));
+    }
+
+    if data.id == user.session_id {
+        cookies.remove_private(Cookie::named(SESSION_COOKIE));
+        return Ok(Flash::success(
+            Redirect::to(uri!(super::login_page_get)),
+            "Session revoked",
+        ));
+    }
+    Ok(Flash::success(
+        Redirect::to(uri!(sessions)),
+        "Session revoked",
+    ))
+}
+
+#[derive(FromForm)]
+pub struct RevokeAllSessionsData {
+    csrf_token: String,
+}
+
+#[post("/sessions/revoke_all", data = "<data>")]
+pub fn sessions_revoke_all_post(
+    data: Form<RevokeAllSessionsData>,
+    user: AuthenticatedUser,
+    mut cookies: Cookies,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let config = config_lock.read().unwrap();
+
+    let count = config.sessions.revoke_user(&user.user_name);
+    cookies.remove_private(Cookie::named(SESSION_COOKIE));
+    Ok(Flash::success(
+        Redirect::to(uri!(super::login_page_get)),
+        format!("{} sessions revoked", count),
+    ))
+}
@@ -0,0 +1,151 @@
+use std::sync::RwLock;
+
+use qrcode::render::svg;
+use qrcode::QrCode;
+use rocket::request::{FlashMessage, Form};
+use rocket::response::{Flash, Redirect};
+use rocket::State;
+use rocket_contrib::templates::Template;
+
+use super::guards::{AuthenticatedUser, TotpSetupUser};
+use crate::config::{ConfigObject, ConfigTrait, Permission};
+
+#[derive(serde::Serialize)]
+struct TwoFactorTemplateContext {
+    can_manage_users: bool,
+    csrf_token: String,
+    enabled: bool,
+    flash: Option<String>,
+    flash_type: Option<String>,
+    provisioning_uri: Option<String>,
+    qr_svg: Option<String>,
+    recovery_codes: Vec<String>,
+    required: bool,
+    secret: Option<String>,
+    user_name: String,
+}
+
+fn build_context(user: &AuthenticatedUser, config: &ConfigObject) -> TwoFactorTemplateContext {
+    TwoFactorTemplateContext {
+        can_manage_users: user.role.has_permission(Permission::ManageUsers),
+        csrf_token: user.csrf_token.clone(),
+        enabled: config.is_totp_enabled(&user.user_name),
+        flash: None,
+        flash_type: None,
+        provisioning_uri: None,
+        qr_svg: None,
+        recovery_codes: vec![],
+        required: config.needs_totp_enrollment(&user.user_name),
+        secret: None,
+        user_name: user.user_name.clone(),
+    }
+}
+
+#[get("/two_factor")]
+pub fn two_factor(
+    flash: Option<FlashMessage<'_, '_>>,
+    user: TotpSetupUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Template {
+    let config = config_lock.read().unwrap();
+    let mut context = build_context(&user.0, &config);
+
+    if let Some(ref msg) = flash {
+        context.flash = Some(msg.msg().to_string());
+        if msg.name() == "error" {
+            context.flash_type = Some("Error".to_string());
+        }
+    }
+    Template::render("two_factor", &context)
+}
+
+#[derive(FromForm)]
+pub struct TwoFactorSetupData {
+    csrf_token: String,
+}
+
+// Secret is rendered directly instead of redirecting, so it never ends up in flash cookie
+#[post("/two_factor/setup", data = "<data>")]
+pub fn two_factor_setup_post(
+    data: Form<TwoFactorSetupData>,
+    user: TotpSetupUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Template, Flash<Redirect>> {
+    let TotpSetupUser(user) = user;
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+
+    let totp = match config.begin_totp_enrollment(&user.user_name) {
+        Ok(totp) => totp,
+        Err(error) => return Err(Flash::error(Redirect::to(uri!(two_factor)), error)),
+    };
+
+    let provisioning_uri = totp.provisioning_uri(&user.user_name);
+    let mut context = build_context(&user, &config);
+    context.qr_svg = match QrCode::new(provisioning_uri.as_bytes()) {
+        Ok(qr_code) => Some(
+            qr_code
+                .render::<svg::Color>()
+                .min_dimensions(200, 200)
+                .build(),
+        ),
+        Err(error) => {
+            println!("Error generating QR code: {}", error);
+            None
+        }
+    };
+    context.provisioning_uri = Some(provisioning_uri);
+    context.secret = Some(totp.secret);
+    Ok(Template::render("two_factor", &context))
+}
+
+#[derive(FromForm)]
+pub struct TwoFactorCodeData {
+    code: String,
+    csrf_token: String,
+}
+
+#[post("/two_factor/confirm", data = "<data>")]
+pub fn two_factor_confirm_post(
+    data: Form<TwoFactorCodeData>,
+    user: TotpSetupUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Template, Flash<Redirect>> {
+    let TotpSetupUser(user) = user;
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+
+    let recovery_codes = match config.confirm_totp_enrollment(&user.user_name, &data.code) {
+        Ok(recovery_codes) => recovery_codes,
+        Err(error) => return Err(Flash::error(Redirect::to(uri!(two_factor)), error)),
+    };
+
+    let mut context = build_context(&user, &config);
+    context.flash = Some("Two-factor authentication enabled".to_string());
+    context.recovery_codes = recovery_codes;
+    Ok(Template::render("two_factor", &context))
+}
+
+#[post("/two_factor/disable", data = "<data>")]
+pub fn two_factor_disable_post(
+    data: Form<TwoFactorCodeData>,
+    user: TotpSetupUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let TotpSetupUser(user) = user;
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+
+    // stolen session alone is not enough to turn it off
+    if !config.verify_second_factor(&user.user_name, &data.code) {
+        return Err(Flash::error(Redirect::to(uri!(two_factor)), "Wrong code"));
+    }
+    if let Err(error) = config.disable_totp(&user.user_name) {
+        return Err(Flash::error(Redirect::to(uri!(two_factor)), error));
+    }
+
+    Ok(Flash::success(
+        Redirect::to(uri!(two_factor)),
+        "Two-factor authentication disabled",
+    ))
+}
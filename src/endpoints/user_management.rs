@@ -1,65 +1,90 @@
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::RwLock;
+use std::time::SystemTime;
 
-use rocket::http::Cookies;
+use chrono::{DateTime, Utc};
+use rocket::http::{Cookie, Cookies};
 use rocket::request::{FlashMessage, Form};
 use rocket::response::{Flash, Redirect};
 use rocket::State;
 use rocket_contrib::templates::Template;
 
-use crate::config::{ConfigObject, ConfigTrait};
+use super::guards::{AdminUser, AuthenticatedUser, SESSION_COOKIE};
+use crate::config::{AuditAction, AuditEntry, ConfigObject, ConfigTrait, Permission, Role};
+
+#[derive(serde::Serialize)]
+struct RoleTemplate {
+    value: &'static str,
+    name: &'static str,
+    selected: bool,
+}
 
 #[derive(serde::Serialize)]
 struct UserTemplate {
     login: String,
-    is_admin: bool,
+    role: &'static str,
+    roles: Vec<RoleTemplate>,
+    is_current_user: bool,
+    is_locked: bool,
+    session_count: usize,
+    totp_enabled: bool,
 }
 
 #[derive(serde::Serialize)]
 struct UserManagementTemplateContext {
+    csrf_token: String,
     flash: Option<String>,
     flash_type: Option<String>,
+    require_totp_for_admins: bool,
+    roles: Vec<RoleTemplate>,
     user_name: String,
     users: Vec<UserTemplate>,
 }
 
+fn role_templates(selected_role: Role) -> Vec<RoleTemplate> {
+    Role::ALL
+        .iter()
+        .map(|role| RoleTemplate {
+            value: role.as_str(),
+            name: role.display_name(),
+            selected: *role == selected_role,
+        })
+        .collect()
+}
+
 #[get("/user_management")]
 pub fn user_management(
     flash: Option<FlashMessage<'_, '_>>,
-    mut cookies: Cookies,
+    user: AdminUser,
     config_lock: State<RwLock<ConfigObject>>,
-) -> Result<Template, Flash<Redirect>> {
-    let user_name = super::get_user_name_from_cookie(&mut cookies);
-    if user_name.is_none() {
-        return Err(Flash::error(
-            Redirect::to(uri!(super::login_page_get)),
-            "You need to be logged in to view this page",
-        ));
-    }
-    std::mem::drop(cookies);
-
+) -> Template {
     let config = config_lock.read().unwrap();
-    let user_name = user_name.unwrap();
-    let is_admin = config.is_user_admin(&user_name);
-
-    if !is_admin {
-        return Err(Flash::error(
-            Redirect::to(uri!(super::index)),
-            "Access denied",
-        ));
-    }
+    let now = SystemTime::now();
 
     let mut context = UserManagementTemplateContext {
+        csrf_token: user.csrf_token(),
         flash: None,
         flash_type: None,
-        user_name: user_name,
+        require_totp_for_admins: config.config.require_totp_for_admins,
+        roles: role_templates(Role::default()),
+        user_name: user.user_name,
         users: vec![],
     };
 
     for user in config.config.users.iter() {
         context.users.push(UserTemplate {
             login: user.login.clone(),
-            is_admin: user.is_admin,
+            role: user.role.display_name(),
+            roles: role_templates(user.role),
+            is_current_user: user.login == context.user_name,
+            is_locked: config.login_throttle.is_locked(
+                &user.login,
+                &config.config.login_throttle,
+                now,
+            ),
+            session_count: config.sessions.list_for_user(&user.login).len(),
+            totp_enabled: config.is_totp_enabled(&user.login),
         });
     }
 
@@ -69,30 +94,18 @@ pub fn user_management(
             context.flash_type = Some("Error".to_string());
         }
     }
-    Ok(Template::render("user_management", &context))
+    Template::render("user_management", &context)
 }
 
 #[get("/user_management/change_password?<username>")]
 pub fn user_management_change_password_get(
     flash: Option<FlashMessage<'_, '_>>,
-    mut cookies: Cookies,
+    user: AuthenticatedUser,
     username: String,
-    config_lock: State<RwLock<ConfigObject>>,
 ) -> Result<Template, Flash<Redirect>> {
-    let user_name = super::get_user_name_from_cookie(&mut cookies);
-    if user_name.is_none() {
-        return Err(Flash::error(
-            Redirect::to(uri!(super::login_page_get)),
-            "You need to be logged in to view this page",
-        ));
-    }
-    std::mem::drop(cookies);
+    let can_manage_users = user.role.has_permission(Permission::ManageUsers);
 
-    let config = config_lock.read().unwrap();
-    let user_name = user_name.unwrap();
-    let is_admin = config.is_user_admin(&user_name);
-
-    if !is_admin && username != user_name {
+    if !can_manage_users && username != user.user_name {
         return Err(Flash::error(
             Redirect::to(uri!(super::index)),
             "Access denied",
@@ -101,8 +114,9 @@ pub fn user_management_change_password_get(
 
     let mut context = HashMap::new();
     context.insert("user_name", username);
-    if is_admin {
-        context.insert("is_admin", "yes".to_string());
+    context.insert("csrf_token", user.csrf_token);
+    if can_manage_users {
+        context.insert("can_manage_users", "yes".to_string());
     }
     if let Some(ref msg) = flash {
         context.insert("flash", msg.msg().to_string());
@@ -115,6 +129,7 @@ pub fn user_management_change_password_get(
 
 #[derive(FromForm)]
 pub struct ChangePasswordData {
+    csrf_token: String,
     login: String,
     password1: String,
     password2: String,
@@ -123,23 +138,16 @@ pub struct ChangePasswordData {
 #[post("/user_management/change_password", data = "<data>")]
 pub fn user_management_change_password_post(
     data: Form<ChangePasswordData>,
+    user: AuthenticatedUser,
     mut cookies: Cookies,
+    remote: Option<SocketAddr>,
     config_lock: State<RwLock<ConfigObject>>,
 ) -> Result<Flash<Redirect>, Flash<Redirect>> {
-    let user_name = super::get_user_name_from_cookie(&mut cookies);
-    if user_name.is_none() {
-        return Err(Flash::error(
-            Redirect::to(uri!(super::index)),
-            "Access denied",
-        ));
-    }
-    std::mem::drop(cookies);
-
+    user.verify_csrf_token(&data.csrf_token)?;
     let mut config = config_lock.write().unwrap();
-    let user_name = user_name.unwrap();
-    let is_admin = config.is_user_admin(&user_name);
+    let can_manage_users = user.role.has_permission(Permission::ManageUsers);
 
-    if !is_admin && data.login != user_name {
+    if !can_manage_users && data.login != user.user_name {
         return Err(Flash::error(
             Redirect::to(uri!(super::index)),
             "Access denied",
@@ -161,7 +169,25 @@ pub fn user_management_change_password_post(
         ));
     }
 
-    if !is_admin {
+    config.audit_log.record(AuditEntry::new(
+        &user.user_name,
+        AuditAction::PasswordChange,
+        Some(&data.login),
+        remote.map(|address| address.ip().to_string()),
+    ));
+
+    // changing password revokes every session, current one is replaced with new session
+    if data.login == user.user_name {
+        let session_id = config.sessions.create(
+            &user.user_name,
+            remote.map(|address| address.ip().to_string()),
+            &config.config.session,
+            SystemTime::now(),
+        );
+        cookies.add_private(Cookie::new(SESSION_COOKIE, session_id));
+    }
+
+    if !can_manage_users {
         return Ok(Flash::success(
             Redirect::to(uri!(super::index)),
             "Password changed",
@@ -172,3 +198,403 @@ pub fn user_management_change_password_post(
         "Password changed",
     ))
 }
+
+#[derive(FromForm)]
+pub struct AddUserData {
+    csrf_token: String,
+    login: String,
+    password1: String,
+    password2: String,
+    role: String,
+}
+
+#[post("/user_management/add", data = "<data>")]
+pub fn user_management_add_post(
+    data: Form<AddUserData>,
+    user: AdminUser,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+
+    let login = data.login.trim().to_string();
+    if login.is_empty() {
+        return Err(Flash::error(
+            Redirect::to(uri!(user_management)),
+            "Login cannot be empty",
+        ));
+    }
+
+    if data.password1 != data.password2 {
+        return Err(Flash::error(
+            Redirect::to(uri!(user_management)),
+            "Passwords are not the same",
+        ));
+    }
+
+    let role: Role = match data.role.parse() {
+        Ok(role) => role,
+        Err(error) => return Err(Flash::error(Redirect::to(uri!(user_management)), error)),
+    };
+
+    if let Err(error) = config.add_user(&login, &data.password1, role) {
+        return Err(Flash::error(Redirect::to(uri!(user_management)), error));
+    }
+
+    let mut entry = AuditEntry::new(
+        &user.user_name,
+        AuditAction::UserAdd,
+        Some(&login),
+        remote.map(|address| address.ip().to_string()),
+    );
+    entry.details = Some(format!("role {}", role.as_str()));
+    config.audit_log.record(entry);
+
+    Ok(Flash::success(
+        Redirect::to(uri!(user_management)),
+        format!("User {} added", login),
+    ))
+}
+
+#[derive(FromForm)]
+pub struct DeleteUserData {
+    csrf_token: String,
+    login: String,
+}
+
+#[post("/user_management/delete", data = "<data>")]
+pub fn user_management_delete_post(
+    data: Form<DeleteUserData>,
+    user: AdminUser,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+
+    if data.login == user.user_name {
+        return Err(Flash::error(
+            Redirect::to(uri!(user_management)),
+            "You cannot delete your own account",
+        ));
+    }
+
+    if let Err(error) = config.delete_user(&data.login) {
+        return Err(Flash::error(Redirect::to(uri!(user_management)), error));
+    }
+
+    config.audit_log.record(AuditEntry::new(
+        &user.user_name,
+        AuditAction::UserDelete,
+        Some(&data.login),
+        remote.map(|address| address.ip().to_string()),
+    ));
+
+    Ok(Flash::success(
+        Redirect::to(uri!(user_management)),
+        format!("User {} deleted", data.login),
+    ))
+}
+
+#[derive(FromForm)]
+pub struct SetRoleData {
+    csrf_token: String,
+    login: String,
+    role: String,
+}
+
+#[post("/user_management/set_role", data = "<data>")]
+pub fn user_management_set_role_post(
+    data: Form<SetRoleData>,
+    user: AdminUser,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+
+    let role: Role = match data.role.parse() {
+        Ok(role) => role,
+        Err(error) => return Err(Flash::error(Redirect::to(uri!(user_management)), error)),
+    };
+
+    if let Err(error) = config.set_user_role(&data.login, role) {
+        return Err(Flash::error(Redirect::to(uri!(user_management)), error));
+    }
+
+    let mut entry = AuditEntry::new(
+        &user.user_name,
+        AuditAction::UserSetRole,
+        Some(&data.login),
+        remote.map(|address| address.ip().to_string()),
+    );
+    entry.details = Some(format!("role {}", role.as_str()));
+    config.audit_log.record(entry);
+
+    Ok(Flash::success(
+        Redirect::to(uri!(user_management)),
+        format!("User {} is now {}", data.login, role.display_name()),
+    ))
+}
+
+#[derive(FromForm)]
+pub struct RevokeSessionsData {
+    csrf_token: String,
+    login: String,
+}
+
+#[post("/user_management/revoke_sessions", data = "<data>")]
+pub fn user_management_revoke_sessions_post(
+    data: Form<RevokeSessionsData>,
+    user: AdminUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let config = config_lock.read().unwrap();
+
+    let count = config.sessions.revoke_user(&data.login);
+    Ok(Flash::success(
+        Redirect::to(uri!(user_management)),
+        format!("{} sessions of user {} revoked", count, data.login),
+    ))
+}
+
+#[derive(FromForm)]
+pub struct UnlockUserData {
+    csrf_token: String,
+    login: String,
+}
+
+#[post("/user_management/unlock", data = "<data>")]
+pub fn user_management_unlock_post(
+    data: Form<UnlockUserData>,
+    user: AdminUser,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let config = config_lock.read().unwrap();
+
+    if !config.login_throttle.unlock(&data.login) {
+        return Err(Flash::error(
+            Redirect::to(uri!(user_management)),
+            format!("User {} has no failed logins", data.login),
+        ));
+    }
+
+    config.audit_log.record(AuditEntry::new(
+        &user.user_name,
+        AuditAction::UserUnlock,
+        Some(&data.login),
+        remote.map(|address| address.ip().to_string()),
+    ));
+
+    Ok(Flash::success(
+        Redirect::to(uri!(user_management)),
+        format!("User {} unlocked", data.login),
+    ))
+}
+
+#[derive(FromForm)]
+pub struct DisableTotpData {
+    csrf_token: String,
+    login: String,
+}
+
+// For users who lost both authenticator and recovery codes
+#[post("/user_management/disable_totp", data = "<data>")]
+pub fn user_management_disable_totp_post(
+    data: Form<DisableTotpData>,
+    user: AdminUser,
+    remote: Option<SocketAddr>,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+
+    if let Err(error) = config.disable_totp(&data.login) {
+        return Err(Flash::error(Redirect::to(uri!(user_management)), error));
+    }
+
+    config.audit_log.record(AuditEntry::new(
+        &user.user_name,
+        AuditAction::TotpDisable,
+        Some(&data.login),
+        remote.map(|address| address.ip().to_string()),
+    ));
+
+    Ok(Flash::success(
+        Redirect::to(uri!(user_management)),
+        format!("Two-factor authentication disabled for user {}", data.login),
+    ))
+}
+
+#[derive(FromForm)]
+pub struct RequireTotpData {
+    csrf_token: String,
+    required: bool,
+}
+
+#[post("/user_management/require_totp", data = "<data>")]
+pub fn user_management_require_totp_post(
+    data: Form<RequireTotpData>,
+    user: AdminUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    user.verify_csrf_token(&data.csrf_token)?;
+    let mut config = config_lock.write().unwrap();
+
+    if let Err(error) = config.set_require_totp_for_admins(data.required) {
+        return Err(Flash::error(Redirect::to(uri!(user_management)), error));
+    }
+
+    if data.required {
+        return Ok(Flash::success(
+            Redirect::to(uri!(user_management)),
+            "Two-factor authentication is now required for administrators",
+        ));
+    }
+    Ok(Flash::success(
+        Redirect::to(uri!(user_management)),
+        "Two-factor authentication is now optional",
+    ))
+}
+
+#[derive(serde::Serialize)]
+struct LoginEventTemplate {
+    time: String,
+    login: String,
+    remote_address: String,
+    success: bool,
+    message: String,
+}
+
+#[derive(serde::Serialize)]
+struct LoginEventsTemplateContext {
+    events: Vec<LoginEventTemplate>,
+    user_name: String,
+}
+
+#[get("/user_management/login_events")]
+pub fn user_management_login_events(
+    user: AdminUser,
+    config_lock: State<RwLock<ConfigObject>>,
+) -> Template {
+    let config = config_lock.read().unwrap();
+
+    let events = config
+        .login_throttle
+        .events()
+        .into_iter()
+        .map(|event| LoginEventTemplate {
+            time: DateTime::<Utc>::from(event.time)
+                .format("%Y-%m-%d %H:%M:%S UTC")
+                .to_string(),
+            login: event.login,
+            remote_address: event.remote_address.unwrap_or("unknown".to_string()),
+            success: event.success,
+            message: event.message,
+        })
+        .collect();
+
+    let context = LoginEventsTemplateContext {
+        events: events,
+        user_name: user.user_name,
+    };
+    Template::render("login_events", &context)
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::{ContentType, Status};
+
+    use crate::config::{ConfigTrait, Role};
+    use crate::tests::test_server::{config, csrf_token, login, test_client};
+
+    #[test]
+    fn test_user_management_rejects_forged_requests() {
+        let (client, _temp_dir) = test_client(&[("admin", Role::Admin), ("user", Role::Viewer)]);
+        login(&client, "admin");
+
+        let forged_requests = [
+            ("/user_management/delete", "login=user&csrf_token=forged"),
+            (
+                "/user_management/set_role",
+                "login=user&role=admin&csrf_token=forged",
+            ),
+            (
+                "/user_management/add",
+                "login=evil&password1=evil&password2=evil&role=admin&csrf_token=forged",
+            ),
+            (
+                "/user_management/change_password",
+                "login=user&password1=evil&password2=evil&csrf_token=forged",
+            ),
+        ];
+        for (path, body) in forged_requests.iter() {
+            let response = client
+                .post(*path)
+                .header(ContentType::Form)
+                .body(*body)
+                .dispatch();
+            assert_eq!(Status::SeeOther, response.status());
+        }
+
+        let config_lock = config(&client);
+        {
+            let config = config_lock.read().unwrap();
+            assert!(config.user_exists(&"user".to_string()));
+            assert!(!config.user_exists(&"evil".to_string()));
+            assert_eq!(
+                Some(Role::Viewer),
+                config.get_user_role(&"user".to_string())
+            );
+        }
+        assert!(crate::config::verify_login(
+            config_lock,
+            &"user".to_string(),
+            &"hunter2".to_string()
+        ));
+    }
+
+    #[test]
+    fn test_user_management_accepts_session_csrf_token() {
+        let (client, _temp_dir) = test_client(&[("admin", Role::Admin), ("user", Role::Viewer)]);
+        login(&client, "admin");
+
+        let response = client
+            .post("/user_management/delete")
+            .header(ContentType::Form)
+            .body(format!(
+                "login=user&csrf_token={}",
+                csrf_token(&client, "admin")
+            ))
+            .dispatch();
+        assert_eq!(Status::SeeOther, response.status());
+        assert!(!config(&client)
+            .read()
+            .unwrap()
+            .user_exists(&"user".to_string()));
+    }
+
+    #[test]
+    fn test_logout_rejects_forged_request() {
+        let (client, _temp_dir) = test_client(&[("admin", Role::Admin)]);
+        login(&client, "admin");
+
+        client
+            .post("/logout")
+            .header(ContentType::Form)
+            .body("csrf_token=forged")
+            .dispatch();
+        assert_eq!(Status::Ok, client.get("/").dispatch().status());
+
+        client
+            .post("/logout")
+            .header(ContentType::Form)
+            .body(format!("csrf_token={}", csrf_token(&client, "admin")))
+            .dispatch();
+        assert_eq!(Status::SeeOther, client.get("/").dispatch().status());
+    }
+}
@@ -0,0 +1,321 @@
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::io::Reader;
+use image::ImageOutputFormat;
+use sha2::{Digest, Sha256};
+
+use crate::mod_metadata::{ContentImage, ImageKind, ModMetadata};
+
+const THUMBNAIL_SIZE: u32 = 256;
+const JPEG_QUALITY: u8 = 85;
+const IMAGE_EXTENSIONS: [&str; 2] = ["jpg", "png"];
+// decoded image takes at least 3 bytes per pixel, this allows 8K previews and refuses
+// decompression bombs
+const MAX_IMAGE_PIXELS: u64 = 8192 * 8192;
+
+/// Images are stored under hash of their content, so identical previews of different mods are kept once
+/// and cached files never change.
+pub struct ImageCache {
+    path: PathBuf,
+}
+
+fn image_name(bytes: &[u8], extension: &str) -> String {
+    format!("{:x}.{}", Sha256::digest(bytes), extension)
+}
+
+fn reader(bytes: &[u8]) -> Result<Reader<Cursor<&[u8]>>, String> {
+    Reader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|error| error.to_string())
+}
+
+fn thumbnail(bytes: &[u8], extension: &str) -> Result<Vec<u8>, String> {
+    // size is read from image header, before any memory is allocated for pixels
+    let (width, height) = reader(bytes)?
+        .into_dimensions()
+        .map_err(|error| error.to_string())?;
+    if u64::from(width) * u64::from(height) > MAX_IMAGE_PIXELS {
+        return Err(format!("Image is too large: {}x{}", width, height));
+    }
+    let image = reader(bytes)?.decode().map_err(|error| error.to_string())?;
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+
+    let mut output = vec![];
+    let result = match extension {
+        // JPEG has no alpha channel
+        "jpg" => image::DynamicImage::ImageRgb8(thumbnail.to_rgb8())
+            .write_to(&mut output, ImageOutputFormat::Jpeg(JPEG_QUALITY)),
+        _ => thumbnail.write_to(&mut output, ImageOutputFormat::Png),
+    };
+    result.map_err(|error| error.to_string())?;
+    Ok(output)
+}
+
+impl ImageCache {
+    pub fn new(path: &Path) -> ImageCache {
+        ImageCache {
+            path: path.to_path_buf(),
+        }
+    }
+
+    fn write(&self, name: &str, bytes: &[u8]) -> Result<(), String> {
+        let path = self.path.join(name);
+        if path.exists() {
+            return Ok(());
+        }
+
+        let temporary_path = self.path.join(format!("{}.tmp", name));
+        std::fs::write(&temporary_path, bytes)
+            .and_then(|_| std::fs::rename(&temporary_path, &path))
+            .map_err(|error| {
+                let _ = std::fs::remove_file(&temporary_path);
+                format!("Failed to write {}: {}", path.display(), error)
+            })
+    }
+
+    /// Stores image with its thumbnail, returns their names in cache.
+    pub fn store(&self, source: &Path) -> Result<(String, String), String> {
+        let extension = match source.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("png") => "png",
+            _ => "jpg",
+        };
+        let bytes =
+            std::fs::read(source).map_err(|error| format!("{}: {}", source.display(), error))?;
+        let thumbnail = thumbnail(&bytes, extension)
+            .map_err(|error| format!("{}: {}", source.display(), error))?;
+
+        std::fs::create_dir_all(&self.path)
+            .map_err(|error| format!("{}: {}", self.path.display(), error))?;
+        let image = image_name(&bytes, extension);
+        let thumbnail_image = image_name(&thumbnail, extension);
+        self.write(&image, &bytes)?;
+        self.write(&thumbnail_image, &thumbnail)?;
+        Ok((image, thumbnail_image))
+    }
+
+    /// Path of cached image, None for anything else than <sha256>.<jpg|png>.
+    pub fn path_of(&self, name: &str) -> Option<PathBuf> {
+        let mut parts = name.splitn(2, '.');
+        let hash = parts.next()?;
+        let extension = parts.next()?;
+        let is_hash =
+            hash.len() == 64 && hash.chars().all(|character| character.is_ascii_hexdigit());
+        if !is_hash || !IMAGE_EXTENSIONS.contains(&extension) {
+            return None;
+        }
+
+        let path = self.path.join(name);
+        if !path.is_file() {
+            return None;
+        }
+        Some(path)
+    }
+
+    fn add_image(
+        &self,
+        images: &mut Vec<ContentImage>,
+        source: &Path,
+        kind: ImageKind,
+        skin: Option<String>,
+    ) {
+        if !source.is_file() {
+            return;
+        }
+        match self.store(source) {
+            Ok((image, thumbnail)) => images.push(ContentImage {
+                kind: kind,
+                skin: skin,
+                image: image,
+                thumbnail: thumbnail,
+            }),
+            Err(error) => println!("Skipping image: {}", error),
+        }
+    }
+
+    /// Caches skin previews and badge of every car and preview and outline of every track layout.
+    pub fn cache_mod_images(&self, install_directory: &Path, metadata: &mut ModMetadata) {
+        for car in metadata.cars.iter_mut() {
+            let car_path = install_directory.join("content/cars").join(&car.id);
+            self.add_image(
+                &mut car.images,
+                &car_path.join("ui/badge.png"),
+                ImageKind::Badge,
+                None,
+            );
+
+            let mut skins: Vec<String> = match std::fs::read_dir(car_path.join("skins")) {
+                Ok(entries) => entries
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .collect(),
+                Err(_) => vec![],
+            };
+            skins.sort();
+            for skin in skins {
+                let preview_path = car_path.join("skins").join(&skin).join("preview.jpg");
+                self.add_image(
+                    &mut car.images,
+                    &preview_path,
+                    ImageKind::Preview,
+                    Some(skin),
+                );
+            }
+        }
+
+        for track in metadata.tracks.iter_mut() {
+            let mut ui_path = install_directory
+                .join("content/tracks")
+                .join(&track.id)
+                .join("ui");
+            if let Some(ref layout) = track.layout {
+                ui_path = ui_path.join(layout);
+            }
+            self.add_image(
+                &mut track.images,
+                &ui_path.join("preview.png"),
+                ImageKind::Preview,
+                None,
+            );
+            self.add_image(
+                &mut track.images,
+                &ui_path.join("outline.png"),
+                ImageKind::Outline,
+                None,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::GenericImageView;
+    use tempdir::TempDir;
+
+    use crate::mod_metadata::ContentMetadata;
+
+    fn write_image(path: &Path, width: u32, height: u32) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image::DynamicImage::new_rgb8(width, height)
+            .save(path)
+            .unwrap();
+    }
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for byte in bytes {
+            crc ^= u32::from(*byte);
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xedb88320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    // 1x1 PNG whose header claims given size, its pixels would never fit in memory
+    fn png_claiming_size(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = vec![];
+        image::DynamicImage::new_rgb8(1, 1)
+            .write_to(&mut bytes, ImageOutputFormat::Png)
+            .unwrap();
+        // IHDR follows 8 byte signature, width and height come after its length and type
+        bytes[16..20].copy_from_slice(&width.to_be_bytes());
+        bytes[20..24].copy_from_slice(&height.to_be_bytes());
+        let crc = crc32(&bytes[12..29]);
+        bytes[29..33].copy_from_slice(&crc.to_be_bytes());
+        bytes
+    }
+
+    fn content(id: &str, layout: Option<&str>) -> ContentMetadata {
+        ContentMetadata {
+            id: id.to_string(),
+            layout: layout.map(|layout| layout.to_string()),
+            ..ContentMetadata::default()
+        }
+    }
+
+    #[test]
+    fn test_thumbnail_rejects_huge_image() {
+        assert_eq!(
+            Err("Image is too large: 100000x100000".to_string()),
+            thumbnail(&png_claiming_size(100000, 100000), "png")
+        );
+        assert!(thumbnail(&png_claiming_size(1, 1), "png").is_ok());
+    }
+
+    #[test]
+    fn test_store_creates_thumbnail() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let source = temp_dir.path().join("preview.jpg");
+        write_image(&source, 1024, 576);
+        let cache = ImageCache::new(&temp_dir.path().join("image_cache"));
+
+        let (image, thumbnail) = cache.store(&source).unwrap();
+        assert!(image.ends_with(".jpg"));
+        assert_ne!(image, thumbnail);
+
+        let thumbnail = image::open(cache.path_of(&thumbnail).unwrap()).unwrap();
+        assert_eq!(THUMBNAIL_SIZE, thumbnail.width());
+        assert_eq!(144, thumbnail.height());
+
+        // same content is stored once
+        assert_eq!(image, cache.store(&source).unwrap().0);
+        assert_eq!(2, std::fs::read_dir(&cache.path).unwrap().count());
+    }
+
+    #[test]
+    fn test_path_of_rejects_other_names() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let cache = ImageCache::new(temp_dir.path());
+        let hash = "a".repeat(64);
+        std::fs::write(temp_dir.path().join(format!("{}.png", hash)), "").unwrap();
+
+        assert!(cache.path_of(&format!("{}.png", hash)).is_some());
+        assert!(cache.path_of(&format!("{}.jpg", hash)).is_none());
+        assert!(cache.path_of(&format!("{}.exe", hash)).is_none());
+        assert!(cache.path_of("../config.json").is_none());
+    }
+
+    #[test]
+    fn test_cache_mod_images() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let install_path = temp_dir.path().join("install");
+        write_image(&install_path.join("content/cars/car/ui/badge.png"), 64, 64);
+        write_image(
+            &install_path.join("content/cars/car/skins/red/preview.jpg"),
+            320,
+            180,
+        );
+        write_image(
+            &install_path.join("content/cars/car/skins/blue/preview.jpg"),
+            320,
+            100,
+        );
+        write_image(
+            &install_path.join("content/tracks/ring/ui/gp/outline.png"),
+            200,
+            200,
+        );
+        let cache = ImageCache::new(&temp_dir.path().join("image_cache"));
+
+        let mut metadata = ModMetadata {
+            cars: vec![content("car", None)],
+            tracks: vec![content("ring", Some("gp"))],
+            ..ModMetadata::default()
+        };
+        cache.cache_mod_images(&install_path, &mut metadata);
+
+        let car_images = &metadata.cars[0].images;
+        assert_eq!(3, car_images.len());
+        assert_eq!(ImageKind::Badge, car_images[0].kind);
+        assert_eq!(Some("blue".to_string()), car_images[1].skin);
+        assert_eq!(1, metadata.tracks[0].images.len());
+        assert_eq!(ImageKind::Outline, metadata.tracks[0].images[0].kind);
+    }
+}
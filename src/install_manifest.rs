@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+// top level directories of server path, kept even when last mod in them is removed
+const KEPT_DIRECTORIES: [&str; 3] = ["content", "content/cars", "content/tracks"];
+
+/// Paths relative to server path.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct InstalledFiles {
+    pub files: Vec<String>,
+    /// Directories which were created empty, like car skin directories.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub directories: Vec<String>,
+}
+
+/// Every file install_mod wrote, per server path.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct InstallManifest {
+    pub server_paths: BTreeMap<String, InstalledFiles>,
+}
+
+/// File which upload would write and installed mod already provides.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileConflict {
+    pub path: String,
+    pub checksum_md5: String,
+    pub filename: String,
+}
+
+fn remove_empty_parents(server_path: &Path, relative_path: &Path) {
+    let mut current = relative_path.parent();
+    while let Some(directory) = current {
+        if directory.as_os_str().is_empty()
+            || KEPT_DIRECTORIES.contains(&directory.to_string_lossy().as_ref())
+        {
+            return;
+        }
+        // fails when directory is not empty, which ends the walk
+        if std::fs::remove_dir(server_path.join(directory)).is_err() {
+            return;
+        }
+        current = directory.parent();
+    }
+}
+
+impl InstallManifest {
+    pub fn record_file(&mut self, server_path: &str, relative_path: &Path) {
+        self.server_paths
+            .entry(server_path.to_string())
+            .or_default()
+            .files
+            .push(relative_path.display().to_string());
+    }
+
+    pub fn record_directory(&mut self, server_path: &str, relative_path: &Path) {
+        self.server_paths
+            .entry(server_path.to_string())
+            .or_default()
+            .directories
+            .push(relative_path.display().to_string());
+    }
+
+    pub fn provides(&self, server_path: &str, relative_path: &str) -> bool {
+        match self.server_paths.get(server_path) {
+            Some(installed) => {
+                installed.files.iter().any(|file| file == relative_path)
+                    || installed
+                        .directories
+                        .iter()
+                        .any(|directory| directory == relative_path)
+            }
+            None => false,
+        }
+    }
+
+    /// Whether mod wrote relative_path to any of the server paths.
+    pub fn contains_file(&self, relative_path: &str) -> bool {
+        self.server_paths
+            .values()
+            .any(|installed| installed.files.iter().any(|file| file == relative_path))
+    }
+
+    /// Removes installed files and directories left empty after that. Files which
+    /// other_manifests also provide are kept, their paths are returned.
+    pub fn uninstall(&self, other_manifests: &[&InstallManifest]) -> Vec<String> {
+        let mut kept = vec![];
+        for (server_path, installed) in self.server_paths.iter() {
+            let server_root = Path::new(server_path);
+            let is_shared = |relative_path: &String| {
+                other_manifests
+                    .iter()
+                    .any(|manifest| manifest.provides(server_path, relative_path))
+            };
+
+            let mut removed: HashSet<&String> = HashSet::new();
+            for file in installed.files.iter() {
+                if is_shared(file) {
+                    kept.push(server_root.join(file).display().to_string());
+                    continue;
+                }
+                let result = std::fs::remove_file(server_root.join(file));
+                if let Err(error) = result {
+                    if error.kind() != std::io::ErrorKind::NotFound {
+                        println!(
+                            "Error removing {}: {}",
+                            server_root.join(file).display(),
+                            error
+                        );
+                        continue;
+                    }
+                }
+                removed.insert(file);
+            }
+
+            for directory in installed.directories.iter() {
+                if !is_shared(directory) && std::fs::remove_dir(server_root.join(directory)).is_ok()
+                {
+                    removed.insert(directory);
+                }
+            }
+
+            for relative_path in removed {
+                remove_empty_parents(server_root, Path::new(relative_path));
+            }
+        }
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    fn install(manifest: &mut InstallManifest, server_path: &Path, relative_path: &str) {
+        let path = server_path.join(relative_path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, relative_path).unwrap();
+        manifest.record_file(server_path.to_str().unwrap(), Path::new(relative_path));
+    }
+
+    #[test]
+    fn test_uninstall_removes_files_and_empty_directories() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let server_path = temp_dir.path();
+        std::fs::create_dir_all(server_path.join("content/tracks")).unwrap();
+
+        let mut manifest = InstallManifest::default();
+        install(&mut manifest, server_path, "content/cars/car/data.acd");
+        install(&mut manifest, server_path, "content/cars/car/data/car.ini");
+        std::fs::create_dir_all(server_path.join("content/cars/car/skins/red")).unwrap();
+        manifest.record_directory(
+            server_path.to_str().unwrap(),
+            Path::new("content/cars/car/skins/red"),
+        );
+
+        assert!(manifest.uninstall(&[]).is_empty());
+        assert!(!server_path.join("content/cars/car").exists());
+        assert!(server_path.join("content/cars").is_dir());
+        assert!(server_path.join("content/tracks").is_dir());
+    }
+
+    #[test]
+    fn test_uninstall_keeps_files_of_other_mods() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let server_path = temp_dir.path();
+
+        let mut manifest = InstallManifest::default();
+        let mut other_manifest = InstallManifest::default();
+        install(
+            &mut manifest,
+            server_path,
+            "content/tracks/ring/data/surfaces.ini",
+        );
+        install(
+            &mut manifest,
+            server_path,
+            "content/tracks/ring/gp/data/surfaces.ini",
+        );
+        install(
+            &mut other_manifest,
+            server_path,
+            "content/tracks/ring/data/surfaces.ini",
+        );
+        // file the mod never wrote stays as well
+        std::fs::write(
+            server_path.join("content/tracks/ring/gp/server_cfg.ini"),
+            "",
+        )
+        .unwrap();
+
+        let kept = manifest.uninstall(&[&other_manifest]);
+        assert_eq!(1, kept.len());
+        assert!(server_path
+            .join("content/tracks/ring/data/surfaces.ini")
+            .is_file());
+        assert!(!server_path.join("content/tracks/ring/gp/data").exists());
+        assert!(server_path
+            .join("content/tracks/ring/gp/server_cfg.ini")
+            .is_file());
+    }
+
+    #[test]
+    fn test_contains_file() {
+        let mut manifest = InstallManifest::default();
+        manifest.record_file("/srv/ac1", Path::new("content/cars/car/data.acd"));
+        manifest.record_directory("/srv/ac2", Path::new("content/cars/car/skins/red"));
+
+        assert!(manifest.contains_file("content/cars/car/data.acd"));
+        assert!(!manifest.contains_file("content/cars/car/skins/red"));
+        assert!(!manifest.contains_file("content/cars/other/data.acd"));
+    }
+}
@@ -0,0 +1,161 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::install_manifest::FileConflict;
+
+/// Files copied to one server path and errors which happened while copying there.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ServerPathReport {
+    pub server_path: String,
+    pub files_copied: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// Why archive wasn't installed, nothing of it is left in mod storage or server paths.
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "kind", content = "details", rename_all = "snake_case")]
+pub enum InstallError {
+    /// Unsupported extension, archive uploaded before or file name taken, archive wasn't unpacked.
+    Rejected(String),
+    Unpack(String),
+    /// Archive layout isn't recognized, like multiple content directories or no cars and tracks.
+    Layout(String),
+    /// Temporary directory, mod storage, mod history or mod database couldn't be written.
+    Storage(String),
+    /// Copying failed on some server paths, files copied before the failure were removed.
+    ServerPaths(Vec<ServerPathReport>),
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InstallError::Rejected(message) => write!(formatter, "{}", message),
+            InstallError::Unpack(message) => {
+                write!(formatter, "Error while unpacking archive: {}", message)
+            }
+            InstallError::Layout(message) => write!(formatter, "Unsupported archive: {}", message),
+            InstallError::Storage(message) => write!(formatter, "{}", message),
+            InstallError::ServerPaths(reports) => {
+                let errors: Vec<String> = reports
+                    .iter()
+                    .flat_map(|report| {
+                        report
+                            .errors
+                            .iter()
+                            .map(move |error| format!("{}: {}", report.server_path, error))
+                    })
+                    .collect();
+                write!(
+                    formatter,
+                    "Copying to server paths failed: {}",
+                    errors.join("; ")
+                )
+            }
+        }
+    }
+}
+
+/// Outcome of one uploaded archive.
+#[derive(Serialize, Debug)]
+pub struct InstallReport {
+    pub file_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum_md5: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub server_paths: Vec<ServerPathReport>,
+    /// Archive names of mods removed because they provided the same files.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub replaced: Vec<String>,
+    /// Set when upload waits for decision about files conflicting with installed mods.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_upload_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<FileConflict>,
+    /// Volumes of multi-volume archive received so far, set while the rest is missing.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub received_volumes: Vec<String>,
+    /// Inner archives which couldn't be unpacked, they were installed as files.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<InstallError>,
+}
+
+impl InstallReport {
+    pub fn new(file_name: &str) -> InstallReport {
+        InstallReport {
+            file_name: file_name.to_string(),
+            checksum_md5: None,
+            version: None,
+            server_paths: vec![],
+            replaced: vec![],
+            pending_upload_id: None,
+            conflicts: vec![],
+            received_volumes: vec![],
+            warnings: vec![],
+            error: None,
+        }
+    }
+
+    pub fn failed(file_name: &str, error: InstallError) -> InstallReport {
+        let mut report = InstallReport::new(file_name);
+        report.error = Some(error);
+        report
+    }
+
+    pub fn is_installed(&self) -> bool {
+        self.checksum_md5.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_paths_error_lists_every_failure() {
+        let error = InstallError::ServerPaths(vec![
+            ServerPathReport {
+                server_path: "/srv/ac1".to_string(),
+                files_copied: 3,
+                errors: vec![],
+            },
+            ServerPathReport {
+                server_path: "/srv/ac2".to_string(),
+                files_copied: 1,
+                errors: vec![
+                    "content/cars/car/data.acd: No space left on device".to_string(),
+                    "content/cars/car/data/car.ini: No space left on device".to_string(),
+                ],
+            },
+        ]);
+
+        assert_eq!(
+            "Copying to server paths failed: \
+             /srv/ac2: content/cars/car/data.acd: No space left on device; \
+             /srv/ac2: content/cars/car/data/car.ini: No space left on device",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_error_is_serialized_with_kind() {
+        let report = InstallReport::failed(
+            "car.zip",
+            InstallError::Layout("Multiple content or extension dirs found".to_string()),
+        );
+
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!("layout", value["error"]["kind"]);
+        assert_eq!(
+            "Multiple content or extension dirs found",
+            value["error"]["details"]
+        );
+        assert!(value.get("server_paths").is_none());
+        assert!(!report.is_installed());
+    }
+}
@@ -1,3 +1,4 @@
+use serde::Serialize;
 use wildmatch::WildMatch;
 
 use crate::common::FsEntry;
@@ -5,7 +6,7 @@ use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::path::Path;
 
-#[derive(Debug, Eq)]
+#[derive(Debug, Eq, Serialize)]
 pub struct InstallTask {
     pub source_path: String,
     pub target_path: String,
@@ -32,7 +33,7 @@ impl PartialEq for InstallTask {
 fn dir_contains(entry_list: &Vec<FsEntry>, dir: &str, entries: &Vec<&str>) -> bool {
     entry_list.iter().any(|i| {
         for entry in entries {
-            let path = format!("{}{}{}", dir, std::path::MAIN_SEPARATOR, entry);
+            let path = Path::new(dir).join(entry).display().to_string();
             if WildMatch::new(path.as_ref()).is_match(i.path.as_ref()) {
                 return true;
             }
@@ -41,8 +42,9 @@ fn dir_contains(entry_list: &Vec<FsEntry>, dir: &str, entries: &Vec<&str>) -> bo
     })
 }
 
-#[derive(Debug, PartialEq)]
-enum ContentType {
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentType {
     Car,
     Track,
     Unknown,
@@ -70,9 +72,12 @@ fn determine_content_type(entry_list: &Vec<FsEntry>, dir: &str) -> ContentType {
     }
 }
 
+/// Folder with kn5 models and type of content detected in it.
+#[derive(Debug, Serialize)]
 pub struct Mod {
-    mod_type: ContentType,
-    path: String,
+    #[serde(rename = "content_type")]
+    pub mod_type: ContentType,
+    pub path: String,
 }
 
 pub fn find_mods(entry_list: &Vec<FsEntry>) -> Vec<Mod> {
@@ -176,6 +181,36 @@ pub fn determine_install_tasks(entry_list: &Vec<FsEntry>) -> Result<Vec<InstallT
     Ok(ret)
 }
 
+/// Only these files of the mod are needed by the server, everything else stays in the archive.
+pub fn is_server_file(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("");
+    let parent_dir_name = path
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    extension == "acd" || extension == "ini" || parent_dir_name == "data"
+}
+
+/// Files which would be written to server path, relative to install root and sorted.
+pub fn determine_target_files(entry_list: &Vec<FsEntry>, root: &Path) -> Vec<String> {
+    let mut files: Vec<String> = entry_list
+        .iter()
+        .filter(|&entry| entry.is_file && is_server_file(Path::new(&entry.path)))
+        .filter_map(|entry| {
+            Path::new(&entry.path)
+                .strip_prefix(root)
+                .ok()
+                .map(|path| path.display().to_string())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -450,4 +485,39 @@ mod tests {
         tasks.sort();
         assert!(vec_equal(&expected, &tasks));
     }
+
+    #[test]
+    fn test_determine_target_files() {
+        let entries: Vec<FsEntry> = vec![
+            FsEntry {
+                path: "/tmp/install/content/cars/some_car".to_string(),
+                is_file: false,
+            },
+            FsEntry {
+                path: "/tmp/install/content/cars/some_car/some_car.kn5".to_string(),
+                is_file: true,
+            },
+            FsEntry {
+                path: "/tmp/install/content/cars/some_car/data.acd".to_string(),
+                is_file: true,
+            },
+            FsEntry {
+                path: "/tmp/install/content/tracks/some_track/data/surfaces.ini".to_string(),
+                is_file: true,
+            },
+            FsEntry {
+                path: "/tmp/install/content/tracks/some_track/data/ideal_line.ai".to_string(),
+                is_file: true,
+            },
+        ];
+
+        assert_eq!(
+            vec![
+                "content/cars/some_car/data.acd",
+                "content/tracks/some_track/data/ideal_line.ai",
+                "content/tracks/some_track/data/surfaces.ini",
+            ],
+            determine_target_files(&entries, Path::new("/tmp/install"))
+        );
+    }
 }
@@ -14,10 +14,16 @@ use rocket::config::{Config, Environment};
 use rocket_contrib::templates::Template;
 
 mod archive_unpacker;
+mod cli;
 mod common;
 mod config;
 mod endpoints;
+mod image_cache;
+mod install_manifest;
+mod install_report;
 mod install_task;
+mod mod_installer;
+mod mod_metadata;
 
 use config::ConfigTrait;
 
@@ -29,9 +35,21 @@ fn main() {
         config_file = c;
     }
 
-    let mut config = config::ConfigObject::new(config_file);
-    if config.get_mod_list().len() == 0 {
-        if let Err(error) = config.rebuild_mod_storage(false) {
+    let mut config = match config::ConfigObject::new(config_file) {
+        Ok(config) => config,
+        Err(error) => panic!("Error loading {}: {}", config_file, error),
+    };
+    if let Some(result) = cli::run_command(&matches, &mut config) {
+        if let Err(error) = result {
+            println!("Error: {}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let rebuild_database = matches.is_present("rebuild_database");
+    if rebuild_database || config.get_mod_list().len() == 0 {
+        if let Err(error) = config.rebuild_mod_storage(rebuild_database) {
             panic!("Error loading {}: {}", config_file, error);
         }
     }
@@ -55,7 +73,9 @@ fn main() {
         .expect("command returned non-UTF8 output")
         .trim_end()
         .to_string();
-        config.set_secret_key(secret_key.clone());
+        if let Err(error) = config.set_secret_key(secret_key.clone()) {
+            panic!("Error saving {}: {}", config_file, error);
+        }
     }
 
     let rocket_config = Config::build(Environment::Staging)
@@ -69,6 +89,7 @@ fn main() {
     rocket::custom(rocket_config)
         .manage(lock)
         .mount("/", routes)
+        .register(endpoints::get_catchers())
         .attach(Template::fairing())
         .launch();
 }
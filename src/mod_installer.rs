@@ -0,0 +1,1302 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use fs_extra::dir::CopyOptions;
+use tempdir::TempDir;
+
+use crate::archive_unpacker::{self, ArchiveLimits, NestedArchive, UnpackError};
+use crate::common::FsEntry;
+use crate::config::{
+    self, AssettoMod, ConfigObject, ConfigTrait, PendingVolumeSet, PendingVolumes,
+};
+use crate::image_cache::ImageCache;
+use crate::install_manifest::{FileConflict, InstallManifest};
+use crate::install_report::{InstallError, ServerPathReport};
+use crate::install_task::{ContentType, InstallTask, Mod};
+use crate::mod_metadata;
+use crate::{common, install_task};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConflictResolution {
+    Abort,
+    KeepBoth,
+    Replace,
+}
+
+impl ConflictResolution {
+    pub const ALL: [ConflictResolution; 3] = [
+        ConflictResolution::Abort,
+        ConflictResolution::KeepBoth,
+        ConflictResolution::Replace,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConflictResolution::Abort => "abort",
+            ConflictResolution::KeepBoth => "keep_both",
+            ConflictResolution::Replace => "replace",
+        }
+    }
+}
+
+impl FromStr for ConflictResolution {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        ConflictResolution::ALL
+            .iter()
+            .find(|resolution| resolution.as_str() == value)
+            .copied()
+            .ok_or(format!("Unknown conflict resolution: {}", value))
+    }
+}
+
+pub enum ImportResult {
+    Imported {
+        installed: AssettoMod,
+        replaced: Vec<AssettoMod>,
+        server_paths: Vec<ServerPathReport>,
+        /// Inner archives which couldn't be unpacked and were installed as files.
+        warnings: Vec<String>,
+    },
+    /// Nothing was installed, archive is not kept in mod storage.
+    Conflicts(Vec<FileConflict>),
+}
+
+/// What installing archive would do, read from archive headers without extracting it. Archive with
+/// archives inside has to be unpacked for that, it is kept unpacked for install then.
+pub struct InstallPlan {
+    /// Source paths are relative to archive root.
+    pub tasks: Vec<InstallTask>,
+    pub contents: Vec<Mod>,
+    /// Inner archive each car and track folder comes from, by folder ID.
+    pub content_sources: BTreeMap<String, String>,
+    /// Inner archives which couldn't be unpacked, they are installed as plain files.
+    pub warnings: Vec<String>,
+    // paths relative to install root, as they will be after tasks are done
+    output_entries: Vec<FsEntry>,
+    unpack_directory: Option<TempDir>,
+}
+
+impl InstallPlan {
+    /// Files which installing would write to every server path, relative to it.
+    pub fn target_files(&self) -> Vec<String> {
+        install_task::determine_target_files(&self.output_entries, Path::new(""))
+    }
+
+    /// Car and track folder IDs in the same order mod metadata lists them.
+    pub fn content_ids(&self) -> Vec<String> {
+        let mut content_ids = vec![];
+        for content_type in ["cars", "tracks"] {
+            let mut ids: Vec<String> = self
+                .output_entries
+                .iter()
+                .filter(|entry| !entry.is_file)
+                .filter_map(|entry| {
+                    let components: Vec<&str> = entry.path.split('/').collect();
+                    match components[..] {
+                        ["content", directory, id] if directory == content_type => {
+                            Some(format!("{}/{}", content_type, id))
+                        }
+                        _ => None,
+                    }
+                })
+                .collect();
+            ids.sort();
+            content_ids.append(&mut ids);
+        }
+        content_ids
+    }
+}
+
+/// Archive unpacked and arranged the way it will be copied to server paths.
+pub struct PreparedMod {
+    _unpack_directory: TempDir,
+    output_directory: TempDir,
+    pub content_sources: BTreeMap<String, String>,
+}
+
+impl PreparedMod {
+    pub fn path(&self) -> &Path {
+        self.output_directory.path()
+    }
+}
+
+fn create_temp_dir(prefix: &str) -> Result<TempDir, InstallError> {
+    TempDir::new(prefix)
+        .map_err(|error| InstallError::Storage(format!("Error creating temp_dir: {}", error)))
+}
+
+// where entry of archive ends up after task moves its source directory into target path
+fn output_path(task: &InstallTask, entry_path: &str) -> Option<PathBuf> {
+    let source_path = Path::new(&task.source_path);
+    let relative_path = Path::new(entry_path).strip_prefix(source_path).ok()?;
+    let directory_name = source_path.file_name()?;
+    Some(
+        Path::new(&task.target_path)
+            .join(directory_name)
+            .join(relative_path),
+    )
+}
+
+// car and track folder ID of install path, like cars/abc_gt3
+fn content_id(path: &Path) -> Option<String> {
+    let components: Vec<String> = path
+        .iter()
+        .map(|component| component.to_string_lossy().to_string())
+        .collect();
+    match &components[..] {
+        [content, content_type, id] if content == "content" => match content_type.as_str() {
+            "cars" | "tracks" => Some(format!("{}/{}", content_type, id)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// listing of unpacked archive in the same form list_archive returns
+fn list_unpacked(directory: &Path) -> Vec<FsEntry> {
+    common::recursive_ls(directory)
+        .into_iter()
+        .filter_map(|entry| {
+            let relative_path = Path::new(&entry.path).strip_prefix(directory).ok()?;
+            if relative_path.as_os_str().is_empty() {
+                return None;
+            }
+            Some(FsEntry {
+                path: relative_path.display().to_string(),
+                is_file: entry.is_file,
+            })
+        })
+        .collect()
+}
+
+pub fn plan_install(
+    archive_path: &Path,
+    limits: &ArchiveLimits,
+) -> Result<InstallPlan, InstallError> {
+    let mut entries = archive_unpacker::list_archive(archive_path).map_err(unpack_error)?;
+    let has_nested_archives = entries
+        .iter()
+        .any(|entry| entry.is_file && archive_unpacker::archive_extension(&entry.path).is_some());
+    let mut unpack_directory = None;
+    let mut nested_archives: Vec<NestedArchive> = vec![];
+    let mut warnings = vec![];
+    if has_nested_archives && limits.max_nesting_depth > 0 {
+        let temp_dir = create_temp_dir("acsync_server_unpack")?;
+        let unpacked = archive_unpacker::unpack_archive(archive_path, temp_dir.path(), limits)
+            .map_err(unpack_error)?;
+        nested_archives = unpacked.nested;
+        warnings = unpacked.warnings;
+        entries = list_unpacked(temp_dir.path());
+        unpack_directory = Some(temp_dir);
+    }
+
+    let mut contents = install_task::find_mods(&entries);
+    contents.sort_by(|a, b| a.path.cmp(&b.path));
+    let tasks = install_task::determine_install_tasks(&entries)
+        .map_err(|error| InstallError::Layout(error.to_string()))?;
+
+    let mut output_entries = vec![];
+    let mut content_sources = BTreeMap::new();
+    for task in tasks.iter() {
+        for entry in entries.iter() {
+            let path = match output_path(task, &entry.path) {
+                Some(path) => path,
+                None => continue,
+            };
+            // innermost archive wins, they are listed outer first
+            let source = nested_archives
+                .iter()
+                .rev()
+                .find(|nested| Path::new(&entry.path).starts_with(&nested.directory));
+            if let (Some(content_id), Some(source)) = (content_id(&path), source) {
+                content_sources.insert(content_id, source.archive.clone());
+            }
+            output_entries.push(FsEntry {
+                path: path.display().to_string(),
+                is_file: entry.is_file,
+            });
+        }
+    }
+
+    Ok(InstallPlan {
+        tasks: tasks,
+        contents: contents,
+        content_sources: content_sources,
+        warnings: warnings,
+        output_entries: output_entries,
+        unpack_directory: unpack_directory,
+    })
+}
+
+fn unpack_error(error: UnpackError) -> InstallError {
+    match error {
+        UnpackError::Unsafe(_) => InstallError::Rejected(error.to_string()),
+        UnpackError::Archive(message) => InstallError::Unpack(message),
+    }
+}
+
+/// Extracts archive, unless planning did already, and moves its directories the way plan says.
+pub fn prepare_mod(
+    archive_path: &Path,
+    plan: InstallPlan,
+    limits: &ArchiveLimits,
+) -> Result<PreparedMod, InstallError> {
+    let temp_dir = match plan.unpack_directory {
+        Some(temp_dir) => temp_dir,
+        None => {
+            let temp_dir = create_temp_dir("acsync_server_unpack")?;
+            archive_unpacker::unpack_archive(archive_path, temp_dir.path(), limits)
+                .map_err(unpack_error)?;
+            temp_dir
+        }
+    };
+    let temp_dir_output = create_temp_dir("acsync_server_install")?;
+    let temporary_directory = temp_dir.path();
+    let output_directory = temp_dir_output.path();
+    let _ = std::fs::create_dir_all(output_directory.join("content/cars"));
+    let _ = std::fs::create_dir_all(output_directory.join("content/tracks"));
+
+    for task in plan.tasks.iter() {
+        let source_path = temporary_directory.join(&task.source_path);
+        let target_path = output_directory.join(&task.target_path);
+        println!("{:?} -> {:?}", source_path, target_path);
+        let result = fs_extra::dir::move_dir(&source_path, target_path, &CopyOptions::new());
+        if let Err(error) = result {
+            return Err(InstallError::Storage(format!(
+                "Error while installing mod: {}",
+                error
+            )));
+        }
+    }
+
+    Ok(PreparedMod {
+        _unpack_directory: temp_dir,
+        output_directory: temp_dir_output,
+        content_sources: plan.content_sources,
+    })
+}
+
+#[derive(serde::Serialize)]
+pub struct ServerPathPreview {
+    pub server_path: String,
+    pub files: Vec<String>,
+}
+
+/// What installing archive would do.
+#[derive(serde::Serialize)]
+pub struct InstallPreview {
+    pub tasks: Vec<InstallTask>,
+    pub contents: Vec<Mod>,
+    /// Folders with models which are neither car nor track, these are only installed as part of
+    /// content directory.
+    pub unknown: Vec<String>,
+    pub server_paths: Vec<ServerPathPreview>,
+    /// Inner archives which couldn't be unpacked.
+    pub warnings: Vec<String>,
+}
+
+/// Lists planned install tasks and files from archive headers, only archives inside archive are
+/// extracted to temporary directory. Nothing is written to server paths and mod storage.
+pub fn preview_archive(
+    source_path: &Path,
+    file_name: &str,
+    server_paths: &Vec<String>,
+    limits: &ArchiveLimits,
+) -> Result<InstallPreview, InstallError> {
+    let (_staging_directory, staged_path) = stage_archive(source_path, file_name)?;
+    let plan = plan_install(&staged_path, limits)?;
+
+    let target_files = plan.target_files();
+    let server_paths = server_paths
+        .iter()
+        .map(|server_path| ServerPathPreview {
+            server_path: server_path.clone(),
+            files: target_files
+                .iter()
+                .map(|file| Path::new(server_path).join(file).display().to_string())
+                .collect(),
+        })
+        .collect();
+    let InstallPlan {
+        tasks,
+        contents,
+        warnings,
+        ..
+    } = plan;
+    let unknown = contents
+        .iter()
+        .filter(|content| content.mod_type == ContentType::Unknown)
+        .map(|content| content.path.clone())
+        .collect();
+
+    Ok(InstallPreview {
+        tasks: tasks,
+        contents: contents,
+        unknown: unknown,
+        server_paths: server_paths,
+        warnings: warnings,
+    })
+}
+
+/// Copies prepared mod to every server path, returns manifest of written files and what happened
+/// on each server path. Files which were there before are only recorded when installed mods
+/// provide them too, so uninstalling doesn't remove stock files.
+pub fn install_prepared_mod(
+    prepared: &PreparedMod,
+    server_paths: &Vec<String>,
+    installed_mods: &[AssettoMod],
+) -> (InstallManifest, Vec<ServerPathReport>) {
+    let mut manifest = InstallManifest::default();
+    let mut reports = vec![];
+    let output_directory = prepared.path();
+    let copy_options = fs_extra::file::CopyOptions {
+        overwrite: true,
+        skip_exist: true,
+        buffer_size: 65536,
+    };
+
+    // for output_dir in server_paths
+    for output_dir_str in server_paths {
+        let output_dir = Path::new(output_dir_str);
+        let mut report = ServerPathReport {
+            server_path: output_dir_str.clone(),
+            ..ServerPathReport::default()
+        };
+        for entry in common::recursive_ls(output_directory) {
+            let path = Path::new(&entry.path);
+            let without_prefix = path.strip_prefix(output_directory).unwrap();
+            let target_path = output_dir.join(without_prefix);
+
+            if entry.is_file {
+                if install_task::is_server_file(path) {
+                    let relative_path = without_prefix.display().to_string();
+                    let is_recorded = !target_path.exists()
+                        || installed_mods.iter().any(|acmod| {
+                            acmod
+                                .install_manifest
+                                .provides(output_dir_str, &relative_path)
+                        });
+                    let result = std::fs::create_dir_all(&target_path.parent().unwrap())
+                        .map_err(|error| error.to_string())
+                        .and_then(|_| {
+                            fs_extra::file::copy(path, &target_path, &copy_options)
+                                .map_err(|error| error.to_string())
+                        });
+                    match result {
+                        Ok(_) => {
+                            if is_recorded {
+                                manifest.record_file(output_dir_str, without_prefix);
+                            }
+                            report.files_copied += 1;
+                        }
+                        Err(error) => {
+                            report
+                                .errors
+                                .push(format!("{}: {}", without_prefix.display(), error))
+                        }
+                    }
+                }
+            } else {
+                let dirname = path.file_name().unwrap().to_str().unwrap();
+                if dirname == "skins" && !target_path.exists() {
+                    match std::fs::create_dir_all(&target_path) {
+                        Ok(_) => manifest.record_directory(output_dir_str, without_prefix),
+                        Err(error) => {
+                            report
+                                .errors
+                                .push(format!("{}: {}", without_prefix.display(), error))
+                        }
+                    }
+                }
+            }
+        }
+        reports.push(report);
+    }
+    (manifest, reports)
+}
+
+fn copy_file(source_path: &Path, output_path: &Path) -> Result<(), InstallError> {
+    match std::fs::copy(source_path, output_path) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(InstallError::Storage(format!(
+            "Error while copying file from '{:?}' to '{:?}', reason: {}",
+            source_path, output_path, error
+        ))),
+    }
+}
+
+// archive is kept under its name until it's installed, format itself is sniffed by unpacker
+fn stage_archive(source_path: &Path, file_name: &str) -> Result<(TempDir, PathBuf), InstallError> {
+    if archive_unpacker::archive_extension(file_name).is_none() {
+        let message = match Path::new(file_name).extension() {
+            Some(extension) => format!(
+                "Unsupported file extension: {}",
+                extension.to_string_lossy()
+            ),
+            None => "Missing file extension".to_string(),
+        };
+        return Err(InstallError::Rejected(message));
+    }
+
+    let staging_directory = create_temp_dir("acsync_server_upload")?;
+    let staged_path = staging_directory.path().join(file_name);
+    copy_file(source_path, &staged_path)?;
+    Ok((staging_directory, staged_path))
+}
+
+/// Joins volumes of complete set into single archive in temporary directory, imported then like
+/// any other upload. None while some volumes are still missing.
+pub fn join_volume_set(
+    pending_volumes: &PendingVolumes,
+    set: &PendingVolumeSet,
+    limits: &ArchiveLimits,
+) -> Result<Option<(TempDir, PathBuf)>, InstallError> {
+    let volumes = pending_volumes.volume_paths(set);
+    if !archive_unpacker::is_complete_set(set.kind, &volumes) {
+        return Ok(None);
+    }
+
+    let joining_directory = create_temp_dir("acsync_server_volumes")?;
+    let joined_path = joining_directory.path().join(set.joined_file_name());
+    archive_unpacker::join_volumes(set.kind, &volumes, &joined_path, limits)
+        .map_err(unpack_error)?;
+    Ok(Some((joining_directory, joined_path)))
+}
+
+// removes files of failed install, files which installed mods provide too are kept
+fn uninstall_files(config: &ConfigObject, manifest: &InstallManifest) {
+    let mods = config.get_mod_list();
+    let other_manifests: Vec<&InstallManifest> =
+        mods.iter().map(|acmod| &acmod.install_manifest).collect();
+    manifest.uninstall(&other_manifests);
+}
+
+// either the mod is installed and added to mod database, or nothing of it is left behind
+fn install_to_storage(
+    config: &mut ConfigObject,
+    prepared: &PreparedMod,
+    archive_path: &Path,
+    output_path: &Path,
+    previous_version: Option<AssettoMod>,
+) -> Result<(AssettoMod, Vec<ServerPathReport>), InstallError> {
+    copy_file(archive_path, output_path)?;
+    let (manifest, reports) =
+        install_prepared_mod(prepared, &config.get_server_paths(), &config.get_mod_list());
+    if reports.iter().any(|report| !report.errors.is_empty()) {
+        uninstall_files(config, &manifest);
+        let _ = std::fs::remove_file(output_path);
+        return Err(InstallError::ServerPaths(reports));
+    }
+
+    let mut metadata = mod_metadata::read_metadata(prepared.path());
+    metadata.content_sources = prepared.content_sources.clone();
+    ImageCache::new(&config.image_cache_path()).cache_mod_images(prepared.path(), &mut metadata);
+
+    match config.add_mod(output_path, metadata, manifest.clone(), previous_version) {
+        Ok(installed) => Ok((installed, reports)),
+        Err(error) => {
+            uninstall_files(config, &manifest);
+            let _ = std::fs::remove_file(output_path);
+            Err(InstallError::Storage(error))
+        }
+    }
+}
+
+// puts retired version back when the one replacing it couldn't be installed
+fn reinstate_previous_version(config: &mut ConfigObject, mut previous_version: AssettoMod) {
+    let history_path = config.mod_version_archive_path(&previous_version.as_version());
+    let limits = config.config.archive_limits.clone();
+    let result = plan_install(&history_path, &limits)
+        .and_then(|plan| prepare_mod(&history_path, plan, &limits))
+        .and_then(|prepared| {
+            let (manifest, reports) = install_prepared_mod(
+                &prepared,
+                &config.get_server_paths(),
+                &config.get_mod_list(),
+            );
+            if reports.iter().any(|report| !report.errors.is_empty()) {
+                uninstall_files(config, &manifest);
+                return Err(InstallError::ServerPaths(reports));
+            }
+            previous_version.install_manifest = manifest;
+            config
+                .reinstate_mod(previous_version)
+                .map_err(InstallError::Storage)
+        });
+    if let Err(error) = result {
+        println!("Error reinstating previous version: {}", error);
+    }
+}
+
+// what checks against installed mods need, kept after plan is consumed by preparing
+struct StagedArchive {
+    _staging_directory: TempDir,
+    path: PathBuf,
+    file_name: String,
+    checksum_md5: String,
+    content_ids: Vec<String>,
+    target_files: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// Upload copied out of request with its install plan, nothing of config is needed for it, so
+/// archive can be read without holding config lock.
+pub struct PlannedImport {
+    staged: StagedArchive,
+    plan: InstallPlan,
+}
+
+/// Planned upload unpacked and arranged for install.
+pub struct PreparedImport {
+    staged: StagedArchive,
+    prepared: PreparedMod,
+}
+
+struct ImportTarget {
+    previous_version: Option<AssettoMod>,
+    conflicts: Vec<FileConflict>,
+    output_path: PathBuf,
+}
+
+fn reject_known_archive(
+    known_checksums: &[String],
+    checksum_md5: &str,
+) -> Result<(), InstallError> {
+    if known_checksums.iter().any(|known| known == checksum_md5) {
+        return Err(InstallError::Rejected("Mod already exists".to_string()));
+    }
+    Ok(())
+}
+
+/// Stages archive and plans its install, archives with known checksums are rejected before
+/// unpacking anything.
+pub fn plan_import(
+    source_path: &Path,
+    file_name: &str,
+    known_checksums: &[String],
+    limits: &ArchiveLimits,
+) -> Result<PlannedImport, InstallError> {
+    let (staging_directory, staged_path) = stage_archive(source_path, file_name)?;
+    let (checksum_md5, _) =
+        config::archive_checksum(&staged_path).map_err(InstallError::Storage)?;
+    reject_known_archive(known_checksums, &checksum_md5)?;
+
+    let plan = plan_install(&staged_path, limits)?;
+    if plan.tasks.is_empty() {
+        return Err(InstallError::Layout(
+            "No cars, tracks or content directory found".to_string(),
+        ));
+    }
+
+    Ok(PlannedImport {
+        staged: StagedArchive {
+            _staging_directory: staging_directory,
+            path: staged_path,
+            file_name: file_name.to_string(),
+            checksum_md5: checksum_md5,
+            content_ids: plan.content_ids(),
+            target_files: plan.target_files(),
+            warnings: plan.warnings.clone(),
+        },
+        plan: plan,
+    })
+}
+
+// installed mods can change between planning and install, so this runs again right before it
+fn find_import_target(
+    config: &ConfigObject,
+    staged: &StagedArchive,
+) -> Result<ImportTarget, InstallError> {
+    let known_checksums: Vec<String> = config
+        .get_mod_list()
+        .into_iter()
+        .map(|acmod| acmod.checksum_md5)
+        .collect();
+    reject_known_archive(&known_checksums, &staged.checksum_md5)?;
+
+    let previous_version = config.find_previous_version(&staged.content_ids);
+    if let Some(ref previous) = previous_version {
+        let older = previous
+            .history
+            .iter()
+            .find(|older| older.checksum_md5 == staged.checksum_md5);
+        if let Some(older) = older {
+            return Err(InstallError::Rejected(format!(
+                "Archive is version {} of {}, restore it instead",
+                older.version, previous.filename
+            )));
+        }
+    }
+    let output_path = Path::new(&config.config.mod_storage_location).join(&staged.file_name);
+    let replaces_same_file = previous_version
+        .as_ref()
+        .map_or(false, |previous| previous.filename == staged.file_name);
+    if output_path.exists() && !replaces_same_file {
+        return Err(InstallError::Rejected(format!(
+            "{} already exists in mod storage",
+            staged.file_name
+        )));
+    }
+
+    // files of previous version are uninstalled anyway, they aren't conflicts
+    let conflicts: Vec<FileConflict> = config
+        .find_file_conflicts(&staged.target_files)
+        .into_iter()
+        .filter(|conflict| {
+            previous_version.as_ref().map_or(true, |previous| {
+                previous.checksum_md5 != conflict.checksum_md5
+            })
+        })
+        .collect();
+
+    Ok(ImportTarget {
+        previous_version: previous_version,
+        conflicts: conflicts,
+        output_path: output_path,
+    })
+}
+
+/// Files of installed mods which planned upload would overwrite, errors if it can't be installed
+/// at all.
+pub fn check_import(
+    config: &ConfigObject,
+    planned: &PlannedImport,
+) -> Result<Vec<FileConflict>, InstallError> {
+    find_import_target(config, &planned.staged).map(|target| target.conflicts)
+}
+
+/// Extracts planned upload, unless planning did already, doesn't need config either.
+pub fn prepare_import(
+    planned: PlannedImport,
+    limits: &ArchiveLimits,
+) -> Result<PreparedImport, InstallError> {
+    let PlannedImport { staged, plan } = planned;
+    let prepared = prepare_mod(&staged.path, plan, limits)?;
+    Ok(PreparedImport {
+        staged: staged,
+        prepared: prepared,
+    })
+}
+
+/// Copies prepared upload into mod storage, installs it to every server path and adds it to mod
+/// database. Installed mod with the same car and track folders is replaced by new version of it,
+/// files which other installed mods already provide are handled according to resolution.
+pub fn install_import(
+    config: &mut ConfigObject,
+    prepared: PreparedImport,
+    resolution: ConflictResolution,
+) -> Result<ImportResult, InstallError> {
+    let PreparedImport { staged, prepared } = prepared;
+    let ImportTarget {
+        previous_version,
+        conflicts,
+        output_path,
+    } = find_import_target(config, &staged)?;
+    if !conflicts.is_empty() && resolution == ConflictResolution::Abort {
+        return Ok(ImportResult::Conflicts(conflicts));
+    }
+
+    // old files have to be gone before new version is installed to the same paths
+    let previous_version = match previous_version {
+        Some(previous) => Some(
+            config
+                .retire_mod(&previous.checksum_md5)
+                .map_err(InstallError::Storage)?,
+        ),
+        None => None,
+    };
+    let result = install_to_storage(
+        config,
+        &prepared,
+        &staged.path,
+        &output_path,
+        previous_version.clone(),
+    );
+    let (installed, server_paths) = match result {
+        Ok(result) => result,
+        Err(error) => {
+            if let Some(previous_version) = previous_version {
+                reinstate_previous_version(config, previous_version);
+            }
+            return Err(error);
+        }
+    };
+
+    // replaced mods are only deleted once the upload is installed, files it provides too are kept
+    let mut replaced = vec![];
+    if resolution == ConflictResolution::Replace {
+        for conflict in conflicts.iter() {
+            let acmod = config
+                .get_mod_list()
+                .into_iter()
+                .find(|acmod| acmod.checksum_md5 == conflict.checksum_md5);
+            if let Some(acmod) = acmod {
+                match config.delete_mod(&acmod.checksum_md5) {
+                    Ok(_) => replaced.push(acmod),
+                    Err(error) => println!("Error deleting replaced {}: {}", acmod.filename, error),
+                }
+            }
+        }
+    }
+    Ok(ImportResult::Imported {
+        installed: installed,
+        replaced: replaced,
+        server_paths: server_paths,
+        warnings: staged.warnings,
+    })
+}
+
+/// Plans, prepares and installs archive in one go, for callers which hold config anyway.
+pub fn import_archive(
+    config: &mut ConfigObject,
+    source_path: &Path,
+    file_name: &str,
+    resolution: ConflictResolution,
+) -> Result<ImportResult, InstallError> {
+    let known_checksums: Vec<String> = config
+        .get_mod_list()
+        .into_iter()
+        .map(|acmod| acmod.checksum_md5)
+        .collect();
+    let planned = plan_import(
+        source_path,
+        file_name,
+        &known_checksums,
+        &config.config.archive_limits,
+    )?;
+
+    // extracted only after conflict check unless archive has archives inside, everything before
+    // needs just the archive listing
+    let conflicts = check_import(config, &planned)?;
+    if !conflicts.is_empty() && resolution == ConflictResolution::Abort {
+        return Ok(ImportResult::Conflicts(conflicts));
+    }
+    let prepared = prepare_import(planned, &config.config.archive_limits)?;
+    install_import(config, prepared, resolution)
+}
+
+/// Installs older version of the mod again, current version is moved to history. Restored archive
+/// becomes the newest version.
+pub fn restore_version(
+    config: &mut ConfigObject,
+    checksum_md5: &String,
+    version_checksum_md5: &String,
+) -> Result<AssettoMod, InstallError> {
+    let current = config
+        .get_mod_list()
+        .into_iter()
+        .find(|acmod| acmod.checksum_md5 == *checksum_md5);
+    let current = match current {
+        Some(current) => current,
+        None => return Err(InstallError::Rejected("Mod not found".to_string())),
+    };
+    let version = current
+        .history
+        .iter()
+        .find(|version| version.checksum_md5 == *version_checksum_md5)
+        .cloned();
+    let version = match version {
+        Some(version) => version,
+        None => return Err(InstallError::Rejected("Version not found".to_string())),
+    };
+
+    let history_path = config.mod_version_archive_path(&version);
+    let plan = plan_install(&history_path, &config.config.archive_limits)?;
+    let output_path = Path::new(&config.config.mod_storage_location).join(&version.filename);
+    if output_path.exists() && current.filename != version.filename {
+        return Err(InstallError::Rejected(format!(
+            "{} already exists in mod storage",
+            version.filename
+        )));
+    }
+
+    let conflicting_mods: BTreeSet<String> = config
+        .find_file_conflicts(&plan.target_files())
+        .into_iter()
+        .filter(|conflict| conflict.checksum_md5 != current.checksum_md5)
+        .map(|conflict| conflict.filename)
+        .collect();
+    if !conflicting_mods.is_empty() {
+        return Err(InstallError::Rejected(format!(
+            "Restoring would overwrite files of {}",
+            conflicting_mods
+                .into_iter()
+                .collect::<Vec<String>>()
+                .join(", ")
+        )));
+    }
+
+    let prepared = prepare_mod(&history_path, plan, &config.config.archive_limits)?;
+    let retired = config
+        .retire_mod(&current.checksum_md5)
+        .map_err(InstallError::Storage)?;
+    let mut previous_version = retired.clone();
+    previous_version
+        .history
+        .retain(|older| older.checksum_md5 != version.checksum_md5);
+    let result = install_to_storage(
+        config,
+        &prepared,
+        &history_path,
+        &output_path,
+        Some(previous_version),
+    );
+    match result {
+        Ok((installed, _)) => {
+            let _ = std::fs::remove_dir_all(history_path.parent().unwrap());
+            Ok(installed)
+        }
+        Err(error) => {
+            reinstate_previous_version(config, retired);
+            Err(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tests::test_archive::{with_mock_archives, write_archive};
+    use crate::tests::test_server::test_config;
+
+    const CAR: &[&str] = &[
+        "content/cars/car/data.acd",
+        "content/cars/car/data/car.ini",
+        "content/cars/car/car.kn5",
+    ];
+    const CAR_V2: &[&str] = &[
+        "content/cars/car/data.acd",
+        "content/cars/car/data/car.ini",
+        "content/cars/car/data/engine.ini",
+    ];
+    // other car folders, but the same data.acd of car
+    const PACK: &[&str] = &["content/cars/car/data.acd", "content/cars/other/data.acd"];
+
+    fn import(
+        config: &mut ConfigObject,
+        temp_dir: &TempDir,
+        file_name: &str,
+        resolution: ConflictResolution,
+    ) -> Result<ImportResult, InstallError> {
+        let archive_path = write_archive(temp_dir.path(), file_name);
+        import_archive(config, &archive_path, file_name, resolution)
+    }
+
+    fn server_file(config: &ConfigObject, relative_path: &str) -> PathBuf {
+        Path::new(&config.get_server_paths()[0]).join(relative_path)
+    }
+
+    fn imported(result: Result<ImportResult, InstallError>) -> AssettoMod {
+        match result {
+            Ok(ImportResult::Imported { installed, .. }) => installed,
+            Ok(ImportResult::Conflicts(conflicts)) => {
+                panic!("Unexpected conflicts {:?}", conflicts)
+            }
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    fn entry(path: &str, is_file: bool) -> FsEntry {
+        FsEntry {
+            path: path.to_string(),
+            is_file: is_file,
+        }
+    }
+
+    #[test]
+    fn test_output_path() {
+        let task = InstallTask {
+            source_path: "pack/cars/abc_gt3".to_string(),
+            target_path: "content/cars".to_string(),
+        };
+        assert_eq!(
+            Some(PathBuf::from("content/cars/abc_gt3/data.acd")),
+            output_path(&task, "pack/cars/abc_gt3/data.acd")
+        );
+        assert_eq!(None, output_path(&task, "pack/cars/abc_gt3_other/data.acd"));
+
+        let task = InstallTask {
+            source_path: "content".to_string(),
+            target_path: "".to_string(),
+        };
+        assert_eq!(
+            Some(PathBuf::from("content/tracks/ring")),
+            output_path(&task, "content/tracks/ring")
+        );
+    }
+
+    #[test]
+    fn test_install_plan_without_extraction() {
+        let plan = InstallPlan {
+            tasks: vec![],
+            contents: vec![],
+            content_sources: BTreeMap::new(),
+            warnings: vec![],
+            output_entries: vec![
+                entry("content/tracks/ring", false),
+                entry("content/tracks/ring/data", false),
+                entry("content/tracks/ring/data/surfaces.ini", true),
+                entry("content/tracks/ring/ring.kn5", true),
+                entry("content/cars/car", false),
+                entry("content/cars/car/data.acd", true),
+                entry("content/cars/car/ui/ui_car.json", true),
+            ],
+            unpack_directory: None,
+        };
+
+        assert_eq!(vec!["cars/car", "tracks/ring"], plan.content_ids());
+        assert_eq!(
+            vec![
+                "content/cars/car/data.acd",
+                "content/tracks/ring/data/surfaces.ini"
+            ],
+            plan.target_files()
+        );
+    }
+
+    #[test]
+    fn test_install_plan_of_nested_archives() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let archive_path = write_archive(temp_dir.path(), "pack.zip");
+        let archives: &[(&str, &[&str])] = &[
+            ("pack.zip", &["pack/content.zip", "pack/readme.txt"]),
+            (
+                "content.zip",
+                &[
+                    "cars/outer/data.acd",
+                    "cars/outer/outer.kn5",
+                    "cars/inner.zip",
+                ],
+            ),
+            ("inner.zip", &["data.acd", "inner.kn5"]),
+        ];
+
+        let plan = with_mock_archives(archives, || {
+            plan_install(&archive_path, &ArchiveLimits::default()).unwrap()
+        });
+        assert_eq!(vec!["cars/inner", "cars/outer"], plan.content_ids());
+        // inner car is unpacked from both archives, innermost one is its source
+        assert_eq!(
+            vec![
+                (
+                    "cars/inner".to_string(),
+                    "pack/content/cars/inner.zip".to_string()
+                ),
+                ("cars/outer".to_string(), "pack/content.zip".to_string()),
+            ],
+            plan.content_sources.into_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_import_rejects_known_archive_before_unpacking() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let mut config = test_config(&temp_dir, &[]);
+        let archive_path = write_archive(temp_dir.path(), "car.zip");
+
+        with_mock_archives(&[("car.zip", CAR)], || {
+            let result = import_archive(
+                &mut config,
+                &archive_path,
+                "car.zip",
+                ConflictResolution::Abort,
+            );
+            imported(result);
+
+            // unpacker doesn't know the archive under other name, so it would fail differently
+            let result = import_archive(
+                &mut config,
+                &archive_path,
+                "copy.zip",
+                ConflictResolution::Abort,
+            );
+            match result {
+                Err(InstallError::Rejected(message)) => assert_eq!("Mod already exists", message),
+                _ => panic!("Archive wasn't rejected"),
+            }
+        });
+        let storage_path = Path::new(&config.config.mod_storage_location);
+        assert!(!storage_path.join("copy.zip").exists());
+        assert_eq!(1, config.get_mod_list().len());
+    }
+
+    #[test]
+    fn test_uninstall_keeps_files_which_existed_before_install() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let mut config = test_config(&temp_dir, &[]);
+        let archive_path = write_archive(temp_dir.path(), "car.zip");
+        let server_path = config.get_server_paths()[0].clone();
+        let stock_file = Path::new(&server_path).join("content/cars/car/data.acd");
+        std::fs::create_dir_all(stock_file.parent().unwrap()).unwrap();
+        std::fs::write(&stock_file, "stock").unwrap();
+
+        let installed = with_mock_archives(&[("car.zip", CAR)], || {
+            imported(import_archive(
+                &mut config,
+                &archive_path,
+                "car.zip",
+                ConflictResolution::Abort,
+            ))
+        });
+        assert_eq!(
+            "content/cars/car/data.acd",
+            std::fs::read_to_string(&stock_file).unwrap()
+        );
+        assert!(!installed
+            .install_manifest
+            .contains_file("content/cars/car/data.acd"));
+
+        config.delete_mod(&installed.checksum_md5).unwrap();
+        assert!(stock_file.exists());
+    }
+
+    #[test]
+    fn test_deleted_mod_files_are_kept_until_database_is_saved() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let mut config = test_config(&temp_dir, &[]);
+        let installed = with_mock_archives(&[("car.zip", CAR)], || {
+            imported(import(
+                &mut config,
+                &temp_dir,
+                "car.zip",
+                ConflictResolution::Abort,
+            ))
+        });
+        let storage_path = Path::new(&config.config.mod_storage_location).join("car.zip");
+        let database_path = config.config.mod_database_path.clone();
+
+        // database can't be written into missing directory
+        config.config.mod_database_path = temp_dir
+            .path()
+            .join("missing/mod_database.json")
+            .to_string_lossy()
+            .to_string();
+        assert!(config.delete_mod(&installed.checksum_md5).is_err());
+        assert_eq!(1, config.get_mod_list().len());
+        assert!(storage_path.exists());
+        assert!(server_file(&config, "content/cars/car/data.acd").exists());
+
+        // archive which is gone already doesn't keep mod in database
+        config.config.mod_database_path = database_path;
+        std::fs::remove_file(&storage_path).unwrap();
+        config.delete_mod(&installed.checksum_md5).unwrap();
+        assert!(config.get_mod_list().is_empty());
+        assert!(!server_file(&config, "content/cars/car/data.acd").exists());
+    }
+
+    #[test]
+    fn test_retired_mod_is_kept_when_database_save_fails() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let mut config = test_config(&temp_dir, &[]);
+        let installed = with_mock_archives(&[("car.zip", CAR)], || {
+            imported(import(
+                &mut config,
+                &temp_dir,
+                "car.zip",
+                ConflictResolution::Abort,
+            ))
+        });
+
+        config.config.mod_database_path = temp_dir
+            .path()
+            .join("missing/mod_database.json")
+            .to_string_lossy()
+            .to_string();
+        assert!(config.retire_mod(&installed.checksum_md5).is_err());
+        assert_eq!(1, config.get_mod_list().len());
+        assert!(Path::new(&config.config.mod_storage_location)
+            .join("car.zip")
+            .exists());
+        assert!(!config
+            .mod_version_archive_path(&installed.as_version())
+            .exists());
+        assert!(server_file(&config, "content/cars/car/data.acd").exists());
+    }
+
+    #[test]
+    fn test_import_conflicts_are_resolved_as_chosen() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let mut config = test_config(&temp_dir, &[]);
+        let archives: &[(&str, &[&str])] = &[("car.zip", CAR), ("pack.zip", PACK)];
+
+        with_mock_archives(archives, || {
+            let car = imported(import(
+                &mut config,
+                &temp_dir,
+                "car.zip",
+                ConflictResolution::Abort,
+            ));
+
+            match import(
+                &mut config,
+                &temp_dir,
+                "pack.zip",
+                ConflictResolution::Abort,
+            ) {
+                Ok(ImportResult::Conflicts(conflicts)) => {
+                    assert_eq!(1, conflicts.len());
+                    assert_eq!("content/cars/car/data.acd", conflicts[0].path);
+                    assert_eq!(car.checksum_md5, conflicts[0].checksum_md5);
+                }
+                _ => panic!("Conflict wasn't reported"),
+            }
+            assert_eq!(1, config.get_mod_list().len());
+            assert!(!server_file(&config, "content/cars/other/data.acd").exists());
+
+            // both mods provide the file then, it stays until the last of them is deleted
+            let pack = imported(import(
+                &mut config,
+                &temp_dir,
+                "pack.zip",
+                ConflictResolution::KeepBoth,
+            ));
+            assert_eq!(2, config.get_mod_list().len());
+            config.delete_mod(&pack.checksum_md5).unwrap();
+            assert!(server_file(&config, "content/cars/car/data.acd").exists());
+            assert!(!server_file(&config, "content/cars/other/data.acd").exists());
+
+            let result = import(
+                &mut config,
+                &temp_dir,
+                "pack.zip",
+                ConflictResolution::Replace,
+            );
+            let replaced = match result {
+                Ok(ImportResult::Imported { replaced, .. }) => replaced,
+                _ => panic!("Pack wasn't installed"),
+            };
+            assert_eq!(
+                vec![car.checksum_md5],
+                replaced
+                    .into_iter()
+                    .map(|acmod| acmod.checksum_md5)
+                    .collect::<Vec<String>>()
+            );
+            let mods = config.get_mod_list();
+            assert_eq!(1, mods.len());
+            assert_eq!("pack.zip", mods[0].filename);
+            assert_eq!(
+                "content/cars/car/data.acd",
+                std::fs::read_to_string(server_file(&config, "content/cars/car/data.acd")).unwrap()
+            );
+            assert!(!server_file(&config, "content/cars/car/data/car.ini").exists());
+        });
+    }
+
+    #[test]
+    fn test_install_checks_mods_installed_after_planning() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let mut config = test_config(&temp_dir, &[]);
+        let archives: &[(&str, &[&str])] = &[("car.zip", CAR), ("pack.zip", PACK)];
+
+        with_mock_archives(archives, || {
+            let pack_path = write_archive(temp_dir.path(), "pack.zip");
+            let limits = config.config.archive_limits.clone();
+            let planned = plan_import(&pack_path, "pack.zip", &[], &limits).unwrap();
+            assert!(check_import(&config, &planned).unwrap().is_empty());
+            let prepared = prepare_import(planned, &limits).unwrap();
+
+            imported(import(
+                &mut config,
+                &temp_dir,
+                "car.zip",
+                ConflictResolution::Abort,
+            ));
+            match install_import(&mut config, prepared, ConflictResolution::Abort) {
+                Ok(ImportResult::Conflicts(conflicts)) => {
+                    assert_eq!(1, conflicts.len());
+                    assert_eq!("content/cars/car/data.acd", conflicts[0].path);
+                }
+                _ => panic!("Conflict wasn't reported"),
+            }
+            assert_eq!(1, config.get_mod_list().len());
+            assert!(!server_file(&config, "content/cars/other/data.acd").exists());
+        });
+    }
+
+    #[test]
+    fn test_replaced_mods_are_kept_when_install_fails() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let mut config = test_config(&temp_dir, &[]);
+
+        with_mock_archives(&[("car.zip", CAR), ("pack.zip", PACK)], || {
+            imported(import(
+                &mut config,
+                &temp_dir,
+                "car.zip",
+                ConflictResolution::Abort,
+            ));
+            // server path can't be written, so nothing gets installed
+            let server_path = PathBuf::from(&config.get_server_paths()[0]);
+            std::fs::remove_dir_all(&server_path).unwrap();
+            std::fs::write(&server_path, "not a directory").unwrap();
+
+            let result = import(
+                &mut config,
+                &temp_dir,
+                "pack.zip",
+                ConflictResolution::Replace,
+            );
+            assert!(matches!(result, Err(InstallError::ServerPaths(_))));
+            let mods = config.get_mod_list();
+            assert_eq!(1, mods.len());
+            assert_eq!("car.zip", mods[0].filename);
+        });
+    }
+
+    fn history(acmod: &AssettoMod) -> Vec<String> {
+        acmod
+            .history
+            .iter()
+            .map(|version| version.checksum_md5.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_same_content_ids_create_version_which_can_be_restored() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let mut config = test_config(&temp_dir, &[]);
+
+        with_mock_archives(&[("car.zip", CAR), ("car_v2.zip", CAR_V2)], || {
+            let first = imported(import(
+                &mut config,
+                &temp_dir,
+                "car.zip",
+                ConflictResolution::Abort,
+            ));
+            // files of previous version aren't conflicts
+            let second = imported(import(
+                &mut config,
+                &temp_dir,
+                "car_v2.zip",
+                ConflictResolution::Abort,
+            ));
+            assert_eq!(2, second.version);
+            assert_eq!(vec![first.checksum_md5.clone()], history(&second));
+            assert_eq!(1, config.get_mod_list().len());
+            assert!(config
+                .mod_version_archive_path(&first.as_version())
+                .exists());
+            assert!(server_file(&config, "content/cars/car/data/engine.ini").exists());
+
+            let restored =
+                restore_version(&mut config, &second.checksum_md5, &first.checksum_md5).unwrap();
+            assert_eq!(first.checksum_md5, restored.checksum_md5);
+            assert_eq!(3, restored.version);
+            assert_eq!(vec![second.checksum_md5.clone()], history(&restored));
+            assert_eq!(1, config.get_mod_list().len());
+            assert!(!config
+                .mod_version_archive_path(&first.as_version())
+                .exists());
+            assert!(config
+                .mod_version_archive_path(&second.as_version())
+                .exists());
+            assert!(!server_file(&config, "content/cars/car/data/engine.ini").exists());
+            assert!(server_file(&config, "content/cars/car/data/car.ini").exists());
+        });
+    }
+}
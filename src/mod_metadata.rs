@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// ui_car.json keeps specs in "specs" object, ui_track.json has these at top level
+const TRACK_SPEC_KEYS: [&str; 3] = ["length", "width", "pitboxes"];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageKind {
+    Badge,
+    Outline,
+    Preview,
+}
+
+/// Image and thumbnail names in image cache, skin is only set for car skin previews.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ContentImage {
+    pub kind: ImageKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skin: Option<String>,
+    pub image: String,
+    pub thumbnail: String,
+}
+
+/// Contents of ui_car.json or ui_track.json, id is the folder name under content/cars or content/tracks.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ContentMetadata {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brand: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub specs: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ContentImage>,
+}
+
+#[derive(Debug, Default)]
+pub struct ModMetadata {
+    pub cars: Vec<ContentMetadata>,
+    pub tracks: Vec<ContentMetadata>,
+    /// Every car and track folder, also those without ui file, as cars/<id> or tracks/<id>.
+    pub content_ids: Vec<String>,
+    /// Inner archive each car and track folder was unpacked from, by folder ID.
+    pub content_sources: BTreeMap<String, String>,
+}
+
+// ui files are hand written and Kunos' parser is forgiving, so they often have BOM,
+// raw newlines and tabs inside strings or trailing commas which serde_json rejects
+fn sanitize_json(content: &str) -> String {
+    let content = content.trim_start_matches('\u{feff}');
+    let mut output = String::with_capacity(content.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut pending_comma = false;
+
+    for character in content.chars() {
+        if in_string {
+            match character {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            output.push(if character.is_control() {
+                ' '
+            } else {
+                character
+            });
+            continue;
+        }
+
+        if character.is_whitespace() {
+            output.push(character);
+            continue;
+        }
+        if pending_comma {
+            if character != '}' && character != ']' {
+                output.push(',');
+            }
+            pending_comma = false;
+        }
+        match character {
+            ',' => pending_comma = true,
+            '"' => {
+                in_string = true;
+                output.push(character);
+            }
+            _ => output.push(character),
+        }
+    }
+    output
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    let text = match value {
+        Value::String(text) => text.trim().to_string(),
+        Value::Number(number) => number.to_string(),
+        Value::Bool(flag) => flag.to_string(),
+        _ => return None,
+    };
+    if text.is_empty() {
+        return None;
+    }
+    Some(text)
+}
+
+fn parse_ui_file(path: &Path) -> Result<Value, String> {
+    let bytes = std::fs::read(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+    let content = String::from_utf8_lossy(&bytes);
+    serde_json::from_str(&sanitize_json(&content))
+        .map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+fn content_metadata(id: &str, layout: Option<String>, ui: &Value) -> ContentMetadata {
+    let field = |key: &str| ui.get(key).and_then(value_to_string);
+
+    let mut specs = BTreeMap::new();
+    if let Some(Value::Object(car_specs)) = ui.get("specs") {
+        for (key, value) in car_specs {
+            if let Some(value) = value_to_string(value) {
+                specs.insert(key.clone(), value);
+            }
+        }
+    }
+    for key in TRACK_SPEC_KEYS.iter() {
+        if let Some(value) = field(key) {
+            specs.insert(key.to_string(), value);
+        }
+    }
+
+    let tags = match ui.get("tags") {
+        Some(Value::Array(tags)) => tags.iter().filter_map(value_to_string).collect(),
+        _ => vec![],
+    };
+
+    ContentMetadata {
+        id: id.to_string(),
+        layout: layout,
+        name: field("name"),
+        brand: field("brand"),
+        class: field("class"),
+        country: field("country"),
+        author: field("author"),
+        version: field("version"),
+        specs: specs,
+        tags: tags,
+        images: vec![],
+    }
+}
+
+fn sorted_directories(path: &Path) -> Vec<String> {
+    let mut directories: Vec<String> = match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect(),
+        Err(_) => vec![],
+    };
+    directories.sort();
+    directories
+}
+
+fn push_metadata(
+    list: &mut Vec<ContentMetadata>,
+    ui_path: &Path,
+    id: &str,
+    layout: Option<String>,
+) {
+    match parse_ui_file(ui_path) {
+        Ok(ui) => list.push(content_metadata(id, layout, &ui)),
+        Err(error) => println!("Skipping metadata of {}: {}", id, error),
+    }
+}
+
+/// Reads ui_car.json and ui_track.json files of content directory prepared for install,
+/// tracks with layouts have one entry per ui/<layout>/ui_track.json.
+pub fn read_metadata(install_directory: &Path) -> ModMetadata {
+    let mut metadata = ModMetadata::default();
+
+    let cars_path = install_directory.join("content/cars");
+    for car in sorted_directories(&cars_path) {
+        metadata.content_ids.push(format!("cars/{}", car));
+        let ui_path = cars_path.join(&car).join("ui/ui_car.json");
+        if ui_path.is_file() {
+            push_metadata(&mut metadata.cars, &ui_path, &car, None);
+        }
+    }
+
+    let tracks_path = install_directory.join("content/tracks");
+    for track in sorted_directories(&tracks_path) {
+        metadata.content_ids.push(format!("tracks/{}", track));
+        let ui_directory = tracks_path.join(&track).join("ui");
+        let ui_path = ui_directory.join("ui_track.json");
+        if ui_path.is_file() {
+            push_metadata(&mut metadata.tracks, &ui_path, &track, None);
+        }
+        for layout in sorted_directories(&ui_directory) {
+            let ui_path = ui_directory.join(&layout).join("ui_track.json");
+            if ui_path.is_file() {
+                push_metadata(&mut metadata.tracks, &ui_path, &track, Some(layout));
+            }
+        }
+    }
+
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    fn write_file(path: &Path, content: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_sanitize_json() {
+        let content =
+            "\u{feff}{\"name\": \"Line\none\",\n\"tags\": [\"a\", \"b\",],\n\"text\": \"a, }\",\n}";
+        let value: Value = serde_json::from_str(&sanitize_json(content)).unwrap();
+
+        assert_eq!("Line one", value["name"]);
+        assert_eq!(2, value["tags"].as_array().unwrap().len());
+        assert_eq!("a, }", value["text"]);
+    }
+
+    #[test]
+    fn test_read_metadata() {
+        let temp_dir = TempDir::new("acsync_server_test").unwrap();
+        let path = temp_dir.path();
+        write_file(
+            &path.join("content/cars/abc_gt3/ui/ui_car.json"),
+            r##"{
+                "name": "ABC GT3",
+                "brand": "ABC",
+                "class": "race",
+                "country": "Germany",
+                "author": "Somebody",
+                "version": 1.2,
+                "tags": ["#GT3", "rwd",],
+                "specs": {"bhp": "500bhp", "weight": "1250kg"},
+            }"##,
+        );
+        write_file(
+            &path.join("content/tracks/ring/ui/gp/ui_track.json"),
+            r#"{"name": "Ring GP", "country": "Germany", "length": "5148m", "pitboxes": 30}"#,
+        );
+        write_file(
+            &path.join("content/tracks/ring/ui/short/ui_track.json"),
+            r#"{"name": "Ring Short", "tags": []}"#,
+        );
+        write_file(&path.join("content/tracks/broken/ui/ui_track.json"), "{");
+
+        let metadata = read_metadata(path);
+        assert_eq!(
+            vec!["cars/abc_gt3", "tracks/broken", "tracks/ring"],
+            metadata.content_ids
+        );
+
+        assert_eq!(1, metadata.cars.len());
+        let car = &metadata.cars[0];
+        assert_eq!("abc_gt3", car.id);
+        assert_eq!(Some("ABC".to_string()), car.brand);
+        assert_eq!(Some("1.2".to_string()), car.version);
+        assert_eq!(vec!["#GT3", "rwd"], car.tags);
+        assert_eq!(Some(&"500bhp".to_string()), car.specs.get("bhp"));
+
+        assert_eq!(2, metadata.tracks.len());
+        let track = &metadata.tracks[0];
+        assert_eq!("ring", track.id);
+        assert_eq!(Some("gp".to_string()), track.layout);
+        assert_eq!(Some(&"30".to_string()), track.specs.get("pitboxes"));
+        assert_eq!(Some("short".to_string()), metadata.tracks[1].layout);
+    }
+}
@@ -1,10 +1,19 @@
+use std::sync::Mutex;
+
 use mockall::automock;
 
+lazy_static::lazy_static! {
+    /// Expectations of mocked functions are global, tests setting them have to hold this.
+    pub static ref MOCK_STABILITY_MUTEX: Mutex<()> = Mutex::new(());
+}
+
 #[allow(dead_code)]
 #[automock]
 pub mod unpacker {
     use std::path::Path;
 
+    use crate::archive_unpacker::ArchiveEntry;
+
     pub fn rar_unpack(
         _archive_path: &Path,
         _destination_path: &Path,
@@ -12,10 +21,27 @@ pub mod unpacker {
         unimplemented!()
     }
 
+    pub fn rar_list_entries(_archive_path: &Path) -> compress_tools::Result<Vec<ArchiveEntry>> {
+        unimplemented!()
+    }
+
     pub fn compress_tools_unpack(
         _archive_path: &Path,
         _destination_path: &Path,
     ) -> compress_tools::Result<()> {
         unimplemented!()
     }
+
+    pub fn compress_tools_list_archive(
+        _archive_path: &Path,
+    ) -> compress_tools::Result<Vec<String>> {
+        unimplemented!()
+    }
+
+    pub fn compress_tools_list_entries(
+        _archive_path: &Path,
+        _size_limit: u64,
+    ) -> compress_tools::Result<Vec<ArchiveEntry>> {
+        unimplemented!()
+    }
 }
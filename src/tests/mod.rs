@@ -1 +1,3 @@
 pub mod mocks;
+pub mod test_archive;
+pub mod test_server;
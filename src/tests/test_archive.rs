@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use crate::archive_unpacker::{archive_extension, ArchiveEntry};
+use crate::tests::mocks::unpack_archive_mock::mock_unpacker as unpacker;
+use crate::tests::mocks::unpack_archive_mock::MOCK_STABILITY_MUTEX;
+
+const ZIP_HEADER: &[u8] = b"PK\x03\x04\x14\x00";
+
+/// Zip header followed by file name, archives of different names differ in checksum.
+pub fn archive_content(file_name: &str) -> String {
+    format!("{}{}", String::from_utf8_lossy(ZIP_HEADER), file_name)
+}
+
+pub fn write_archive(directory: &Path, file_name: &str) -> PathBuf {
+    let path = directory.join(file_name);
+    std::fs::write(&path, archive_content(file_name)).unwrap();
+    path
+}
+
+// archive is found by file name, staged copies and history keep it
+fn archive_files(
+    archives: &[(String, Vec<String>)],
+    archive_path: &Path,
+) -> compress_tools::Result<Vec<String>> {
+    let file_name = archive_path.file_name().unwrap().to_string_lossy();
+    archives
+        .iter()
+        .find(|(name, _)| *name == file_name)
+        .map(|(_, files)| files.clone())
+        .ok_or_else(|| compress_tools::Error::from(format!("Unknown archive {}", file_name)))
+}
+
+/// Runs test with unpacker seeing archives of given names as zip archives of given files, every
+/// file unpacked contains its own path. Unpacked archives are archives again, found by file name.
+pub fn with_mock_archives<T>(archives: &[(&str, &[&str])], test: impl FnOnce() -> T) -> T {
+    let _lock = MOCK_STABILITY_MUTEX.lock().unwrap();
+    let archives: Vec<(String, Vec<String>)> = archives
+        .iter()
+        .map(|(name, files)| {
+            let files = files.iter().map(|file| file.to_string()).collect();
+            (name.to_string(), files)
+        })
+        .collect();
+
+    let list_context = unpacker::compress_tools_list_archive_context();
+    let listed = archives.clone();
+    list_context
+        .expect()
+        .returning(move |archive_path| archive_files(&listed, archive_path));
+    let entries_context = unpacker::compress_tools_list_entries_context();
+    let listed = archives.clone();
+    entries_context.expect().returning(move |archive_path, _| {
+        let files = archive_files(&listed, archive_path)?;
+        Ok(files
+            .into_iter()
+            .map(|file| ArchiveEntry {
+                size: file.len() as u64,
+                path: file,
+                is_directory: false,
+                is_symlink: false,
+            })
+            .collect())
+    });
+    let unpack_context = unpacker::compress_tools_unpack_context();
+    unpack_context
+        .expect()
+        .returning(move |archive_path, destination_path| {
+            for file in archive_files(&archives, archive_path)? {
+                let path = destination_path.join(&file);
+                let content = match archive_extension(&file) {
+                    Some(_) => archive_content(&path.file_name().unwrap().to_string_lossy()),
+                    None => file,
+                };
+                std::fs::create_dir_all(path.parent().unwrap())
+                    .and_then(|_| std::fs::write(&path, content))
+                    .map_err(|error| compress_tools::Error::from(error.to_string()))?;
+            }
+            Ok(())
+        });
+
+    test()
+}
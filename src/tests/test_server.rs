@@ -0,0 +1,82 @@
+use std::fs;
+use std::sync::RwLock;
+
+use rocket::config::{Config, Environment};
+use rocket::http::{ContentType, Status};
+use rocket::local::Client;
+use rocket_contrib::templates::Template;
+use tempdir::TempDir;
+
+use crate::config::{ConfigObject, ConfigTrait, Role};
+use crate::endpoints;
+
+pub const PASSWORD: &str = "hunter2";
+
+/// Config, mod database, mod storage and single server path in temporary directory.
+pub fn test_config(temp_dir: &TempDir, users: &[(&str, Role)]) -> ConfigObject {
+    let mod_storage_path = temp_dir.path().join("mods");
+    fs::create_dir(&mod_storage_path).unwrap();
+    let server_path = temp_dir.path().join("server");
+    fs::create_dir(&server_path).unwrap();
+
+    let config_path = temp_dir.path().join("config.json");
+    let config_json = serde_json::json!({
+        "audit_log_path": temp_dir.path().join("audit_log.jsonl"),
+        "bind_address": "127.0.0.1",
+        "mod_database_path": temp_dir.path().join("mod_database.json"),
+        "mod_storage_location": mod_storage_path,
+        "password_hashing": {
+            "memory_cost_kib": 64,
+            "iterations": 1,
+            "parallelism": 1
+        },
+        "port": 8080,
+        "secret_key": null,
+        "server_paths": [server_path],
+        "users": []
+    });
+    fs::write(&config_path, config_json.to_string()).unwrap();
+
+    let mut config = ConfigObject::new(config_path.to_str().unwrap()).unwrap();
+    for (login, role) in users.iter() {
+        config
+            .add_user(&login.to_string(), &PASSWORD.to_string(), *role)
+            .unwrap();
+    }
+    config
+}
+
+/// Server with config, mod database and mod storage in temporary directory,
+/// directory has to outlive the client.
+pub fn test_client(users: &[(&str, Role)]) -> (Client, TempDir) {
+    let temp_dir = TempDir::new("acsync_server_test").unwrap();
+    let config = test_config(&temp_dir, users);
+
+    let rocket_config = Config::build(Environment::Development).finalize().unwrap();
+    let rocket = rocket::custom(rocket_config)
+        .manage(RwLock::new(config))
+        .mount("/", endpoints::get_routes())
+        .register(endpoints::get_catchers())
+        .attach(Template::fairing());
+    (Client::new(rocket).unwrap(), temp_dir)
+}
+
+pub fn login(client: &Client, login: &str) {
+    let response = client
+        .post("/login")
+        .header(ContentType::Form)
+        .body(format!("login={}&password={}", login, PASSWORD))
+        .dispatch();
+    assert_eq!(Status::SeeOther, response.status());
+    assert_eq!(Some("/"), response.headers().get_one("Location"));
+}
+
+pub fn config(client: &Client) -> &RwLock<ConfigObject> {
+    client.rocket().state::<RwLock<ConfigObject>>().unwrap()
+}
+
+/// CSRF token of the newest session of login.
+pub fn csrf_token(client: &Client, login: &str) -> String {
+    let config = config(client).read().unwrap();
+    config.sessions.list_for_user(login)[0].csrf_token.clone()
+}